@@ -0,0 +1,121 @@
+//! Frame assembly and hand-off cost: the baseline path, which copied every frame
+//! out of the assembler, against pooled frames shared by the camera and the stream
+//! meter. Both include the copy into the Media Foundation buffer that the camera
+//! still makes. Run with `cargo bench --bench frame_copies`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use opticlink_lib::depacketizer::{Depacketizer, H264Depacketizer};
+
+/// Counts allocations and allocated bytes on the benchmark thread
+struct CountingAlloc;
+
+thread_local! {
+    static ALLOCATED: Cell<(u64, u64)> = const { Cell::new((0, 0)) };
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATED.try_with(|a| a.set((a.get().0 + 1, a.get().1 + layout.size() as u64)));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let _ = ALLOCATED.try_with(|a| a.set((a.get().0 + 1, a.get().1 + new_size as u64)));
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+type Packet = (Vec<u8>, u32, bool);
+
+/// FU-A packets for 10 s of 30 fps H.264: a 150 KB IDR every 2 s, 25 KB P-frames
+fn packets() -> Vec<Packet> {
+    let mut packets = Vec::new();
+    for i in 0..300u32 {
+        let (header, size) = if i % 60 == 0 { (0x65, 150_000) } else { (0x41, 25_000) };
+        let nal: Vec<u8> = (0..size).map(|b| (b % 251) as u8 | 0x01).collect();
+        let chunks: Vec<&[u8]> = nal.chunks(1200).collect();
+        for (n, chunk) in chunks.iter().enumerate() {
+            let start = if n == 0 { 0x80 } else { 0 };
+            let end = if n == chunks.len() - 1 { 0x40 } else { 0 };
+            let mut p = vec![(header & 0x60) | 28, start | end | (header & 0x1F)];
+            p.extend_from_slice(chunk);
+            packets.push((p, i * 3000, end != 0));
+        }
+    }
+    packets
+}
+
+/// The copy `media_stream::create_sample` makes into an `IMFMediaBuffer`
+fn mf_copy(data: &[u8]) -> Vec<u8> {
+    data.to_vec()
+}
+
+/// Depacketize every packet and deliver each frame. `baseline` emulates the old
+/// pipeline: the assembler cloned its buffer into each frame and the only consumer,
+/// the virtual camera, copied it into an MF buffer. Otherwise the camera and the
+/// stream meter share the frame and the camera makes the MF copy. Returns the frame
+/// count and copied bytes.
+fn run(d: &mut H264Depacketizer, packets: &[Packet], baseline: bool) -> (u64, u64) {
+    let (mut frames, mut copied) = (0, 0);
+    for (payload, timestamp, marker) in packets {
+        for frame in d.push(payload, *timestamp, *marker) {
+            frames += 1;
+            let len = frame.data.len() as u64;
+            if baseline {
+                let assembled = frame.data.to_vec();
+                black_box(mf_copy(&assembled));
+                copied += 2 * len;
+            } else {
+                black_box(frame.data.clone());
+                black_box(mf_copy(&frame.data));
+                copied += len;
+            }
+        }
+    }
+    (frames, copied)
+}
+
+fn frame_copies(c: &mut Criterion) {
+    let packets = packets();
+    let paths = [("cloned frame, one consumer (old)", true), ("pooled, shared (new)", false)];
+
+    println!("{:<34} {:>12} {:>14} {:>15}", "", "allocs/frame", "alloc KB/frame", "copied KB/frame");
+    for (name, baseline) in paths {
+        // A warm depacketizer, as on a running stream, so the pool is primed
+        let mut d = H264Depacketizer::new();
+        run(&mut d, &packets, baseline);
+        let before = ALLOCATED.with(Cell::get);
+        let (frames, copied) = run(&mut d, &packets, baseline);
+        let after = ALLOCATED.with(Cell::get);
+        let per_frame = |v: u64| v as f64 / frames as f64;
+        println!(
+            "{:<34} {:>12.2} {:>14.1} {:>15.1}",
+            name,
+            per_frame(after.0 - before.0),
+            per_frame(after.1 - before.1) / 1024.0,
+            per_frame(copied) / 1024.0
+        );
+    }
+
+    let mut group = c.benchmark_group("frame_copies");
+    group.sample_size(20);
+    for (name, baseline) in paths {
+        let mut d = H264Depacketizer::new();
+        group.bench_function(name, |b| b.iter(|| run(&mut d, &packets, baseline)));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, frame_copies);
+criterion_main!(benches);
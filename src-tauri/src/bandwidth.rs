@@ -0,0 +1,228 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::frame_timing::CLOCK_RATE;
+
+/// Never ask the phone to go below this (bps)
+pub const MIN_BITRATE: u64 = 150_000;
+/// Estimate before the link has been measured (bps)
+const INITIAL_BITRATE: u64 = 2_500_000;
+/// Window the incoming rate is measured over
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+/// Window the uncongested one-way delay is taken from
+const BASELINE_WINDOW: Duration = Duration::from_secs(10);
+/// Queueing delay (seconds) above which the path is taken to be over capacity
+const OVERUSE_DELAY: f64 = 0.040;
+/// Loss above this fraction backs off even without a delay signal
+const HIGH_LOSS: f64 = 0.10;
+/// Loss below this fraction allows increasing
+const LOW_LOSS: f64 = 0.02;
+/// Spacing between decreases, so one congestion episode is acted on once
+const DECREASE_INTERVAL: Duration = Duration::from_millis(500);
+/// Multiplicative increase per second while the path is clear
+const INCREASE_PER_SECOND: f64 = 0.08;
+/// REMB refresh while the estimate holds steady
+const REMB_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Receive-side bandwidth estimate for one video track, sent to the phone as REMB.
+///
+/// Delay based like GCC's receive side: the one-way delay of each frame's first
+/// packet is compared with the lowest seen recently, and a growing queue means the
+/// phone is sending more than the path carries. Heavy loss also backs off. While
+/// the path is clear the estimate grows slowly, but never far past what actually
+/// arrives, and never past the user's cap.
+pub struct BandwidthEstimator {
+    max_bitrate: Option<u64>,
+    estimate: f64,
+    /// Packets within `RATE_WINDOW`: arrival and size
+    received: VecDeque<(Instant, usize)>,
+    received_bytes: usize,
+    /// RTP timestamp of the last frame and its capture time in seconds, unwrapped
+    last_frame: Option<(u32, f64)>,
+    /// Arrival of the first packet seen, origin for one-way delays
+    origin: Option<Instant>,
+    /// One-way delay (arbitrary offset) of recent frames, for the baseline
+    delays: VecDeque<(Instant, f64)>,
+    queue_delay: f64,
+    packets: u64,
+    /// Packet and loss counts at the previous update
+    counted: (u64, u64),
+    last_update: Option<Instant>,
+    last_decrease: Option<Instant>,
+    last_sent: Option<(Instant, u64)>,
+}
+
+impl BandwidthEstimator {
+    /// `max_bitrate` in bps; `None` for no cap
+    pub fn new(max_bitrate: Option<u64>) -> Self {
+        let estimate = max_bitrate.map_or(INITIAL_BITRATE, |max| max.min(INITIAL_BITRATE));
+        Self {
+            max_bitrate,
+            estimate: estimate as f64,
+            received: VecDeque::new(),
+            received_bytes: 0,
+            last_frame: None,
+            origin: None,
+            delays: VecDeque::new(),
+            queue_delay: 0.0,
+            packets: 0,
+            counted: (0, 0),
+            last_update: None,
+            last_decrease: None,
+            last_sent: None,
+        }
+    }
+
+    /// Record a packet as it arrives (before the jitter buffer reorders anything).
+    pub fn on_packet(&mut self, rtp_timestamp: u32, size: usize, arrival: Instant) {
+        self.packets += 1;
+        self.received.push_back((arrival, size));
+        self.received_bytes += size;
+
+        let origin = *self.origin.get_or_insert(arrival);
+        let captured = match self.last_frame {
+            Some((last_ts, _)) if last_ts == rtp_timestamp => return,
+            Some((last_ts, last_time)) => {
+                last_time + rtp_timestamp.wrapping_sub(last_ts) as i32 as f64 / CLOCK_RATE as f64
+            }
+            None => 0.0,
+        };
+        self.last_frame = Some((rtp_timestamp, captured));
+
+        let delay = arrival.duration_since(origin).as_secs_f64() - captured;
+        while self.delays.front().is_some_and(|(t, _)| arrival.duration_since(*t) > BASELINE_WINDOW) {
+            self.delays.pop_front();
+        }
+        self.delays.push_back((arrival, delay));
+        let baseline = self.delays.iter().map(|(_, d)| *d).fold(f64::INFINITY, f64::min);
+        self.queue_delay = delay - baseline;
+    }
+
+    /// Bits per second arriving over the last `RATE_WINDOW`
+    pub fn incoming_bitrate(&mut self, now: Instant) -> f64 {
+        while let Some(&(t, size)) = self.received.front() {
+            if now.saturating_duration_since(t) <= RATE_WINDOW {
+                break;
+            }
+            self.received.pop_front();
+            self.received_bytes -= size;
+        }
+        self.received_bytes as f64 * 8.0 / RATE_WINDOW.as_secs_f64()
+    }
+
+    pub fn estimate(&self) -> u64 {
+        self.estimate as u64
+    }
+
+    /// Move the estimate on. `lost` is the track's running count of lost packets.
+    /// Returns a bitrate when a REMB is due: periodically, and right away when the
+    /// estimate drops.
+    pub fn update(&mut self, now: Instant, lost: u64) -> Option<u64> {
+        let elapsed = self.last_update.map_or(0.0, |t| now.saturating_duration_since(t).as_secs_f64().min(1.0));
+        self.last_update = Some(now);
+
+        let (packets, previous_lost) = self.counted;
+        let new_lost = lost.saturating_sub(previous_lost);
+        let new_received = self.packets - packets;
+        self.counted = (self.packets, lost);
+        let loss = if new_lost + new_received > 0 {
+            new_lost as f64 / (new_lost + new_received) as f64
+        } else {
+            0.0
+        };
+
+        let incoming = self.incoming_bitrate(now);
+        let may_decrease = self.last_decrease.is_none_or(|t| now.duration_since(t) >= DECREASE_INTERVAL);
+        if self.queue_delay > OVERUSE_DELAY {
+            if may_decrease {
+                self.estimate = self.estimate.min(0.85 * incoming);
+                self.last_decrease = Some(now);
+            }
+        } else if loss > HIGH_LOSS {
+            if may_decrease {
+                self.estimate *= 1.0 - 0.5 * loss;
+                self.last_decrease = Some(now);
+            }
+        } else if loss < LOW_LOSS && self.queue_delay < OVERUSE_DELAY / 2.0 && self.estimate < 1.5 * incoming {
+            // Only grow while the phone is actually using what it's allowed
+            self.estimate = (self.estimate * (1.0 + INCREASE_PER_SECOND * elapsed)).min(1.5 * incoming);
+        }
+        let max = self.max_bitrate.unwrap_or(u64::MAX).max(MIN_BITRATE);
+        self.estimate = self.estimate.clamp(MIN_BITRATE as f64, max as f64);
+
+        let estimate = self.estimate();
+        let due = match self.last_sent {
+            None => true,
+            Some((t, sent)) => now.duration_since(t) >= REMB_INTERVAL || (estimate as f64) < 0.97 * sent as f64,
+        };
+        if !due {
+            return None;
+        }
+        self.last_sent = Some((now, estimate));
+        Some(estimate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed `frames` of 30 fps video at `bps`, five packets a frame, each frame
+    /// arriving `extra_delay(frame)` later than it was captured, with every
+    /// `drop_every`th packet lost. Returns the last estimate.
+    fn run(
+        bwe: &mut BandwidthEstimator,
+        start: Instant,
+        frames: std::ops::Range<u32>,
+        bps: u64,
+        extra_delay: impl Fn(u32) -> Duration,
+        drop_every: Option<u64>,
+    ) -> u64 {
+        let packet_size = (bps / 8 / 30 / 5) as usize;
+        let mut lost = 0;
+        let mut seq = 0u64;
+        for frame in frames {
+            let arrival = start + Duration::from_micros(frame as u64 * 33_333) + extra_delay(frame);
+            for _ in 0..5 {
+                seq += 1;
+                if drop_every.is_some_and(|n| seq.is_multiple_of(n)) {
+                    lost += 1;
+                    continue;
+                }
+                bwe.on_packet(frame * 3000, packet_size, arrival);
+            }
+            bwe.update(arrival, lost);
+        }
+        bwe.estimate()
+    }
+
+    #[test]
+    fn clear_path_grows_toward_what_arrives_and_respects_the_cap() {
+        let start = Instant::now();
+        let mut bwe = BandwidthEstimator::new(None);
+        let estimate = run(&mut bwe, start, 0..900, 2_000_000, |_| Duration::ZERO, None);
+        assert!(estimate > INITIAL_BITRATE && estimate <= 3_200_000, "{}", estimate);
+
+        let mut capped = BandwidthEstimator::new(Some(1_000_000));
+        let estimate = run(&mut capped, start, 0..900, 2_000_000, |_| Duration::ZERO, None);
+        assert_eq!(estimate, 1_000_000);
+    }
+
+    #[test]
+    fn growing_queue_backs_off_below_the_incoming_rate() {
+        let start = Instant::now();
+        let mut bwe = BandwidthEstimator::new(None);
+        run(&mut bwe, start, 0..150, 2_000_000, |_| Duration::ZERO, None);
+        // The path drains slower than the phone sends: 4 ms more queue each frame
+        let estimate = run(&mut bwe, start, 150..180, 2_000_000, |f| Duration::from_millis((f as u64 - 149) * 4), None);
+        assert!(estimate <= 1_700_000, "{}", estimate);
+    }
+
+    #[test]
+    fn heavy_loss_backs_off() {
+        let start = Instant::now();
+        let mut bwe = BandwidthEstimator::new(None);
+        let estimate = run(&mut bwe, start, 0..180, 2_000_000, |_| Duration::ZERO, Some(4));
+        assert!(estimate < INITIAL_BITRATE / 2, "{}", estimate);
+    }
+}
@@ -0,0 +1,267 @@
+use serde::{Deserialize, Serialize};
+
+use crate::depacketizer::Codec;
+
+/// Which video codecs we accept from the phone and with what parameters.
+///
+/// Replaces webrtc-rs's `register_default_codecs`: by default only codecs the virtual
+/// camera can pass through (H.264, and H.265 when opted into) are registered, so the
+/// phone can't negotiate something the output path would have to throw away. VP8, VP9
+/// and AV1 are only accepted when listed, and then reach the camera as an explicit
+/// error rather than a corrupt stream. Limits are advertised in the answer we send.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, rename_all = "camelCase")]
+pub struct CodecPolicy {
+    /// Accepted codecs, most preferred first
+    pub codecs: Vec<Codec>,
+    /// H.264 `profile-level-id`s (hex) to accept, most preferred first
+    pub h264_profile_level_ids: Vec<String>,
+    /// H.264 packetization modes to accept (0 = single NAL, 1 = non-interleaved)
+    pub h264_packetization_modes: Vec<u8>,
+    /// Largest picture the phone should send; 0 means no limit
+    pub max_width: u32,
+    pub max_height: u32,
+    /// Highest frame rate the phone should send; 0 means no limit
+    pub max_framerate: u32,
+    /// Highest video bitrate the phone should send, in kbps; 0 means no limit.
+    /// Advertised as `b=AS`/`b=TIAS` and enforced through REMB.
+    pub max_bitrate_kbps: u32,
+}
+
+impl Default for CodecPolicy {
+    fn default() -> Self {
+        Self {
+            codecs: vec![Codec::H264],
+            // Constrained Baseline, Baseline and Constrained High (constraint_set4/5), as
+            // Chrome and Safari offer them. High allows CABAC, which concealment can't
+            // build a repeat picture for (`h264_syntax::skip_picture` is CAVLC-only), so
+            // after a loss such a stream only has its withheld frames dropped
+            h264_profile_level_ids: vec!["42e01f".into(), "42001f".into(), "640c1f".into()],
+            h264_packetization_modes: vec![1, 0],
+            // What the virtual camera advertises
+            max_width: 1920,
+            max_height: 1080,
+            max_framerate: 30,
+            max_bitrate_kbps: 0,
+        }
+    }
+}
+
+/// One codec entry to register with the media engine
+#[derive(Debug, Clone, PartialEq)]
+pub struct CodecEntry {
+    pub codec: Codec,
+    pub sdp_fmtp_line: String,
+}
+
+impl CodecPolicy {
+    /// The accepted codecs, in preference order.
+    pub fn accepted(&self) -> Vec<Codec> {
+        let mut accepted: Vec<Codec> = Vec::new();
+        for &codec in &self.codecs {
+            if !accepted.contains(&codec) {
+                accepted.push(codec);
+            }
+        }
+        accepted
+    }
+
+    /// Codec entries to register, in preference order.
+    pub fn entries(&self) -> Vec<CodecEntry> {
+        let mut entries = Vec::new();
+        for codec in self.accepted() {
+            match codec {
+                Codec::H264 => {
+                    for profile_level_id in &self.h264_profile_level_ids {
+                        for mode in &self.h264_packetization_modes {
+                            entries.push(CodecEntry {
+                                codec,
+                                sdp_fmtp_line: format!(
+                                    "level-asymmetry-allowed=1;packetization-mode={};profile-level-id={}",
+                                    mode, profile_level_id
+                                ),
+                            });
+                        }
+                    }
+                }
+                Codec::H265 | Codec::Vp8 | Codec::Vp9 | Codec::Av1 => entries.push(CodecEntry { codec, sdp_fmtp_line: String::new() }),
+            }
+        }
+        entries
+    }
+
+    /// Receive limits for one codec, as fmtp parameters.
+    fn limit_params(&self, codec: Codec) -> Vec<String> {
+        let mut params = Vec::new();
+        // Frame size in 16x16 macroblocks
+        let max_fs = (self.max_width > 0 && self.max_height > 0)
+            .then(|| self.max_width.div_ceil(16) * self.max_height.div_ceil(16));
+
+        match codec {
+            Codec::H264 => {
+                if let Some(fs) = max_fs {
+                    params.push(format!("max-fs={}", fs));
+                    if self.max_framerate > 0 {
+                        params.push(format!("max-mbps={}", fs * self.max_framerate));
+                    }
+                }
+            }
+            Codec::H265 => {
+                if self.max_width > 0 && self.max_height > 0 {
+                    params.push(format!("max-lps={}", self.max_width * self.max_height));
+                }
+                if self.max_framerate > 0 {
+                    params.push(format!("max-fps={}", self.max_framerate * 100));
+                }
+            }
+            Codec::Vp8 | Codec::Vp9 => {
+                if let Some(fs) = max_fs {
+                    params.push(format!("max-fs={}", fs));
+                }
+                if self.max_framerate > 0 {
+                    params.push(format!("max-fr={}", self.max_framerate));
+                }
+            }
+            Codec::Av1 => {}
+        }
+        params
+    }
+
+    /// Add our resolution and frame rate limits to an SDP we send (answer or offer):
+    /// receive limits on each video payload type's fmtp, plus `a=framerate` and the
+    /// bitrate cap per video section.
+    pub fn shape_sdp(&self, sdp: &str) -> String {
+        let lines: Vec<&str> = sdp.split("\r\n").filter(|l| !l.is_empty()).collect();
+        let mut out: Vec<String> = Vec::with_capacity(lines.len() + 8);
+        let mut section_start = 0;
+        let mut in_video = false;
+
+        for (i, line) in lines.iter().enumerate() {
+            if line.starts_with("m=") {
+                if in_video {
+                    self.shape_video_section(&mut out, section_start);
+                }
+                in_video = line.starts_with("m=video");
+                section_start = out.len();
+            }
+            out.push(line.to_string());
+            if i == lines.len() - 1 && in_video {
+                self.shape_video_section(&mut out, section_start);
+            }
+        }
+
+        let mut shaped = out.join("\r\n");
+        shaped.push_str("\r\n");
+        shaped
+    }
+
+    /// Rewrite the video section that starts at `out[start]` and runs to the end.
+    fn shape_video_section(&self, out: &mut Vec<String>, start: usize) {
+        let rtpmaps: Vec<(String, Codec)> = out[start..]
+            .iter()
+            .filter_map(|l| l.strip_prefix("a=rtpmap:"))
+            .filter_map(|rest| {
+                let (pt, encoding) = rest.split_once(' ')?;
+                let name = encoding.split('/').next()?;
+                Some((pt.to_string(), Codec::from_mime_type(&format!("video/{}", name))?))
+            })
+            .collect();
+
+        for (pt, codec) in rtpmaps {
+            let params = self.limit_params(codec);
+            if params.is_empty() {
+                continue;
+            }
+            let params = params.join(";");
+            let fmtp_prefix = format!("a=fmtp:{} ", pt);
+            if let Some(fmtp) = out[start..].iter_mut().find(|l| l.starts_with(&fmtp_prefix)) {
+                fmtp.push(';');
+                fmtp.push_str(&params);
+            } else if let Some(pos) = out[start..].iter().position(|l| l.starts_with(&format!("a=rtpmap:{} ", pt))) {
+                out.insert(start + pos + 1, format!("{}{}", fmtp_prefix, params));
+            }
+        }
+
+        if self.max_framerate > 0 {
+            out.push(format!("a=framerate:{}", self.max_framerate));
+        }
+
+        // Bandwidth lines go after the section's c= line, before any attribute
+        if self.max_bitrate_kbps > 0 {
+            let at = out[start..]
+                .iter()
+                .position(|l| l.starts_with("a="))
+                .map_or(out.len(), |pos| start + pos);
+            out.insert(at, format!("b=TIAS:{}", self.max_bitrate_kbps as u64 * 1000));
+            out.insert(at, format!("b=AS:{}", self.max_bitrate_kbps));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ANSWER: &str = "v=0\r\no=- 1 2 IN IP4 0.0.0.0\r\ns=-\r\nt=0 0\r\n\
+        m=video 9 UDP/TLS/RTP/SAVPF 102 108\r\nc=IN IP4 0.0.0.0\r\n\
+        a=rtpmap:102 H264/90000\r\n\
+        a=fmtp:102 level-asymmetry-allowed=1;packetization-mode=1;profile-level-id=42e01f\r\n\
+        a=rtpmap:108 H264/90000\r\n\
+        m=application 9 UDP/DTLS/SCTP webrtc-datachannel\r\na=mid:1\r\n";
+
+    #[test]
+    fn entries_follow_policy_order_without_duplicates() {
+        let policy = CodecPolicy {
+            codecs: vec![Codec::H265, Codec::H264, Codec::H265],
+            h264_profile_level_ids: vec!["42e01f".into()],
+            h264_packetization_modes: vec![1],
+            ..Default::default()
+        };
+        let entries = policy.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].codec, Codec::H265);
+        assert_eq!(entries[1].sdp_fmtp_line, "level-asymmetry-allowed=1;packetization-mode=1;profile-level-id=42e01f");
+    }
+
+    #[test]
+    fn answer_gets_limits_in_video_section_only() {
+        let policy = CodecPolicy { max_width: 1280, max_height: 720, max_framerate: 30, ..Default::default() };
+        let shaped = policy.shape_sdp(ANSWER);
+        let lines: Vec<&str> = shaped.split("\r\n").collect();
+
+        assert!(lines.contains(&"a=fmtp:102 level-asymmetry-allowed=1;packetization-mode=1;profile-level-id=42e01f;max-fs=3600;max-mbps=108000"));
+        // fmtp inserted right after the rtpmap that had none
+        let rtpmap = lines.iter().position(|l| *l == "a=rtpmap:108 H264/90000").unwrap();
+        assert_eq!(lines[rtpmap + 1], "a=fmtp:108 max-fs=3600;max-mbps=108000");
+        // framerate lands before the next section
+        let app = lines.iter().position(|l| l.starts_with("m=application")).unwrap();
+        assert_eq!(lines[app - 1], "a=framerate:30");
+        assert_eq!(lines.iter().filter(|l| l.starts_with("a=framerate")).count(), 1);
+    }
+
+    #[test]
+    fn vp8_gets_frame_size_and_rate_limits() {
+        let policy = CodecPolicy { codecs: vec![Codec::H264, Codec::Vp8], ..Default::default() };
+        assert_eq!(policy.entries().last().unwrap(), &CodecEntry { codec: Codec::Vp8, sdp_fmtp_line: String::new() });
+
+        let answer = ANSWER.replace("a=rtpmap:108 H264/90000", "a=rtpmap:108 VP8/90000");
+        let shaped = policy.shape_sdp(&answer);
+        assert!(shaped.contains("a=rtpmap:108 VP8/90000\r\na=fmtp:108 max-fs=8160;max-fr=30\r\n"));
+    }
+
+    #[test]
+    fn bitrate_cap_goes_before_video_attributes() {
+        let policy = CodecPolicy { max_bitrate_kbps: 2500, ..Default::default() };
+        let shaped = policy.shape_sdp(ANSWER);
+        let lines: Vec<&str> = shaped.split("\r\n").collect();
+        let c = lines.iter().position(|l| l.starts_with("c=")).unwrap();
+        assert_eq!(&lines[c + 1..c + 3], &["b=AS:2500", "b=TIAS:2500000"]);
+        assert_eq!(lines.iter().filter(|l| l.starts_with("b=")).count(), 2);
+    }
+
+    #[test]
+    fn no_limits_leave_answer_unchanged() {
+        let policy = CodecPolicy { max_width: 0, max_height: 0, max_framerate: 0, ..Default::default() };
+        assert_eq!(policy.shape_sdp(ANSWER), ANSWER);
+    }
+}
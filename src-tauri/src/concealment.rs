@@ -0,0 +1,414 @@
+use std::collections::VecDeque;
+use std::ops::Range;
+
+use crate::depacketizer::Codec;
+use crate::frame_timing::FrameTiming;
+use crate::h264_syntax::{self, Pps, SliceHeader, Sps, NAL_IDR, NAL_PPS, NAL_SEI, NAL_SPS};
+use crate::webrtc_client::VideoFrame;
+
+/// Loss ranges not yet matched to a frame; more than this means frames went missing whole
+const MAX_PENDING_LOSSES: usize = 32;
+
+/// What to do with an assembled access unit
+#[derive(Clone)]
+pub enum Verdict {
+    /// Decodable: hand it to the sinks
+    Forward,
+    /// Depends on a picture we lost: withhold it and hand the sinks this repeat of the
+    /// last good picture instead, so they keep their cadence
+    Repeat(VideoFrame),
+    /// Depends on a picture we lost and we can't make a repeat (not H.264, CABAC, ...):
+    /// withhold it, the sink keeps showing the last good picture
+    Drop,
+}
+
+/// The last H.264 picture we forwarded, for repeating it
+struct Shown {
+    sps: Sps,
+    pps: Pps,
+    pic_order_cnt_lsb: u32,
+    width: u32,
+    height: u32,
+    frame_rate: Option<(u32, u32)>,
+}
+
+/// Tracks H.264 reference-chain integrity so that, after a loss, dependent frames are
+/// held back until a complete IDR or recovery point arrives. Withheld frames are
+/// replaced by an all-skip picture, which decodes to the last good one; that needs a
+/// CAVLC stream, so CABAC streams (common with High profile) only have them dropped.
+/// Other codecs are only inspected for keyframes.
+///
+/// Without this, phones with long GOPs produce seconds of smearing after every drop.
+#[derive(Default)]
+pub struct ConcealmentTracker {
+    sps: Option<Sps>,
+    pps: Vec<Pps>,
+    /// frame_num of the last reference picture we forwarded
+    prev_ref_frame_num: Option<u32>,
+    shown: Option<Shown>,
+    /// Repeats sent since `shown`
+    repeats: u32,
+    /// Lost sequence numbers reported by the jitter buffer, oldest first
+    lost: VecDeque<Range<u64>>,
+    /// Last sequence number of the last frame inspected
+    last_seq: Option<u64>,
+    broken: bool,
+}
+
+impl ConcealmentTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The jitter buffer gave up on packets `seqs`. They're charged to the frame whose
+    /// packets span them once it's inspected.
+    pub fn packets_lost(&mut self, seqs: Range<u64>) {
+        if self.lost.len() == MAX_PENDING_LOSSES {
+            self.lost.pop_front();
+        }
+        self.lost.push_back(seqs);
+    }
+
+    /// Whether the frame lost packets: between the previous frame's last packet and its
+    /// own last one, or right after it if it didn't end on a marker.
+    fn damaged(&mut self, timing: &FrameTiming) -> bool {
+        let start = self.last_seq.map_or(timing.first_seq, |last| (last + 1).min(timing.first_seq));
+        let end = timing.last_seq + 1;
+        let mut damaged = self.lost.iter().any(|r| r.start < end && r.end > start);
+        if !timing.marker {
+            // The loss also overlaps the next frame: both are charged, we can't tell where
+            // one ended and the other began
+            damaged |= self.lost.iter().any(|r| r.start == end);
+        }
+        self.lost.retain(|r| r.end > end);
+        self.last_seq = Some(self.last_seq.map_or(timing.last_seq, |last| last.max(timing.last_seq)));
+        damaged
+    }
+
+    /// Inspect one frame and decide whether it can be forwarded.
+    pub fn inspect(&mut self, frame: &VideoFrame) -> Verdict {
+        let damaged = self.damaged(&frame.timing);
+        let mut idr = frame.keyframe;
+        let mut recovery_point = false;
+        // Header and nal_ref_idc of the first slice
+        let mut slice: Option<(SliceHeader, u8)> = None;
+
+        let access_unit: &[u8] = if frame.codec == Codec::H264 { &frame.data } else { &[] };
+        for nal in h264_syntax::nal_units(access_unit) {
+            match h264_syntax::nal_type(nal) {
+                NAL_SPS => {
+                    if let Some(sps) = Sps::parse(nal) {
+                        self.sps = Some(sps);
+                    }
+                }
+                NAL_PPS => {
+                    if let Some(pps) = Pps::parse(nal) {
+                        self.pps.retain(|p| p.id != pps.id);
+                        self.pps.push(pps);
+                    }
+                }
+                NAL_SEI => recovery_point |= h264_syntax::has_recovery_point(nal),
+                t => {
+                    idr |= t == NAL_IDR;
+                    if slice.is_none() {
+                        if let Some(sps) = &self.sps {
+                            slice = SliceHeader::parse(nal, sps).map(|h| (h, h264_syntax::nal_ref_idc(nal)));
+                        }
+                    }
+                }
+            }
+        }
+
+        self.broken |= damaged;
+        // A frame_num that skips ahead means a reference picture never arrived,
+        // even if the jitter buffer didn't notice (e.g. a whole frame lost at once)
+        if let (Some((header, _)), Some(prev), Some(sps), false) = (slice, self.prev_ref_frame_num, &self.sps, idr) {
+            let expected = (prev + 1) % (1 << sps.log2_max_frame_num);
+            if header.frame_num != expected && header.frame_num != prev {
+                self.broken = true;
+            }
+        }
+
+        // Recovering from a partial keyframe would only trade smearing for garbage
+        let complete = !damaged
+            && frame.timing.marker
+            && slice.is_none_or(|(header, _)| header.first_mb_in_slice == 0);
+        if self.broken && !(complete && (idr || recovery_point)) {
+            return self.repeat(&frame.timing);
+        }
+
+        self.broken = false;
+        self.repeats = 0;
+        self.shown = None;
+        if let Some((header, ref_idc)) = slice {
+            if ref_idc != 0 || idr {
+                self.prev_ref_frame_num = Some(header.frame_num);
+            }
+            let pps = self.pps.iter().find(|p| p.id == header.pps_id);
+            if let (Some(sps), Some(pps)) = (&self.sps, pps) {
+                self.shown = Some(Shown {
+                    sps: sps.clone(),
+                    pps: pps.clone(),
+                    pic_order_cnt_lsb: header.pic_order_cnt_lsb.unwrap_or(0),
+                    width: frame.width,
+                    height: frame.height,
+                    frame_rate: frame.frame_rate,
+                });
+            }
+        }
+        Verdict::Forward
+    }
+
+    /// A non-reference skip picture following the last one shown, in place of a frame
+    /// with timing `timing`.
+    fn repeat(&mut self, timing: &FrameTiming) -> Verdict {
+        let (Some(shown), Some(prev_ref)) = (&self.shown, self.prev_ref_frame_num) else {
+            return Verdict::Drop;
+        };
+        // Each repeat is a frame later in display order than the one before
+        let pic_order_cnt_lsb = shown.pic_order_cnt_lsb + 2 * (self.repeats + 1);
+        let Some(data) = h264_syntax::skip_picture(&shown.sps, &shown.pps, prev_ref + 1, pic_order_cnt_lsb) else {
+            return Verdict::Drop;
+        };
+        self.repeats += 1;
+        Verdict::Repeat(VideoFrame {
+            data: data.into(),
+            width: shown.width,
+            height: shown.height,
+            frame_rate: shown.frame_rate,
+            codec: Codec::H264,
+            keyframe: false,
+            timing: timing.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::h264_syntax::BitWriter;
+
+    const LOG2_MAX_FRAME_NUM: u32 = 4;
+    const LOG2_MAX_POC_LSB: u32 = 6;
+
+    fn nal(header: u8, w: BitWriter) -> Vec<u8> {
+        let mut nal = vec![header];
+        nal.extend(h264_syntax::escape(&w.finish()));
+        nal
+    }
+
+    /// Baseline 32x32, pic_order_cnt_type 0
+    fn sps() -> Vec<u8> {
+        let mut w = BitWriter::new();
+        w.write_bits(66, 8); // profile_idc
+        w.write_bits(0, 8); // constraint flags
+        w.write_bits(30, 8); // level_idc
+        w.write_ue(0); // seq_parameter_set_id
+        w.write_ue(LOG2_MAX_FRAME_NUM - 4);
+        w.write_ue(0); // pic_order_cnt_type
+        w.write_ue(LOG2_MAX_POC_LSB - 4);
+        w.write_ue(1); // max_num_ref_frames
+        w.write_bit(false); // gaps_in_frame_num_value_allowed_flag
+        w.write_ue(1); // pic_width_in_mbs_minus1
+        w.write_ue(1); // pic_height_in_map_units_minus1
+        w.write_bit(true); // frame_mbs_only_flag
+        w.write_bit(true); // direct_8x8_inference_flag
+        w.write_bit(false); // frame_cropping_flag
+        w.write_bit(false); // vui_parameters_present_flag
+        nal(0x67, w)
+    }
+
+    fn pps() -> Vec<u8> {
+        let mut w = BitWriter::new();
+        w.write_ue(0); // pic_parameter_set_id
+        w.write_ue(0); // seq_parameter_set_id
+        w.write_bits(0, 2); // CAVLC, bottom_field_pic_order_in_frame_present_flag
+        w.write_ue(0); // num_slice_groups_minus1
+        w.write_ue(0);
+        w.write_ue(0);
+        w.write_bits(0, 3); // weighted_pred_flag, weighted_bipred_idc
+        w.write_se(0);
+        w.write_se(0);
+        w.write_se(0);
+        w.write_bits(0b100, 3); // deblocking_filter_control_present_flag, ...
+        nal(0x68, w)
+    }
+
+    fn slice(idr: bool, ref_idc: u8, first_mb: u32, frame_num: u32, poc_lsb: u32) -> Vec<u8> {
+        let mut w = BitWriter::new();
+        w.write_ue(first_mb);
+        w.write_ue(if idr { 7 } else { 5 });
+        w.write_ue(0); // pic_parameter_set_id
+        w.write_bits(frame_num, LOG2_MAX_FRAME_NUM);
+        if idr {
+            w.write_ue(0); // idr_pic_id
+        }
+        w.write_bits(poc_lsb, LOG2_MAX_POC_LSB);
+        nal(ref_idc << 5 | if idr { 5 } else { 1 }, w)
+    }
+
+    /// A recovery point SEI
+    const RECOVERY_POINT: [u8; 5] = [0x06, 0x06, 0x01, 0xc4, 0x80];
+
+    /// Hands out frames of two packets each, with consecutive sequence numbers
+    struct Stream {
+        seq: u64,
+    }
+
+    impl Stream {
+        fn new() -> Self {
+            Self { seq: 0 }
+        }
+
+        fn frame(&mut self, nals: &[&[u8]]) -> VideoFrame {
+            let mut data = Vec::new();
+            for nal in nals {
+                data.extend_from_slice(&[0, 0, 0, 1]);
+                data.extend_from_slice(nal);
+            }
+            let keyframe = nals.iter().any(|n| h264_syntax::nal_type(n) == NAL_IDR);
+            let timing = FrameTiming {
+                pts: self.seq * 1500,
+                first_seq: self.seq,
+                last_seq: self.seq + 1,
+                marker: true,
+                ..Default::default()
+            };
+            self.seq += 2;
+            VideoFrame::h264(data, 32, 32, keyframe, timing)
+        }
+
+        fn idr(&mut self) -> VideoFrame {
+            self.frame(&[&sps(), &pps(), &slice(true, 3, 0, 0, 0)])
+        }
+
+        fn p(&mut self, frame_num: u32) -> VideoFrame {
+            self.frame(&[&slice(false, 2, 0, frame_num, (frame_num * 2) % (1 << LOG2_MAX_POC_LSB))])
+        }
+
+        /// Lose the next packets, before the frame they belonged to was assembled
+        fn lose(&mut self, tracker: &mut ConcealmentTracker, count: u64) {
+            tracker.packets_lost(self.seq..self.seq + count);
+            self.seq += count;
+        }
+    }
+
+    fn forwarded(verdict: Verdict) -> bool {
+        matches!(verdict, Verdict::Forward)
+    }
+
+    #[test]
+    fn frame_num_wraps_at_max_frame_num() {
+        let mut tracker = ConcealmentTracker::new();
+        let mut stream = Stream::new();
+        assert!(forwarded(tracker.inspect(&stream.idr())));
+        // 1..=15, then 0 again: no gap
+        for frame_num in (1..1 << LOG2_MAX_FRAME_NUM).chain([0, 1]) {
+            assert!(forwarded(tracker.inspect(&stream.p(frame_num))), "frame_num {frame_num}");
+        }
+        // A whole frame (frame_num 2) missing without a sequence gap
+        assert!(!forwarded(tracker.inspect(&stream.p(3))));
+    }
+
+    #[test]
+    fn only_the_frame_spanning_the_gap_is_concealed() {
+        let mut tracker = ConcealmentTracker::new();
+        let mut stream = Stream::new();
+        assert!(forwarded(tracker.inspect(&stream.idr())));
+
+        // Reported before the frame ahead of the gap came out of the depacketizer
+        // (completed by the next frame's timestamp rather than its marker)
+        let before = stream.p(1);
+        stream.lose(&mut tracker, 1);
+        let after = stream.p(2);
+        assert!(forwarded(tracker.inspect(&before)));
+        assert!(!forwarded(tracker.inspect(&after)));
+
+        // Without a marker the frame ahead may have lost its tail: both are charged
+        let mut tracker = ConcealmentTracker::new();
+        let mut stream = Stream::new();
+        tracker.inspect(&stream.idr());
+        let mut before = stream.p(1);
+        before.timing.marker = false;
+        stream.lose(&mut tracker, 1);
+        assert!(!forwarded(tracker.inspect(&before)));
+    }
+
+    #[test]
+    fn recovers_only_on_a_complete_idr_or_recovery_point() {
+        let mut tracker = ConcealmentTracker::new();
+        let mut stream = Stream::new();
+        tracker.inspect(&stream.idr());
+        stream.lose(&mut tracker, 2);
+        assert!(!forwarded(tracker.inspect(&stream.p(2))));
+
+        // Leading slice missing
+        let partial = stream.frame(&[&sps(), &pps(), &slice(true, 3, 2, 0, 0)]);
+        assert!(!forwarded(tracker.inspect(&partial)));
+        // Packets lost inside the IDR
+        stream.lose(&mut tracker, 1);
+        assert!(!forwarded(tracker.inspect(&stream.idr())));
+        // Tail missing
+        let mut truncated = stream.idr();
+        truncated.timing.marker = false;
+        assert!(!forwarded(tracker.inspect(&truncated)));
+        // Dependent frames stay withheld until then
+        assert!(!forwarded(tracker.inspect(&stream.p(1))));
+
+        assert!(forwarded(tracker.inspect(&stream.idr())));
+        assert!(forwarded(tracker.inspect(&stream.p(1))));
+
+        // A recovery point SEI works like an IDR, with a frame_num gap and all
+        stream.lose(&mut tracker, 2);
+        assert!(!forwarded(tracker.inspect(&stream.p(3))));
+        let recovery = stream.frame(&[&RECOVERY_POINT, &slice(false, 2, 0, 7, 14)]);
+        assert!(forwarded(tracker.inspect(&recovery)));
+        assert!(forwarded(tracker.inspect(&stream.p(8))));
+    }
+
+    #[test]
+    fn concealed_frames_become_repeats_of_the_last_picture() {
+        let mut tracker = ConcealmentTracker::new();
+        let mut stream = Stream::new();
+        tracker.inspect(&stream.idr());
+        tracker.inspect(&stream.p(1));
+        stream.lose(&mut tracker, 2);
+
+        let sps = Sps::parse(&sps()).unwrap();
+        for n in 1..=2 {
+            let concealed = stream.p(3);
+            let Verdict::Repeat(repeat) = tracker.inspect(&concealed) else {
+                panic!("no repeat");
+            };
+            assert_eq!(repeat.timing, concealed.timing);
+            assert_eq!((repeat.width, repeat.height, repeat.keyframe), (32, 32, false));
+
+            let nals: Vec<_> = h264_syntax::nal_units(&repeat.data).collect();
+            assert_eq!(nals.len(), 1);
+            assert_eq!((h264_syntax::nal_type(nals[0]), h264_syntax::nal_ref_idc(nals[0])), (1, 0));
+            let header = SliceHeader::parse(nals[0], &sps).unwrap();
+            // Follows the last reference picture, each repeat later in display order
+            assert_eq!(header.frame_num, 2);
+            assert_eq!(header.pic_order_cnt_lsb, Some(2 + 2 * n));
+        }
+    }
+
+    #[test]
+    fn nothing_to_repeat_without_an_h264_picture() {
+        let mut tracker = ConcealmentTracker::new();
+        let mut frame = Stream::new().frame(&[]);
+        frame.codec = Codec::H265;
+        frame.keyframe = true;
+        assert!(forwarded(tracker.inspect(&frame)));
+
+        tracker.packets_lost(2..3);
+        frame.keyframe = false;
+        frame.timing.first_seq = 3;
+        frame.timing.last_seq = 4;
+        assert!(matches!(tracker.inspect(&frame), Verdict::Drop));
+        frame.keyframe = true;
+        frame.timing.first_seq = 5;
+        frame.timing.last_seq = 6;
+        assert!(forwarded(tracker.inspect(&frame)));
+    }
+}
@@ -0,0 +1,331 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::telemetry::TelemetryReport;
+
+/// Label of the data channel the desktop opens for control traffic
+pub const CHANNEL_LABEL: &str = "control";
+/// How long the phone gets to answer a request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Which way the phone's camera faces
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Facing {
+    User,
+    Environment,
+}
+
+/// `focusMode` as in the browser's media track constraints
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FocusMode {
+    Continuous,
+    SingleShot,
+    Manual,
+}
+
+/// What the desktop can ask of the phone. Camera requests are answered with a
+/// `CameraState`, `SetQuality` with nothing.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase", rename_all_fields = "camelCase")]
+pub enum ControlRequest {
+    GetCameraState,
+    /// Switch to the camera facing `facing`, or to the other one
+    SwitchCamera { facing: Option<Facing> },
+    SetTorch { on: bool },
+    SetZoom { zoom: f64 },
+    SetFocusMode { mode: FocusMode },
+    /// In EV stops
+    SetExposureCompensation { value: f64 },
+    /// Capture size; the phone picks the closest it supports
+    SetResolution { width: u32, height: u32 },
+    SetFramerate { fps: f64 },
+    /// Cap the phone's encoder; 0 lifts the cap
+    SetQuality { max_bitrate_kbps: u32 },
+}
+
+/// A numeric capability as the browser reports it
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Range {
+    pub min: f64,
+    pub max: f64,
+    #[serde(default)]
+    pub step: Option<f64>,
+}
+
+/// What the phone's current camera can do; `None` where it can't be controlled
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct CameraCapabilities {
+    pub zoom: Option<Range>,
+    pub torch: bool,
+    pub focus_modes: Vec<FocusMode>,
+    pub exposure_compensation: Option<Range>,
+    pub width: Option<Range>,
+    pub height: Option<Range>,
+    pub frame_rate: Option<Range>,
+}
+
+/// The phone's current camera settings
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct CameraSettings {
+    pub facing: Option<Facing>,
+    pub zoom: Option<f64>,
+    pub torch: Option<bool>,
+    pub focus_mode: Option<FocusMode>,
+    pub exposure_compensation: Option<f64>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub frame_rate: Option<f64>,
+}
+
+/// Reply to camera requests: the camera's ranges, for sliders, and where it is now
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct CameraState {
+    /// The browser's name for the camera, e.g. "Back Camera"
+    pub label: Option<String>,
+    pub capabilities: CameraCapabilities,
+    pub settings: CameraSettings,
+}
+
+/// What the phone sends unprompted
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum PhoneEvent {
+    Telemetry(Box<TelemetryReport>),
+}
+
+/// One control message on the data channel, or inside a signaling `control` message
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum Frame {
+    Request {
+        id: u64,
+        request: ControlRequest,
+    },
+    /// Answers the request with the same id: `error` if it failed, otherwise
+    /// `result` (absent for requests that return nothing)
+    Response {
+        id: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        result: Option<Value>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    Event {
+        event: PhoneEvent,
+    },
+}
+
+/// Sends control requests to the phone and matches responses to them by id.
+///
+/// Requests go over the peer connection's data channel while it is open, and over
+/// the signaling WebSocket otherwise; the phone answers on the transport a request
+/// came in on. Registered as Tauri managed state.
+#[derive(Default)]
+pub struct ControlLink {
+    next_id: AtomicU64,
+    pending: Mutex<HashMap<u64, oneshot::Sender<Result<Value, String>>>>,
+    /// Text messages for the open data channel
+    channel: Mutex<Option<mpsc::UnboundedSender<String>>>,
+    /// Outgoing signaling messages, while the WebRTC client is connected
+    signaling: Mutex<Option<mpsc::UnboundedSender<String>>>,
+    /// Where events from the phone go
+    events: Mutex<Option<mpsc::UnboundedSender<PhoneEvent>>>,
+}
+
+impl ControlLink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_channel(&self, channel: mpsc::UnboundedSender<String>) {
+        println!("[Control] Data channel open");
+        *self.channel.lock().unwrap() = Some(channel);
+    }
+
+    /// Forget `channel` if it is still the current one (a newer connection may
+    /// have replaced it already).
+    pub fn clear_channel(&self, channel: &mpsc::UnboundedSender<String>) {
+        let mut current = self.channel.lock().unwrap();
+        if current.as_ref().is_some_and(|c| c.same_channel(channel)) {
+            println!("[Control] Data channel closed, falling back to signaling");
+            *current = None;
+        }
+    }
+
+    pub fn set_signaling(&self, signaling: Option<mpsc::UnboundedSender<String>>) {
+        *self.signaling.lock().unwrap() = signaling;
+    }
+
+    pub fn set_events(&self, events: mpsc::UnboundedSender<PhoneEvent>) {
+        *self.events.lock().unwrap() = Some(events);
+    }
+
+    /// Send `request` and wait for the phone's answer, decoded as `T`.
+    pub async fn request<T: DeserializeOwned>(&self, request: ControlRequest) -> Result<T, String> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, tx);
+        if let Err(e) = self.send(&Frame::Request { id, request }) {
+            self.pending.lock().unwrap().remove(&id);
+            return Err(e);
+        }
+        let result = match tokio::time::timeout(REQUEST_TIMEOUT, rx).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err("The phone disconnected".to_string()),
+            Err(_) => {
+                self.pending.lock().unwrap().remove(&id);
+                Err("The phone didn't answer".to_string())
+            }
+        };
+        serde_json::from_value(result?).map_err(|e| format!("Unexpected answer from the phone: {}", e))
+    }
+
+    /// A message from the phone, from either transport.
+    pub fn on_frame(&self, frame: Frame) {
+        match frame {
+            Frame::Response { id, result, error } => {
+                let Some(waiter) = self.pending.lock().unwrap().remove(&id) else {
+                    return; // timed out already
+                };
+                let _ = waiter.send(match error {
+                    Some(error) => Err(error),
+                    None => Ok(result.unwrap_or(Value::Null)),
+                });
+            }
+            Frame::Event { event } => {
+                if let Some(events) = self.events.lock().unwrap().as_ref() {
+                    let _ = events.send(event);
+                }
+            }
+            Frame::Request { .. } => eprintln!("[Control] Ignoring request from the phone"),
+        }
+    }
+
+    /// A text message on the data channel.
+    pub fn on_text(&self, text: &str) {
+        match serde_json::from_str(text) {
+            Ok(frame) => self.on_frame(frame),
+            Err(e) => eprintln!("[Control] Bad message: {}", e),
+        }
+    }
+
+    fn send(&self, frame: &Frame) -> Result<(), String> {
+        let text = serde_json::to_string(frame).map_err(|e| e.to_string())?;
+        let mut channel = self.channel.lock().unwrap();
+        if let Some(tx) = channel.as_ref() {
+            if tx.send(text.clone()).is_ok() {
+                return Ok(());
+            }
+            *channel = None;
+        }
+        drop(channel);
+
+        let message = serde_json::json!({ "type": "control", "frame": frame, "target": "phone" });
+        match self.signaling.lock().unwrap().as_ref() {
+            Some(tx) if tx.send(message.to_string()).is_ok() => Ok(()),
+            _ => Err("No phone connected".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sent_frame(text: &str) -> Frame {
+        serde_json::from_str(text).unwrap()
+    }
+
+    #[tokio::test]
+    async fn responses_are_matched_to_requests_by_id() {
+        let link = std::sync::Arc::new(ControlLink::new());
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        link.set_channel(tx);
+
+        let torch = tokio::spawn({
+            let link = link.clone();
+            async move { link.request::<()>(ControlRequest::SetTorch { on: true }).await }
+        });
+        let switch = tokio::spawn({
+            let link = link.clone();
+            async move { link.request::<CameraState>(ControlRequest::SwitchCamera { facing: None }).await }
+        });
+
+        let mut ids = HashMap::new();
+        for _ in 0..2 {
+            let Frame::Request { id, request } = sent_frame(&rx.recv().await.unwrap()) else { panic!() };
+            ids.insert(matches!(request, ControlRequest::SetTorch { .. }), id);
+        }
+        // Answered out of order
+        link.on_text(&format!(
+            r#"{{"kind":"response","id":{},"result":{{"capabilities":{{"zoom":{{"min":1,"max":8}}}},"settings":{{"facing":"user"}}}}}}"#,
+            ids[&false]
+        ));
+        link.on_text(&format!(r#"{{"kind":"response","id":{},"error":"No torch"}}"#, ids[&true]));
+
+        let state = switch.await.unwrap().unwrap();
+        assert_eq!(state.settings.facing, Some(Facing::User));
+        assert_eq!(state.capabilities.zoom, Some(Range { min: 1.0, max: 8.0, step: None }));
+        assert!(!state.capabilities.torch);
+        assert_eq!(torch.await.unwrap(), Err("No torch".to_string()));
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_signaling_without_a_channel() {
+        let link = std::sync::Arc::new(ControlLink::new());
+        assert!(link.request::<()>(ControlRequest::SetTorch { on: false }).await.is_err());
+
+        let (channel, closed) = mpsc::unbounded_channel();
+        link.set_channel(channel);
+        drop(closed);
+        let (signaling, mut sent) = mpsc::unbounded_channel();
+        link.set_signaling(Some(signaling));
+
+        let request = tokio::spawn({
+            let link = link.clone();
+            async move { link.request::<()>(ControlRequest::SetZoom { zoom: 2.0 }).await }
+        });
+        let message: Value = serde_json::from_str(&sent.recv().await.unwrap()).unwrap();
+        assert_eq!(message["type"], "control");
+        assert_eq!(message["frame"]["request"], serde_json::json!({ "type": "setZoom", "zoom": 2.0 }));
+        let id = message["frame"]["id"].as_u64().unwrap();
+        link.on_frame(Frame::Response { id, result: None, error: None });
+        assert_eq!(request.await.unwrap(), Ok(()));
+    }
+
+    #[test]
+    fn events_go_to_the_event_sink() {
+        let link = ControlLink::new();
+        link.on_text(r#"{"kind":"event","event":{"type":"telemetry","streaming":true}}"#); // no sink yet
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        link.set_events(tx);
+        link.on_text(r#"{"kind":"event","event":{"type":"telemetry","battery":{"level":0.5,"charging":true},"captureFps":30}}"#);
+        let PhoneEvent::Telemetry(report) = rx.try_recv().unwrap();
+        assert!(!report.streaming);
+        assert_eq!(report.battery.map(|b| b.level), Some(0.5));
+        assert_eq!(report.capture_fps, Some(30.0));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn requests_serialize_in_the_phone_format() {
+        let frame = Frame::Request { id: 3, request: ControlRequest::SetQuality { max_bitrate_kbps: 2500 } };
+        assert_eq!(
+            serde_json::to_value(&frame).unwrap(),
+            serde_json::json!({ "kind": "request", "id": 3, "request": { "type": "setQuality", "maxBitrateKbps": 2500 } })
+        );
+        let focus = ControlRequest::SetFocusMode { mode: FocusMode::SingleShot };
+        assert_eq!(serde_json::to_value(&focus).unwrap()["mode"], "single-shot");
+    }
+}
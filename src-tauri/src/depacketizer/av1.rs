@@ -0,0 +1,340 @@
+use super::{Codec, Depacketizer};
+use crate::frame_pool::FramePool;
+use crate::frame_timing::FrameTiming;
+use crate::h264_syntax::BitReader;
+use crate::webrtc_client::VideoFrame;
+
+// OBU types (AV1 spec section 6.2.2)
+const OBU_SEQUENCE_HEADER: u8 = 1;
+const OBU_TEMPORAL_DELIMITER: u8 = 2;
+const OBU_TILE_LIST: u8 = 8;
+
+/// RTP -> AV1 temporal unit depacketizer (AV1 RTP payload format, section 4).
+///
+/// OBU elements are reassembled across packets (Z/Y bits) and rewritten into the
+/// low-overhead bitstream format, i.e. with `obu_size` fields, behind a temporal
+/// delimiter. A temporal unit carrying a sequence header, or the first packet of a
+/// new coded video sequence (N bit), is a keyframe; the sequence header also gives
+/// the resolution.
+pub struct Av1Depacketizer {
+    current_timestamp: u32,
+    pool: FramePool,
+    /// Temporal unit being assembled, starting with its temporal delimiter
+    frame_buffer: Vec<u8>,
+    /// Partial OBU continued in the next packet (Y bit)
+    obu_buffer: Vec<u8>,
+    /// The current OBU fragment chain is broken; drop until a new OBU starts
+    obu_lost: bool,
+    keyframe: bool,
+    width: u32,
+    height: u32,
+    /// Frames finished by a timestamp change because their marker packet never arrived
+    partial_frames: u64,
+    /// OBU fragments dropped because an earlier fragment was missing
+    orphan_fragments: u64,
+}
+
+impl Default for Av1Depacketizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn read_leb128(data: &[u8]) -> Option<(usize, usize)> {
+    let mut value = 0usize;
+    for (i, &b) in data.iter().take(8).enumerate() {
+        value |= ((b & 0x7F) as usize) << (i * 7);
+        if b & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+fn write_leb128(out: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// uvlc() from the AV1 spec (4.10.3)
+fn read_uvlc(r: &mut BitReader) -> Option<u32> {
+    let mut leading_zeros = 0;
+    while !r.read_bit()? {
+        leading_zeros += 1;
+        if leading_zeros >= 32 {
+            return Some(u32::MAX);
+        }
+    }
+    r.read_bits(leading_zeros)
+}
+
+/// `max_frame_width_minus_1 + 1` / `max_frame_height_minus_1 + 1` from a sequence header OBU payload.
+fn sequence_header_resolution(payload: &[u8]) -> Option<(u32, u32)> {
+    let mut r = BitReader::new(payload);
+    r.skip(3)?; // seq_profile
+    r.skip(1)?; // still_picture
+    let reduced_still_picture_header = r.read_bit()?;
+
+    if reduced_still_picture_header {
+        r.skip(5)?; // seq_level_idx[0]
+    } else {
+        let mut buffer_delay_length = 0;
+        let timing_info_present = r.read_bit()?;
+        let mut decoder_model_info_present = false;
+        if timing_info_present {
+            r.skip(64)?; // num_units_in_display_tick, time_scale
+            if r.read_bit()? {
+                read_uvlc(&mut r)?; // num_ticks_per_picture_minus_1
+            }
+            decoder_model_info_present = r.read_bit()?;
+            if decoder_model_info_present {
+                buffer_delay_length = r.read_bits(5)? + 1;
+                r.skip(32 + 5 + 5)?; // num_units_in_decoding_tick, removal/presentation time lengths
+            }
+        }
+        let initial_display_delay_present = r.read_bit()?;
+        let operating_points = r.read_bits(5)? + 1;
+        for _ in 0..operating_points {
+            r.skip(12)?; // operating_point_idc
+            let seq_level_idx = r.read_bits(5)?;
+            if seq_level_idx > 7 {
+                r.skip(1)?; // seq_tier
+            }
+            if decoder_model_info_present && r.read_bit()? {
+                // decoder_buffer_delay, encoder_buffer_delay, low_delay_mode_flag
+                r.skip(2 * buffer_delay_length as usize + 1)?;
+            }
+            if initial_display_delay_present && r.read_bit()? {
+                r.skip(4)?; // initial_display_delay_minus_1
+            }
+        }
+    }
+
+    let width_bits = r.read_bits(4)? + 1;
+    let height_bits = r.read_bits(4)? + 1;
+    let width = r.read_bits(width_bits)? + 1;
+    let height = r.read_bits(height_bits)? + 1;
+    Some((width, height))
+}
+
+impl Av1Depacketizer {
+    pub fn new() -> Self {
+        let pool = FramePool::new(100 * 1024);
+        Self {
+            current_timestamp: 0,
+            frame_buffer: pool.take(),
+            pool,
+            obu_buffer: Vec::new(),
+            obu_lost: false,
+            keyframe: false,
+            width: 0,
+            height: 0,
+            partial_frames: 0,
+            orphan_fragments: 0,
+        }
+    }
+
+    fn take_frame(&mut self) -> Option<VideoFrame> {
+        self.obu_buffer.clear();
+        let keyframe = std::mem::replace(&mut self.keyframe, false);
+        if self.frame_buffer.is_empty() {
+            return None;
+        }
+
+        let data = std::mem::replace(&mut self.frame_buffer, self.pool.take());
+        Some(VideoFrame {
+            data: self.pool.freeze(data),
+            width: self.width,
+            height: self.height,
+            frame_rate: None,
+            codec: Codec::Av1,
+            keyframe,
+            timing: FrameTiming::new(self.current_timestamp),
+        })
+    }
+
+    /// Append one complete OBU (as sent, without `obu_size`) with its size field set.
+    fn push_obu(&mut self, obu: &[u8]) {
+        let Some(&header) = obu.first() else { return };
+        let obu_type = (header >> 3) & 0x0F;
+        if matches!(obu_type, OBU_TEMPORAL_DELIMITER | OBU_TILE_LIST) {
+            return;
+        }
+        let header_len = if header & 0x04 != 0 { 2 } else { 1 };
+        if obu.len() < header_len {
+            return;
+        }
+
+        // The element may already carry obu_size; strip it so it's written once
+        let mut body = &obu[header_len..];
+        if header & 0x02 != 0 {
+            let Some((size, len)) = read_leb128(body) else { return };
+            body = body.get(len..len + size).unwrap_or(&body[len..]);
+        }
+
+        if obu_type == OBU_SEQUENCE_HEADER {
+            self.keyframe = true;
+            if let Some((width, height)) = sequence_header_resolution(body) {
+                self.width = width;
+                self.height = height;
+            }
+        }
+
+        if self.frame_buffer.is_empty() {
+            // Temporal delimiter first, as a decoder reading a plain OBU stream expects
+            self.frame_buffer.extend_from_slice(&[OBU_TEMPORAL_DELIMITER << 3 | 0x02, 0x00]);
+        }
+        self.frame_buffer.push(header | 0x02);
+        self.frame_buffer.extend_from_slice(&obu[1..header_len]);
+        write_leb128(&mut self.frame_buffer, body.len());
+        self.frame_buffer.extend_from_slice(body);
+    }
+
+    fn depacketize(&mut self, payload: &[u8]) {
+        let Some((&aggregation, mut body)) = payload.split_first() else { return };
+        let continues_previous = aggregation & 0x80 != 0;
+        let continues_next = aggregation & 0x40 != 0;
+        let element_count = ((aggregation >> 4) & 0x03) as usize;
+        if aggregation & 0x08 != 0 {
+            self.keyframe = true; // first packet of a coded video sequence
+        }
+
+        let mut index = 0;
+        while !body.is_empty() {
+            index += 1;
+            // With W set, the last element has no length field and runs to the end
+            let element = if element_count != 0 && index == element_count {
+                std::mem::take(&mut body)
+            } else {
+                let Some((len, n)) = read_leb128(body) else { return };
+                let Some(element) = body.get(n..n + len) else { return };
+                body = &body[n + len..];
+                element
+            };
+
+            let first = index == 1;
+            let last = body.is_empty();
+            if first && continues_previous {
+                if self.obu_buffer.is_empty() || self.obu_lost {
+                    self.orphan_fragments += 1;
+                    if self.orphan_fragments == 1 || self.orphan_fragments.is_multiple_of(100) {
+                        println!(
+                            "[AV1] Dropping OBU fragment ts={} without its start ({} so far)",
+                            self.current_timestamp, self.orphan_fragments
+                        );
+                    }
+                    self.obu_lost = true;
+                } else {
+                    self.obu_buffer.extend_from_slice(element);
+                }
+            } else {
+                self.obu_lost = false;
+                self.obu_buffer.clear();
+                self.obu_buffer.extend_from_slice(element);
+            }
+
+            if !(last && continues_next) {
+                if !self.obu_lost {
+                    let obu = std::mem::take(&mut self.obu_buffer);
+                    self.push_obu(&obu);
+                    self.obu_buffer = obu;
+                }
+                self.obu_buffer.clear();
+                self.obu_lost = false;
+            }
+        }
+    }
+}
+
+impl Depacketizer for Av1Depacketizer {
+    fn push(&mut self, payload: &[u8], timestamp: u32, marker: bool) -> Vec<VideoFrame> {
+        let mut completed = Vec::new();
+
+        if self.current_timestamp != timestamp {
+            if !self.frame_buffer.is_empty() {
+                self.partial_frames += 1;
+                if self.partial_frames == 1 || self.partial_frames.is_multiple_of(100) {
+                    println!(
+                        "[AV1] Temporal unit ts={} finished without marker bit ({} bytes, {} so far)",
+                        self.current_timestamp,
+                        self.frame_buffer.len(),
+                        self.partial_frames
+                    );
+                }
+                completed.extend(self.take_frame());
+            }
+            self.current_timestamp = timestamp;
+            // A fragment can't span temporal units, so a pending one was cut short
+            self.obu_buffer.clear();
+            self.obu_lost = false;
+        }
+
+        self.depacketize(payload);
+
+        if marker {
+            completed.extend(self.take_frame());
+        }
+
+        completed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Sequence header for 1280x720, no timing info, one operating point at level 4.0;
+    // cut off after the fields the depacketizer reads
+    const SEQ_HEADER: [u8; 1 + 8] = [
+        0x08, // OBU_SEQUENCE_HEADER, no extension, no size
+        0x00, 0x00, 0x00, 0x42, 0xa6, 0x7f, 0xd9, 0xe0,
+    ];
+
+    #[test]
+    fn sequence_header_gives_resolution() {
+        assert_eq!(sequence_header_resolution(&SEQ_HEADER[1..]), Some((1280, 720)));
+    }
+
+    #[test]
+    fn keyframe_temporal_unit_gets_sizes_and_delimiter() {
+        let mut d = Av1Depacketizer::new();
+        // W=2, N=1: sequence header with a length, then a frame OBU to the end
+        let mut packet = vec![0x28, SEQ_HEADER.len() as u8];
+        packet.extend_from_slice(&SEQ_HEADER);
+        packet.extend_from_slice(&[0x30, 0x10, 0x20]);
+        let frames = d.push(&packet, 3000, true);
+
+        assert_eq!(frames.len(), 1);
+        let frame = &frames[0];
+        assert!(frame.keyframe);
+        assert_eq!((frame.width, frame.height), (1280, 720));
+
+        let mut expected = vec![0x12, 0x00, 0x0a, 0x08];
+        expected.extend_from_slice(&SEQ_HEADER[1..]);
+        expected.extend_from_slice(&[0x32, 0x02, 0x10, 0x20]);
+        assert_eq!(frame.data, expected);
+    }
+
+    #[test]
+    fn obu_fragmented_across_packets() {
+        let mut d = Av1Depacketizer::new();
+        assert!(d.push(&[0x50, 0x30, 0x01, 0x02], 6000, false).is_empty()); // Y=1, W=1
+        let frames = d.push(&[0x90, 0x03, 0x04], 6000, true); // Z=1, W=1
+        assert!(!frames[0].keyframe);
+        assert_eq!(frames[0].data, vec![0x12, 0x00, 0x32, 0x04, 0x01, 0x02, 0x03, 0x04]);
+    }
+
+    #[test]
+    fn continuation_without_start_is_dropped() {
+        let mut d = Av1Depacketizer::new();
+        let frames = d.push(&[0x90, 0x03, 0x04], 6000, true);
+        assert!(frames.is_empty());
+    }
+}
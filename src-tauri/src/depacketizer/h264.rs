@@ -0,0 +1,563 @@
+use base64::Engine;
+
+use super::{Codec, Depacketizer, START_CODE};
+use crate::frame_pool::FramePool;
+use crate::frame_timing::FrameTiming;
+use crate::h264_syntax::{Sps, NAL_IDR, NAL_PPS, NAL_SPS};
+use crate::webrtc_client::VideoFrame;
+
+// RTP payload types beyond single NAL units (RFC 6184 section 5.2)
+const STAP_A: u8 = 24;
+const STAP_B: u8 = 25;
+const MTAP16: u8 = 26;
+const MTAP24: u8 = 27;
+const FU_A: u8 = 28;
+const FU_B: u8 = 29;
+
+/// RTP -> Annex-B depacketizer for H.264 (RFC 6184, all packetization modes).
+///
+/// Access units are finished by the RTP marker bit, with a timestamp change as a
+/// fallback. The most recent SPS/PPS are cached (seeded from `sprop-parameter-sets`
+/// when the SDP has them) and written ahead of the IDR slice of any access unit that
+/// lacks them, so a decoder can join mid-stream. The cached SPS also gives every frame its size and
+/// frame rate. Interleaved-mode NAL units (STAP-B, MTAP, FU-B) are
+/// put back in decoding order by DON within each access unit. MTAP units with a
+/// non-zero timestamp offset belong to another access unit and are dropped.
+pub struct H264Depacketizer {
+    current_timestamp: u32,
+    pool: FramePool,
+    frame_buffer: Vec<u8>,
+    /// NAL units carrying a decoding order number, flushed sorted at the end of the AU
+    interleaved: Vec<(u16, Vec<u8>)>,
+    fu_buffer: Vec<u8>,
+    fu_don: Option<u16>,
+    in_fu: bool,
+    sps: Option<Vec<u8>>,
+    pps: Option<Vec<u8>>,
+    /// Size and frame rate from the cached SPS
+    width: u32,
+    height: u32,
+    frame_rate: Option<(u32, u32)>,
+    au_has_idr: bool,
+    au_has_sps: bool,
+    au_has_pps: bool,
+    /// Frames finished by a timestamp change because their marker packet never arrived
+    partial_frames: u64,
+    /// NAL units dropped for a set forbidden bit or an invalid NRI
+    malformed_nals: u64,
+    /// MTAP units dropped for a non-zero timestamp offset
+    offset_nals: u64,
+}
+
+impl Default for H264Depacketizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl H264Depacketizer {
+    pub fn new() -> Self {
+        let pool = FramePool::new(100 * 1024); // 100KB prealloc
+        Self {
+            current_timestamp: 0,
+            frame_buffer: pool.take(),
+            pool,
+            interleaved: Vec::new(),
+            fu_buffer: Vec::with_capacity(50 * 1024),
+            fu_don: None,
+            in_fu: false,
+            sps: None,
+            pps: None,
+            width: 0,
+            height: 0,
+            frame_rate: None,
+            au_has_idr: false,
+            au_has_sps: false,
+            au_has_pps: false,
+            partial_frames: 0,
+            malformed_nals: 0,
+            offset_nals: 0,
+        }
+    }
+
+    /// Seed the parameter set cache from an SDP fmtp line, e.g.
+    /// `profile-level-id=42e01f;packetization-mode=1;sprop-parameter-sets=Z0LgH9oFB+Q=,aM4G4g==`
+    pub fn set_sprop_parameter_sets(&mut self, fmtp: &str) {
+        let Some(sets) = fmtp
+            .split(';')
+            .filter_map(|kv| kv.trim().split_once('='))
+            .find(|(k, _)| k.trim() == "sprop-parameter-sets")
+            .map(|(_, v)| v)
+        else {
+            return;
+        };
+
+        for set in sets.split(',') {
+            match base64::engine::general_purpose::STANDARD.decode(set.trim()) {
+                Ok(nal) => match nal.first().map(|h| h & 0x1F) {
+                    Some(NAL_SPS) => self.set_sps(nal),
+                    Some(NAL_PPS) => self.pps = Some(nal),
+                    _ => {}
+                },
+                Err(e) => eprintln!("[H264] Bad sprop-parameter-sets entry {:?}: {}", set, e),
+            }
+        }
+    }
+
+    fn set_sps(&mut self, nal: Vec<u8>) {
+        match Sps::parse(&nal) {
+            Some(sps) => {
+                self.width = sps.width;
+                self.height = sps.height;
+                self.frame_rate = sps.frame_rate;
+            }
+            None => println!("[H264] Could not parse SPS ({} bytes), keeping previous size", nal.len()),
+        }
+        self.sps = Some(nal);
+    }
+
+    fn has_pending(&self) -> bool {
+        !self.frame_buffer.is_empty() || !self.interleaved.is_empty()
+    }
+
+    fn reset_fu(&mut self) {
+        self.in_fu = false;
+        self.fu_don = None;
+        self.fu_buffer.clear();
+    }
+
+    fn take_frame(&mut self) -> VideoFrame {
+        if !self.interleaved.is_empty() {
+            let base = self.interleaved[0].0;
+            self.interleaved.sort_by_key(|(don, _)| don.wrapping_sub(base) as i16);
+            for (_, nal) in std::mem::take(&mut self.interleaved) {
+                self.frame_buffer.extend_from_slice(&START_CODE);
+                self.frame_buffer.extend_from_slice(&nal);
+            }
+        }
+
+        let data = std::mem::replace(&mut self.frame_buffer, self.pool.take());
+        self.au_has_sps = false;
+        self.au_has_pps = false;
+
+        VideoFrame {
+            data: self.pool.freeze(data),
+            width: self.width,
+            height: self.height,
+            frame_rate: self.frame_rate,
+            codec: Codec::H264,
+            keyframe: std::mem::take(&mut self.au_has_idr),
+            timing: FrameTiming::new(self.current_timestamp),
+        }
+    }
+
+    /// Validate one complete NAL unit, update the parameter set cache and append it
+    /// to the access unit (in arrival order, or by DON for interleaved mode).
+    fn push_nal(&mut self, nal: &[u8], don: Option<u16>) {
+        let Some(&header) = nal.first() else { return };
+        let nal_type = header & 0x1F;
+        let nri = (header >> 5) & 0x03;
+
+        // forbidden_zero_bit set, or a zero NRI on a unit that must be a reference
+        if header & 0x80 != 0 || (nri == 0 && matches!(nal_type, NAL_IDR | NAL_SPS | NAL_PPS)) {
+            self.malformed_nals += 1;
+            if self.malformed_nals == 1 || self.malformed_nals.is_multiple_of(100) {
+                println!(
+                    "[H264] Dropping malformed NAL (header={:#04x}, {} so far)",
+                    header, self.malformed_nals
+                );
+            }
+            return;
+        }
+
+        match nal_type {
+            NAL_SPS => {
+                if self.sps.as_deref() != Some(nal) {
+                    self.set_sps(nal.to_vec());
+                }
+                self.au_has_sps = true;
+            }
+            NAL_PPS => {
+                self.pps = Some(nal.to_vec());
+                self.au_has_pps = true;
+            }
+            NAL_IDR if !self.au_has_idr => {
+                self.au_has_idr = true;
+                // Make every IDR self-contained so a decoder can start here. Written
+                // now rather than in front of the finished access unit, which would
+                // move the whole picture.
+                if let (false, Some(sps)) = (self.au_has_sps, &self.sps) {
+                    self.frame_buffer.extend_from_slice(&START_CODE);
+                    self.frame_buffer.extend_from_slice(sps);
+                }
+                if let (false, Some(pps)) = (self.au_has_pps, &self.pps) {
+                    self.frame_buffer.extend_from_slice(&START_CODE);
+                    self.frame_buffer.extend_from_slice(pps);
+                }
+                self.au_has_sps = true;
+                self.au_has_pps = true;
+            }
+            _ => {}
+        }
+
+        match don {
+            Some(don) => self.interleaved.push((don, nal.to_vec())),
+            None => {
+                self.frame_buffer.extend_from_slice(&START_CODE);
+                self.frame_buffer.extend_from_slice(nal);
+            }
+        }
+    }
+
+    fn depacketize(&mut self, payload: &[u8]) {
+        let Some(&header) = payload.first() else { return };
+
+        match header & 0x1F {
+            STAP_A => self.aggregation(&payload[1..], None),
+            STAP_B => {
+                if payload.len() < 3 { return; }
+                let don = u16::from_be_bytes([payload[1], payload[2]]);
+                self.aggregation(&payload[3..], Some(don));
+            }
+            MTAP16 => self.multi_time_aggregation(payload, 2),
+            MTAP24 => self.multi_time_aggregation(payload, 3),
+            FU_A | FU_B => self.fragment(payload),
+            0 | 30 | 31 => {} // reserved / undefined
+            _ => self.push_nal(payload, None),
+        }
+    }
+
+    /// STAP-A / STAP-B body: repeated (16-bit size, NAL unit). In STAP-B each
+    /// subsequent unit's DON is one more than the previous.
+    fn aggregation(&mut self, mut body: &[u8], mut don: Option<u16>) {
+        while body.len() >= 2 {
+            let len = u16::from_be_bytes([body[0], body[1]]) as usize;
+            body = &body[2..];
+            if len == 0 || len > body.len() { break; }
+
+            self.push_nal(&body[..len], don);
+            don = don.map(|d| d.wrapping_add(1));
+            body = &body[len..];
+        }
+    }
+
+    /// MTAP16 / MTAP24: DONB, then units of (16-bit size, DOND, TS offset, NAL unit),
+    /// where the size covers DOND and the TS offset as well as the NAL unit. Only units
+    /// of the packet's own access unit (offset 0) are kept: we assemble one access unit
+    /// at a time, so units of another can't be placed.
+    fn multi_time_aggregation(&mut self, payload: &[u8], ts_offset_len: usize) {
+        if payload.len() < 3 { return; }
+        let donb = u16::from_be_bytes([payload[1], payload[2]]);
+        let mut body = &payload[3..];
+
+        while body.len() >= 2 {
+            let len = u16::from_be_bytes([body[0], body[1]]) as usize;
+            body = &body[2..];
+            if len <= 1 + ts_offset_len || len > body.len() { break; }
+
+            let don = donb.wrapping_add(body[0] as u16);
+            if body[1..1 + ts_offset_len].iter().any(|&b| b != 0) {
+                self.offset_nals += 1;
+                if self.offset_nals == 1 || self.offset_nals.is_multiple_of(100) {
+                    println!(
+                        "[H264] Dropping MTAP unit of another access unit ({} so far)",
+                        self.offset_nals
+                    );
+                }
+            } else {
+                self.push_nal(&body[1 + ts_offset_len..len], Some(don));
+            }
+            body = &body[len..];
+        }
+    }
+
+    /// FU-A / FU-B: FU indicator, FU header, (FU-B only: DON), fragment.
+    fn fragment(&mut self, payload: &[u8]) {
+        if payload.len() < 2 { return; }
+        let indicator = payload[0];
+        let fu_header = payload[1];
+        let s_bit = (fu_header & 0x80) != 0;
+        let e_bit = (fu_header & 0x40) != 0;
+
+        let mut data = &payload[2..];
+        if indicator & 0x1F == FU_B {
+            // FU-B is only valid as the first fragment and carries the DON
+            if !s_bit || data.len() < 2 { return; }
+            self.fu_don = Some(u16::from_be_bytes([data[0], data[1]]));
+            data = &data[2..];
+        }
+
+        if s_bit {
+            if self.in_fu {
+                println!("[H264] FU start before previous FU ended (ts={})", self.current_timestamp);
+            }
+            self.in_fu = true;
+            self.fu_buffer.clear();
+            // Reconstruct the NAL header: F and NRI from the indicator, type from the FU header
+            self.fu_buffer.push((indicator & 0xE0) | (fu_header & 0x1F));
+            if indicator & 0x1F == FU_A {
+                self.fu_don = None;
+            }
+        } else if !self.in_fu {
+            return; // start fragment was lost
+        }
+
+        self.fu_buffer.extend_from_slice(data);
+
+        if e_bit {
+            let nal = std::mem::take(&mut self.fu_buffer);
+            let don = self.fu_don.take();
+            self.in_fu = false;
+            self.push_nal(&nal, don);
+            self.fu_buffer = nal;
+            self.fu_buffer.clear();
+        }
+    }
+}
+
+impl Depacketizer for H264Depacketizer {
+    /// Returns the access units completed: usually none or one, but a lost marker
+    /// packet means the previous (partial) one is returned too.
+    fn push(&mut self, payload: &[u8], timestamp: u32, marker: bool) -> Vec<VideoFrame> {
+        let mut completed = Vec::new();
+
+        if self.current_timestamp != timestamp {
+            if self.has_pending() {
+                self.partial_frames += 1;
+                if self.partial_frames == 1 || self.partial_frames.is_multiple_of(100) {
+                    println!(
+                        "[H264] Frame ts={} finished without marker bit ({} bytes{}, {} so far)",
+                        self.current_timestamp,
+                        self.frame_buffer.len(),
+                        if self.in_fu { ", FU cut short" } else { "" },
+                        self.partial_frames
+                    );
+                }
+                completed.push(self.take_frame());
+            }
+            self.current_timestamp = timestamp;
+            // Packets arrive in sequence order (the jitter buffer reorders them),
+            // so a new timestamp means any unfinished FU belongs to a lost fragment
+            self.reset_fu();
+        }
+
+        self.depacketize(payload);
+
+        if marker && self.has_pending() {
+            if self.in_fu {
+                println!("[H264] Marker bit inside an unfinished FU (ts={})", timestamp);
+                self.reset_fu();
+            }
+            completed.push(self.take_frame());
+        }
+
+        completed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Synthetic payloads: real 640x480 constrained baseline parameter sets, placeholder
+    // slice data
+    const SPS: [u8; 9] = [0x67, 0x42, 0xc0, 0x1e, 0xda, 0x02, 0x80, 0xf6, 0x84];
+    const PPS: [u8; 4] = [0x68, 0xce, 0x3c, 0x80];
+
+    /// RTP packets, each prefixed with its u16 length: a decodable 32x32 stream (an IDR of
+    /// four I_PCM macroblocks, then an all-skip P picture) as webrtc-rs's `H264Payloader`
+    /// sends it at a 1200-byte MTU. SPS and PPS go in a STAP-A, the IDR in two FU-A
+    /// fragments and the P slice in a single NAL unit packet.
+    const PACKETS: &[u8] = include_bytes!("testdata/h264_32x32.rtp");
+
+    /// (payload, timestamp, marker) of each packet in `PACKETS`
+    fn packets() -> Vec<(&'static [u8], u32, bool)> {
+        let mut packets = Vec::new();
+        let mut rest = PACKETS;
+        while let [hi, lo, tail @ ..] = rest {
+            let (packet, next) = tail.split_at(u16::from_be_bytes([*hi, *lo]) as usize);
+            let timestamp = u32::from_be_bytes(packet[4..8].try_into().unwrap());
+            packets.push((&packet[12..], timestamp, packet[1] & 0x80 != 0));
+            rest = next;
+        }
+        packets
+    }
+
+    fn stap_a(nals: &[&[u8]]) -> Vec<u8> {
+        let mut p = vec![0x78]; // F=0, NRI=3, type 24
+        for nal in nals {
+            p.extend_from_slice(&(nal.len() as u16).to_be_bytes());
+            p.extend_from_slice(nal);
+        }
+        p
+    }
+
+    fn nals(frame: &VideoFrame) -> Vec<Vec<u8>> {
+        crate::h264_syntax::nal_units(&frame.data).map(|n| n.to_vec()).collect()
+    }
+
+    #[test]
+    fn stap_a_then_fu_a_idr() {
+        let mut d = H264Depacketizer::new();
+        assert!(d.push(&stap_a(&[&SPS, &PPS]), 3000, false).is_empty());
+        // IDR split over three FU-A fragments
+        assert!(d.push(&[0x7c, 0x85, 0x88, 0x84, 0x00], 3000, false).is_empty());
+        assert!(d.push(&[0x7c, 0x05, 0x33, 0xff], 3000, false).is_empty());
+        let frames = d.push(&[0x7c, 0x45, 0x21, 0x10], 3000, true);
+
+        assert_eq!(frames.len(), 1);
+        assert_eq!(
+            nals(&frames[0]),
+            vec![SPS.to_vec(), PPS.to_vec(), vec![0x65, 0x88, 0x84, 0x00, 0x33, 0xff, 0x21, 0x10]]
+        );
+    }
+
+    #[test]
+    fn packetized_stream_reassembles_into_decodable_access_units() {
+        let packets = packets();
+        let types: Vec<u8> = packets.iter().map(|(payload, ..)| payload[0] & 0x1f).collect();
+        assert_eq!(types, [STAP_A, FU_A, FU_A, 1]);
+
+        let mut d = H264Depacketizer::new();
+        let frames: Vec<VideoFrame> =
+            packets.into_iter().flat_map(|(payload, ts, marker)| d.push(payload, ts, marker)).collect();
+        assert_eq!(frames.len(), 2);
+
+        let idr = nals(&frames[0]);
+        assert_eq!(idr.iter().map(Vec::len).collect::<Vec<_>>(), [7, 4, 1548]);
+        assert!(frames[0].keyframe);
+        assert_eq!((frames[0].width, frames[0].height), (32, 32));
+        // The fragments joined back into the IDR up to its trailing bits
+        let sps = Sps::parse(&idr[0]).unwrap();
+        let header = crate::h264_syntax::SliceHeader::parse(&idr[2], &sps).unwrap();
+        assert_eq!((header.first_mb_in_slice, header.frame_num), (0, 0));
+        assert_eq!(idr[2].last(), Some(&0x80));
+
+        assert_eq!(nals(&frames[1]), vec![vec![0x41, 0x9a, 0x22, 0x8b]]);
+        assert!(!frames[1].keyframe);
+        assert_eq!(frames[1].timing.rtp_timestamp.wrapping_sub(frames[0].timing.rtp_timestamp), 3000);
+    }
+
+    #[test]
+    fn stap_a_length_ending_exactly_at_payload_end() {
+        let mut d = H264Depacketizer::new();
+        let frames = d.push(&stap_a(&[&[0x06, 0x05, 0x01], &[0x41, 0x9a]]), 90, true);
+        assert_eq!(nals(&frames[0]), vec![vec![0x06, 0x05, 0x01], vec![0x41, 0x9a]]);
+    }
+
+    #[test]
+    fn idr_gets_cached_parameter_sets() {
+        let mut d = H264Depacketizer::new();
+        d.push(&stap_a(&[&SPS, &PPS]), 0, false);
+        d.push(&[0x65, 0x88, 0x80], 0, true);
+        d.push(&[0x41, 0x9a, 0x02], 3000, true);
+
+        // Later IDR without in-band SPS/PPS (e.g. after a PLI)
+        let frames = d.push(&[0x65, 0x88, 0x81], 6000, true);
+        assert_eq!(nals(&frames[0]), vec![SPS.to_vec(), PPS.to_vec(), vec![0x65, 0x88, 0x81]]);
+    }
+
+    #[test]
+    fn sprop_parameter_sets_seed_the_cache() {
+        let mut d = H264Depacketizer::new();
+        d.set_sprop_parameter_sets(
+            "level-asymmetry-allowed=1;packetization-mode=1;profile-level-id=42e01f;sprop-parameter-sets=Z0LAHtoCgPaE,aM48gA==",
+        );
+        let frames = d.push(&[0x65, 0x88, 0x80], 0, true);
+        assert_eq!(nals(&frames[0]), vec![SPS.to_vec(), PPS.to_vec(), vec![0x65, 0x88, 0x80]]);
+    }
+
+    #[test]
+    fn sps_gives_cropped_size_and_frame_rate() {
+        // High profile 1920x1088 cropped to 1080, VUI timing for 30 fps
+        let sps = [
+            0x67, 0x64, 0x00, 0x28, 0xac, 0xda, 0x01, 0xe0, 0x08, 0x9f, 0x97, 0x01, 0x6a, 0x02, 0x02,
+            0x02, 0x80, 0x00, 0x00, 0x03, 0x00, 0x80, 0x00, 0x00, 0x1e, 0x60,
+        ];
+        let mut d = H264Depacketizer::new();
+        d.push(&stap_a(&[&sps, &PPS]), 0, false);
+        let frames = d.push(&[0x65, 0x88, 0x80], 0, true);
+        assert_eq!((frames[0].width, frames[0].height), (1920, 1080));
+        assert_eq!(frames[0].frame_rate, Some((60, 2)));
+
+        // Later frames keep the size without an in-band SPS
+        let frames = d.push(&[0x41, 0x9a, 0x02], 3000, true);
+        assert_eq!((frames[0].width, frames[0].height), (1920, 1080));
+
+        // A new SPS mid-stream switches the size
+        d.push(&stap_a(&[&SPS, &PPS]), 6000, false);
+        let frames = d.push(&[0x65, 0x88, 0x81], 6000, true);
+        assert_eq!((frames[0].width, frames[0].height), (640, 480));
+    }
+
+    #[test]
+    fn timestamp_change_finishes_frame_without_marker() {
+        let mut d = H264Depacketizer::new();
+        assert!(d.push(&[0x41, 0x9a, 0x01], 3000, false).is_empty());
+        let frames = d.push(&[0x41, 0x9a, 0x02], 6000, true);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(nals(&frames[0]), vec![vec![0x41, 0x9a, 0x01]]);
+        assert_eq!(nals(&frames[1]), vec![vec![0x41, 0x9a, 0x02]]);
+    }
+
+    #[test]
+    fn fu_a_missing_start_is_dropped() {
+        let mut d = H264Depacketizer::new();
+        d.push(&[0x5c, 0x01, 0xaa], 3000, false); // middle fragment, start lost
+        let frames = d.push(&[0x5c, 0x41, 0xbb], 3000, true);
+        assert!(frames.is_empty());
+    }
+
+    #[test]
+    fn forbidden_bit_and_zero_nri_are_rejected() {
+        let mut d = H264Depacketizer::new();
+        d.push(&[0xc1, 0x9a], 0, false); // F=1
+        d.push(&[0x05, 0x88], 0, false); // IDR with NRI=0
+        let frames = d.push(&[0x41, 0x9a], 0, true);
+        assert_eq!(nals(&frames[0]), vec![vec![0x41, 0x9a]]);
+    }
+
+    #[test]
+    fn interleaved_units_are_ordered_by_don() {
+        let mut d = H264Depacketizer::new();
+        // MTAP16 with DONB=10: units with DOND 2 and 0, TS offset 0
+        let mut mtap = vec![0x7a, 0x00, 0x0a];
+        for (dond, nal) in [(2u8, [0x41, 0x12]), (0, [0x41, 0x10])] {
+            mtap.extend_from_slice(&5u16.to_be_bytes());
+            mtap.push(dond);
+            mtap.extend_from_slice(&[0, 0]);
+            mtap.extend_from_slice(&nal);
+        }
+        d.push(&mtap, 0, false);
+        // STAP-B with DON=11
+        let frames = d.push(&[0x79, 0x00, 0x0b, 0x00, 0x02, 0x41, 0x11], 0, true);
+        assert_eq!(
+            nals(&frames[0]),
+            vec![vec![0x41, 0x10], vec![0x41, 0x11], vec![0x41, 0x12]]
+        );
+    }
+
+    #[test]
+    fn mtap_units_of_other_access_units_are_dropped() {
+        let mut d = H264Depacketizer::new();
+        // MTAP24 with DONB=0: a unit of this access unit, then one 3000 ticks later
+        let mut mtap = vec![0x7b, 0x00, 0x00];
+        for (offset, nal) in [(0u32, [0x41, 0x10]), (3000, [0x41, 0x11])] {
+            mtap.extend_from_slice(&6u16.to_be_bytes());
+            mtap.push(0);
+            mtap.extend_from_slice(&offset.to_be_bytes()[1..]);
+            mtap.extend_from_slice(&nal);
+        }
+        let frames = d.push(&mtap, 0, true);
+        assert_eq!(nals(&frames[0]), vec![vec![0x41, 0x10]]);
+        assert_eq!(d.offset_nals, 1);
+    }
+
+    #[test]
+    fn fu_b_start_then_fu_a() {
+        let mut d = H264Depacketizer::new();
+        d.push(&[0x7d, 0x81, 0x00, 0x07, 0x9a], 0, false); // FU-B start, DON=7
+        let frames = d.push(&[0x7c, 0x41, 0x10], 0, true);
+        assert_eq!(nals(&frames[0]), vec![vec![0x61, 0x9a, 0x10]]);
+    }
+}
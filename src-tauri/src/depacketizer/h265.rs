@@ -0,0 +1,385 @@
+use base64::Engine;
+
+use super::{Codec, Depacketizer, START_CODE};
+use crate::frame_pool::FramePool;
+use crate::frame_timing::FrameTiming;
+use crate::h264_syntax::{rbsp, BitReader};
+use crate::webrtc_client::VideoFrame;
+
+// NAL unit types (H.265 table 7-1)
+const NAL_BLA_W_LP: u8 = 16;
+const NAL_CRA: u8 = 21;
+const NAL_VPS: u8 = 32;
+const NAL_SPS: u8 = 33;
+const NAL_PPS: u8 = 34;
+
+// RTP payload structures beyond single NAL units (RFC 7798 section 4.4)
+const AP: u8 = 48;
+const FU: u8 = 49;
+const PACI: u8 = 50;
+
+fn nal_type(header: u8) -> u8 {
+    (header >> 1) & 0x3F
+}
+
+/// Picture size from an SPS NAL unit (H.265 7.3.2.2), after the conformance window.
+fn sps_resolution(nal: &[u8]) -> Option<(u32, u32)> {
+    let data = rbsp(nal.get(2..)?);
+    let mut r = BitReader::new(&data);
+    r.skip(4)?; // sps_video_parameter_set_id
+    let max_sub_layers_minus1 = r.read_bits(3)? as usize;
+    r.skip(1)?; // sps_temporal_id_nesting_flag
+
+    // profile_tier_level(1, sps_max_sub_layers_minus1): general profile and level
+    r.skip(88 + 8)?;
+    let mut sub_layers = Vec::with_capacity(max_sub_layers_minus1);
+    for _ in 0..max_sub_layers_minus1 {
+        sub_layers.push((r.read_bit()?, r.read_bit()?));
+    }
+    if max_sub_layers_minus1 > 0 {
+        r.skip(2 * (8 - max_sub_layers_minus1))?; // reserved_zero_2bits
+    }
+    for (profile_present, level_present) in sub_layers {
+        if profile_present {
+            r.skip(88)?;
+        }
+        if level_present {
+            r.skip(8)?;
+        }
+    }
+
+    r.read_ue()?; // sps_seq_parameter_set_id
+    let chroma_format_idc = r.read_ue()?;
+    if chroma_format_idc == 3 {
+        r.skip(1)?; // separate_colour_plane_flag
+    }
+    let mut width = r.read_ue()?;
+    let mut height = r.read_ue()?;
+    if r.read_bit()? {
+        // conformance_window offsets are in chroma sample units
+        let (sub_width, sub_height) = match chroma_format_idc {
+            1 => (2, 2),
+            2 => (2, 1),
+            _ => (1, 1),
+        };
+        let (left, right) = (r.read_ue()?, r.read_ue()?);
+        let (top, bottom) = (r.read_ue()?, r.read_ue()?);
+        width = width.saturating_sub(sub_width * (left + right));
+        height = height.saturating_sub(sub_height * (top + bottom));
+    }
+    Some((width, height))
+}
+
+/// RTP -> Annex-B depacketizer for H.265/HEVC (RFC 7798).
+///
+/// Handles single NAL units, aggregation packets, fragmentation units and PACI
+/// packets (whose extension header is skipped). The latest VPS/SPS/PPS are cached,
+/// seeded from `sprop-vps`/`sprop-sps`/`sprop-pps`, and written ahead of the first
+/// slice of IRAP access units that lack them; the SPS gives the picture size. Like
+/// browsers, we assume `sprop-max-don-diff` is 0, so no DONL/DOND fields are present.
+pub struct H265Depacketizer {
+    current_timestamp: u32,
+    pool: FramePool,
+    frame_buffer: Vec<u8>,
+    fu_buffer: Vec<u8>,
+    in_fu: bool,
+    /// Cached VPS, SPS and PPS, indexed by `type - NAL_VPS`
+    parameter_sets: [Option<Vec<u8>>; 3],
+    /// Which parameter sets the current access unit carried itself
+    au_has: [bool; 3],
+    au_has_irap: bool,
+    /// Picture size from the cached SPS
+    width: u32,
+    height: u32,
+    /// Frames finished by a timestamp change because their marker packet never arrived
+    partial_frames: u64,
+    /// NAL units dropped for a set forbidden bit
+    malformed_nals: u64,
+}
+
+impl Default for H265Depacketizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl H265Depacketizer {
+    pub fn new() -> Self {
+        let pool = FramePool::new(100 * 1024);
+        Self {
+            current_timestamp: 0,
+            frame_buffer: pool.take(),
+            pool,
+            fu_buffer: Vec::with_capacity(50 * 1024),
+            in_fu: false,
+            parameter_sets: [None, None, None],
+            au_has: [false; 3],
+            au_has_irap: false,
+            width: 0,
+            height: 0,
+            partial_frames: 0,
+            malformed_nals: 0,
+        }
+    }
+
+    /// Seed the parameter set cache from an SDP fmtp line
+    /// (`sprop-vps=...;sprop-sps=...;sprop-pps=...`).
+    pub fn set_sprop_parameter_sets(&mut self, fmtp: &str) {
+        for (key, value) in fmtp.split(';').filter_map(|kv| kv.trim().split_once('=')) {
+            if !matches!(key.trim(), "sprop-vps" | "sprop-sps" | "sprop-pps") {
+                continue;
+            }
+            for set in value.split(',') {
+                match base64::engine::general_purpose::STANDARD.decode(set.trim()) {
+                    Ok(nal) => {
+                        if let Some(slot) = nal.first().and_then(|&h| Self::parameter_set_slot(h)) {
+                            self.set_parameter_set(slot, nal);
+                        }
+                    }
+                    Err(e) => eprintln!("[H265] Bad {} entry {:?}: {}", key.trim(), set, e),
+                }
+            }
+        }
+    }
+
+    fn parameter_set_slot(header: u8) -> Option<usize> {
+        match nal_type(header) {
+            t @ NAL_VPS..=NAL_PPS => Some((t - NAL_VPS) as usize),
+            _ => None,
+        }
+    }
+
+    fn set_parameter_set(&mut self, slot: usize, nal: Vec<u8>) {
+        if slot == (NAL_SPS - NAL_VPS) as usize {
+            match sps_resolution(&nal) {
+                Some((width, height)) => {
+                    self.width = width;
+                    self.height = height;
+                }
+                None => println!("[H265] Could not parse SPS ({} bytes), keeping previous size", nal.len()),
+            }
+        }
+        self.parameter_sets[slot] = Some(nal);
+    }
+
+    fn reset_fu(&mut self) {
+        self.in_fu = false;
+        self.fu_buffer.clear();
+    }
+
+    fn take_frame(&mut self) -> VideoFrame {
+        let keyframe = std::mem::take(&mut self.au_has_irap);
+        self.au_has = [false; 3];
+
+        let data = std::mem::replace(&mut self.frame_buffer, self.pool.take());
+
+        VideoFrame {
+            data: self.pool.freeze(data),
+            width: self.width,
+            height: self.height,
+            frame_rate: None,
+            codec: Codec::H265,
+            keyframe,
+            timing: FrameTiming::new(self.current_timestamp),
+        }
+    }
+
+    fn push_nal(&mut self, nal: &[u8]) {
+        let Some(&header) = nal.first() else { return };
+        if nal.len() < 2 || header & 0x80 != 0 {
+            self.malformed_nals += 1;
+            if self.malformed_nals == 1 || self.malformed_nals.is_multiple_of(100) {
+                println!(
+                    "[H265] Dropping malformed NAL (header={:#04x}, {} so far)",
+                    header, self.malformed_nals
+                );
+            }
+            return;
+        }
+
+        if let Some(slot) = Self::parameter_set_slot(header) {
+            if self.parameter_sets[slot].as_deref() != Some(nal) {
+                self.set_parameter_set(slot, nal.to_vec());
+            }
+            self.au_has[slot] = true;
+        }
+        if (NAL_BLA_W_LP..=NAL_CRA).contains(&nal_type(header)) && !self.au_has_irap {
+            self.au_has_irap = true;
+            // Make every IRAP self-contained, writing the missing parameter sets ahead
+            // of its first slice rather than moving the finished access unit
+            for (set, present) in self.parameter_sets.iter().zip(&mut self.au_has) {
+                if let (false, Some(set)) = (*present, set) {
+                    self.frame_buffer.extend_from_slice(&START_CODE);
+                    self.frame_buffer.extend_from_slice(set);
+                    *present = true;
+                }
+            }
+        }
+
+        self.frame_buffer.extend_from_slice(&START_CODE);
+        self.frame_buffer.extend_from_slice(nal);
+    }
+
+    fn depacketize(&mut self, payload: &[u8]) {
+        if payload.len() < 2 { return; }
+
+        match nal_type(payload[0]) {
+            AP => {
+                // Repeated (16-bit size, NAL unit) after the payload header
+                let mut body = &payload[2..];
+                while body.len() >= 2 {
+                    let len = u16::from_be_bytes([body[0], body[1]]) as usize;
+                    body = &body[2..];
+                    if len == 0 || len > body.len() { break; }
+                    self.push_nal(&body[..len]);
+                    body = &body[len..];
+                }
+            }
+            FU => {
+                if payload.len() < 3 { return; }
+                let fu_header = payload[2];
+                let s_bit = (fu_header & 0x80) != 0;
+                let e_bit = (fu_header & 0x40) != 0;
+
+                if s_bit {
+                    if self.in_fu {
+                        println!("[H265] FU start before previous FU ended (ts={})", self.current_timestamp);
+                    }
+                    self.in_fu = true;
+                    self.fu_buffer.clear();
+                    // Payload header with the type replaced by the FU's original type
+                    self.fu_buffer.push((payload[0] & 0x81) | ((fu_header & 0x3F) << 1));
+                    self.fu_buffer.push(payload[1]);
+                } else if !self.in_fu {
+                    return; // start fragment was lost
+                }
+
+                self.fu_buffer.extend_from_slice(&payload[3..]);
+
+                if e_bit {
+                    let nal = std::mem::take(&mut self.fu_buffer);
+                    self.in_fu = false;
+                    self.push_nal(&nal);
+                    self.fu_buffer = nal;
+                    self.fu_buffer.clear();
+                }
+            }
+            PACI => {
+                // A, cType, PHSsize, TSCI flags and Y in the two bytes after the header
+                if payload.len() < 4 { return; }
+                let c_type = (payload[2] >> 1) & 0x3F;
+                let phs_size = (((payload[2] & 0x01) << 4) | (payload[3] >> 4)) as usize;
+                let Some(inner) = payload.get(4 + phs_size..) else { return };
+                if c_type == PACI { return; }
+
+                let mut unwrapped = Vec::with_capacity(2 + inner.len());
+                unwrapped.push((payload[0] & 0x81) | (c_type << 1));
+                unwrapped.push(payload[1]);
+                unwrapped.extend_from_slice(inner);
+                self.depacketize(&unwrapped);
+            }
+            _ => self.push_nal(payload),
+        }
+    }
+}
+
+impl Depacketizer for H265Depacketizer {
+    fn push(&mut self, payload: &[u8], timestamp: u32, marker: bool) -> Vec<VideoFrame> {
+        let mut completed = Vec::new();
+
+        if self.current_timestamp != timestamp {
+            if !self.frame_buffer.is_empty() {
+                self.partial_frames += 1;
+                if self.partial_frames == 1 || self.partial_frames.is_multiple_of(100) {
+                    println!(
+                        "[H265] Frame ts={} finished without marker bit ({} bytes{}, {} so far)",
+                        self.current_timestamp,
+                        self.frame_buffer.len(),
+                        if self.in_fu { ", FU cut short" } else { "" },
+                        self.partial_frames
+                    );
+                }
+                completed.push(self.take_frame());
+            }
+            self.current_timestamp = timestamp;
+            self.reset_fu();
+        }
+
+        self.depacketize(payload);
+
+        if marker && !self.frame_buffer.is_empty() {
+            if self.in_fu {
+                println!("[H265] Marker bit inside an unfinished FU (ts={})", timestamp);
+                self.reset_fu();
+            }
+            completed.push(self.take_frame());
+        }
+
+        completed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VPS: [u8; 4] = [0x40, 0x01, 0x0c, 0x01];
+    const SPS: [u8; 4] = [0x42, 0x01, 0x01, 0x01];
+    const PPS: [u8; 4] = [0x44, 0x01, 0xc1, 0x72];
+
+    fn nals(frame: &VideoFrame) -> Vec<Vec<u8>> {
+        crate::h264_syntax::nal_units(&frame.data).map(|n| n.to_vec()).collect()
+    }
+
+    #[test]
+    fn aggregation_then_fragmented_idr() {
+        let mut d = H265Depacketizer::new();
+        let mut ap = vec![0x60, 0x01]; // type 48
+        for nal in [&VPS, &SPS, &PPS] {
+            ap.extend_from_slice(&(nal.len() as u16).to_be_bytes());
+            ap.extend_from_slice(nal);
+        }
+        assert!(d.push(&ap, 3000, false).is_empty());
+        // IDR_W_RADL (19) in two FUs
+        assert!(d.push(&[0x62, 0x01, 0x93, 0xaf, 0x01], 3000, false).is_empty());
+        let frames = d.push(&[0x62, 0x01, 0x53, 0x02], 3000, true);
+
+        assert_eq!(frames.len(), 1);
+        assert!(frames[0].keyframe);
+        assert_eq!(
+            nals(&frames[0]),
+            vec![VPS.to_vec(), SPS.to_vec(), PPS.to_vec(), vec![0x26, 0x01, 0xaf, 0x01, 0x02]]
+        );
+    }
+
+    #[test]
+    fn irap_gets_cached_parameter_sets_from_sprop() {
+        let mut d = H265Depacketizer::new();
+        d.set_sprop_parameter_sets("sprop-vps=QAEMAQ==;sprop-sps=QgEBAQ==;sprop-pps=RAHBcg==");
+        let frames = d.push(&[0x2a, 0x01, 0xaf], 0, true); // CRA
+        assert!(frames[0].keyframe);
+        assert_eq!(
+            nals(&frames[0]),
+            vec![VPS.to_vec(), SPS.to_vec(), PPS.to_vec(), vec![0x2a, 0x01, 0xaf]]
+        );
+    }
+
+    #[test]
+    fn sps_gives_size_after_conformance_window() {
+        // Main profile, 1920x1088 coded with 8 lines cropped at the bottom
+        let sps = [
+            0x42, 0x01, 0x01, 0x01, 0x60, 0x00, 0x00, 0x03, 0x00, 0x90, 0x00, 0x00, 0x03, 0x00, 0x00,
+            0x03, 0x00, 0x78, 0xa0, 0x03, 0xc0, 0x80, 0x11, 0x07, 0xcb,
+        ];
+        assert_eq!(sps_resolution(&sps), Some((1920, 1080)));
+    }
+
+    #[test]
+    fn paci_header_extension_is_skipped() {
+        let mut d = H265Depacketizer::new();
+        // PACI (50) wrapping a TRAIL_R (1) NAL, PHSsize 2
+        let frames = d.push(&[0x64, 0x01, 0x02, 0x20, 0xee, 0xee, 0xd0, 0x0d], 0, true);
+        assert!(!frames[0].keyframe);
+        assert_eq!(nals(&frames[0]), vec![vec![0x02, 0x01, 0xd0, 0x0d]]);
+    }
+}
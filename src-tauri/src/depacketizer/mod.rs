@@ -0,0 +1,95 @@
+pub mod av1;
+pub mod h264;
+pub mod h265;
+pub mod vp8;
+pub mod vp9;
+
+pub use av1::Av1Depacketizer;
+pub use h264::H264Depacketizer;
+pub use h265::H265Depacketizer;
+pub use vp8::Vp8Depacketizer;
+pub use vp9::Vp9Depacketizer;
+
+use serde::{Deserialize, Serialize};
+
+use crate::webrtc_client::VideoFrame;
+
+/// Annex-B start code put in front of every H.264/H.265 NAL unit
+const START_CODE: [u8; 4] = [0, 0, 0, 1];
+
+/// Video codecs the receive path can depacketize
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    H264,
+    H265,
+    Vp8,
+    Vp9,
+    Av1,
+}
+
+impl Codec {
+    /// Map a negotiated RTP mime type (e.g. `video/H264`) to a codec.
+    pub fn from_mime_type(mime_type: &str) -> Option<Self> {
+        match mime_type.to_ascii_lowercase().as_str() {
+            "video/h264" => Some(Self::H264),
+            "video/h265" | "video/hevc" => Some(Self::H265),
+            "video/vp8" => Some(Self::Vp8),
+            "video/vp9" => Some(Self::Vp9),
+            "video/av1" => Some(Self::Av1),
+            _ => None,
+        }
+    }
+
+    /// RTP mime type as registered with the media engine
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            Self::H264 => "video/H264",
+            Self::H265 => "video/H265",
+            Self::Vp8 => "video/VP8",
+            Self::Vp9 => "video/VP9",
+            Self::Av1 => "video/AV1",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::H264 => "H.264",
+            Self::H265 => "H.265",
+            Self::Vp8 => "VP8",
+            Self::Vp9 => "VP9",
+            Self::Av1 => "AV1",
+        }
+    }
+
+    /// The virtual camera can carry it: frames go to Media Foundation still
+    /// compressed, and there is no transcoder.
+    pub fn can_output(self) -> bool {
+        matches!(self, Self::H264 | Self::H265)
+    }
+}
+
+/// Turns the RTP payloads of one track into whole frames.
+pub trait Depacketizer: Send {
+    /// Feed one RTP payload (in sequence order). Returns the frames it completed.
+    fn push(&mut self, payload: &[u8], timestamp: u32, marker: bool) -> Vec<VideoFrame>;
+}
+
+/// Create the depacketizer for a negotiated codec. `fmtp` is the track's SDP fmtp line.
+pub fn for_codec(codec: Codec, fmtp: &str) -> Box<dyn Depacketizer> {
+    match codec {
+        Codec::H264 => {
+            let mut depacketizer = H264Depacketizer::new();
+            depacketizer.set_sprop_parameter_sets(fmtp);
+            Box::new(depacketizer)
+        }
+        Codec::H265 => {
+            let mut depacketizer = H265Depacketizer::new();
+            depacketizer.set_sprop_parameter_sets(fmtp);
+            Box::new(depacketizer)
+        }
+        Codec::Vp8 => Box::new(Vp8Depacketizer::new()),
+        Codec::Vp9 => Box::new(Vp9Depacketizer::new()),
+        Codec::Av1 => Box::new(Av1Depacketizer::new()),
+    }
+}
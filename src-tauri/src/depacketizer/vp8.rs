@@ -0,0 +1,194 @@
+use super::{Codec, Depacketizer};
+use crate::frame_pool::FramePool;
+use crate::frame_timing::FrameTiming;
+use crate::webrtc_client::VideoFrame;
+
+/// RTP -> raw VP8 frame depacketizer (RFC 7741).
+///
+/// Strips the VP8 payload descriptor and concatenates partitions until the marker
+/// bit (or a timestamp change, if the marker packet was lost). Frames whose first
+/// packet never arrived are dropped rather than handed on truncated.
+pub struct Vp8Depacketizer {
+    current_timestamp: u32,
+    pool: FramePool,
+    frame_buffer: Vec<u8>,
+    /// The current frame began with a start-of-partition-0 packet
+    frame_started: bool,
+    /// Dimensions from the last keyframe header
+    width: u32,
+    height: u32,
+    /// Frames finished by a timestamp change because their marker packet never arrived
+    partial_frames: u64,
+    /// Frames dropped because their first packet was missing
+    headless_frames: u64,
+}
+
+impl Default for Vp8Depacketizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Vp8Depacketizer {
+    pub fn new() -> Self {
+        let pool = FramePool::new(100 * 1024);
+        Self {
+            current_timestamp: 0,
+            frame_buffer: pool.take(),
+            pool,
+            frame_started: false,
+            width: 0,
+            height: 0,
+            partial_frames: 0,
+            headless_frames: 0,
+        }
+    }
+
+    fn take_frame(&mut self) -> Option<VideoFrame> {
+        let started = std::mem::replace(&mut self.frame_started, false);
+        if !started {
+            self.frame_buffer.clear();
+            self.headless_frames += 1;
+            if self.headless_frames == 1 || self.headless_frames.is_multiple_of(100) {
+                println!(
+                    "[VP8] Dropping frame ts={} without its first packet ({} so far)",
+                    self.current_timestamp, self.headless_frames
+                );
+            }
+            return None;
+        }
+        let data = std::mem::replace(&mut self.frame_buffer, self.pool.take());
+
+        // Frame tag (RFC 6386 section 9.1): P bit clear means keyframe, which is
+        // followed by the 0x9d012a start code and the 14-bit dimensions
+        let keyframe = data.first().is_some_and(|b| b & 0x01 == 0);
+        if let Some([0x9d, 0x01, 0x2a, w0, w1, h0, h1]) = data.get(3..10) {
+            if keyframe {
+                self.width = u16::from_le_bytes([*w0, *w1]) as u32 & 0x3FFF;
+                self.height = u16::from_le_bytes([*h0, *h1]) as u32 & 0x3FFF;
+            }
+        }
+
+        Some(VideoFrame {
+            data: self.pool.freeze(data),
+            width: self.width,
+            height: self.height,
+            frame_rate: None,
+            codec: Codec::Vp8,
+            keyframe,
+            timing: FrameTiming::new(self.current_timestamp),
+        })
+    }
+
+    /// Strip the payload descriptor; returns (start of partition 0, VP8 payload).
+    fn parse_descriptor(payload: &[u8]) -> Option<(bool, &[u8])> {
+        let first = *payload.first()?;
+        let extended = first & 0x80 != 0;
+        let start = first & 0x10 != 0 && first & 0x07 == 0;
+        let mut offset = 1;
+
+        if extended {
+            let x = *payload.get(offset)?;
+            offset += 1;
+            if x & 0x80 != 0 {
+                // PictureID, 7 or 15 bits depending on the M bit
+                let m = *payload.get(offset)? & 0x80 != 0;
+                offset += if m { 2 } else { 1 };
+            }
+            if x & 0x40 != 0 {
+                offset += 1; // TL0PICIDX
+            }
+            if x & 0x30 != 0 {
+                offset += 1; // TID / Y / KEYIDX
+            }
+        }
+
+        payload.get(offset..).filter(|p| !p.is_empty()).map(|p| (start, p))
+    }
+}
+
+impl Depacketizer for Vp8Depacketizer {
+    fn push(&mut self, payload: &[u8], timestamp: u32, marker: bool) -> Vec<VideoFrame> {
+        let mut completed = Vec::new();
+
+        if self.current_timestamp != timestamp {
+            if !self.frame_buffer.is_empty() {
+                self.partial_frames += 1;
+                if self.partial_frames == 1 || self.partial_frames.is_multiple_of(100) {
+                    println!(
+                        "[VP8] Frame ts={} finished without marker bit ({} bytes, {} so far)",
+                        self.current_timestamp,
+                        self.frame_buffer.len(),
+                        self.partial_frames
+                    );
+                }
+                completed.extend(self.take_frame());
+            }
+            self.current_timestamp = timestamp;
+            self.frame_started = false;
+        }
+
+        if let Some((start, data)) = Self::parse_descriptor(payload) {
+            if start && self.frame_buffer.is_empty() {
+                self.frame_started = true;
+            }
+            self.frame_buffer.extend_from_slice(data);
+        }
+
+        if marker && !self.frame_buffer.is_empty() {
+            completed.extend(self.take_frame());
+        }
+
+        completed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 640x480 keyframe tag + header, then payload bytes
+    const KEY_HEADER: [u8; 10] = [0x50, 0x42, 0x00, 0x9d, 0x01, 0x2a, 0x80, 0x02, 0xe0, 0x01];
+
+    #[test]
+    fn keyframe_across_packets_with_extended_descriptor() {
+        let mut d = Vp8Depacketizer::new();
+        // X=1, S=1, PID=0; I=1 with a 15-bit PictureID
+        let mut first = vec![0x90, 0x80, 0x81, 0x23];
+        first.extend_from_slice(&KEY_HEADER);
+        assert!(d.push(&first, 9000, false).is_empty());
+        let frames = d.push(&[0x80, 0x80, 0x81, 0x23, 0xaa, 0xbb], 9000, true);
+
+        assert_eq!(frames.len(), 1);
+        let frame = &frames[0];
+        assert!(frame.keyframe);
+        assert_eq!((frame.width, frame.height), (640, 480));
+        assert_eq!(frame.data.len(), KEY_HEADER.len() + 2);
+        assert_eq!(&frame.data[KEY_HEADER.len()..], &[0xaa, 0xbb]);
+    }
+
+    #[test]
+    fn interframe_with_minimal_descriptor() {
+        let mut d = Vp8Depacketizer::new();
+        let frames = d.push(&[0x10, 0x31, 0x02, 0x00, 0x55], 3000, true);
+        assert!(!frames[0].keyframe);
+        assert_eq!(frames[0].data, vec![0x31, 0x02, 0x00, 0x55]);
+    }
+
+    #[test]
+    fn frame_missing_first_packet_is_dropped() {
+        let mut d = Vp8Depacketizer::new();
+        assert!(d.push(&[0x00, 0x11, 0x22], 3000, true).is_empty());
+        // The next frame is unaffected
+        assert_eq!(d.push(&[0x10, 0x31, 0x02], 6000, true).len(), 1);
+    }
+
+    #[test]
+    fn timestamp_change_finishes_frame_without_marker() {
+        let mut d = Vp8Depacketizer::new();
+        assert!(d.push(&[0x10, 0x31, 0x01], 3000, false).is_empty());
+        let frames = d.push(&[0x10, 0x31, 0x02], 6000, true);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].data, vec![0x31, 0x01]);
+    }
+}
@@ -0,0 +1,275 @@
+use super::{Codec, Depacketizer};
+use crate::frame_pool::FramePool;
+use crate::frame_timing::FrameTiming;
+use crate::webrtc_client::VideoFrame;
+
+/// RTP -> VP9 frame depacketizer (RFC 9628), flexible and non-flexible mode.
+///
+/// Layer frames are delimited by the B/E bits and a picture by the marker bit. A
+/// picture made of several spatial layers is emitted as one superframe (with the
+/// index from VP9 Annex B) so it stays a single decodable unit. Resolution comes
+/// from the scalability structure, which senders attach to keyframes.
+pub struct Vp9Depacketizer {
+    current_timestamp: u32,
+    pool: FramePool,
+    /// Layer frames of the current picture, completed ones first
+    layers: Vec<Vec<u8>>,
+    /// A layer frame is in progress (its B packet was seen)
+    in_layer: bool,
+    /// The picture's base layer is not predicted from an earlier picture
+    keyframe: bool,
+    /// Dimensions of the highest spatial layer from the last scalability structure
+    width: u32,
+    height: u32,
+    /// Frames finished by a timestamp change because their marker packet never arrived
+    partial_frames: u64,
+    /// Layer frames dropped because their first packet was missing
+    headless_frames: u64,
+}
+
+struct Descriptor {
+    predicted: bool,
+    begin: bool,
+    end: bool,
+    spatial_id: u8,
+    /// Highest spatial layer's (width, height), if a scalability structure was present
+    resolution: Option<(u32, u32)>,
+}
+
+impl Default for Vp9Depacketizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Vp9Depacketizer {
+    pub fn new() -> Self {
+        Self {
+            current_timestamp: 0,
+            pool: FramePool::new(100 * 1024),
+            layers: Vec::new(),
+            in_layer: false,
+            keyframe: false,
+            width: 0,
+            height: 0,
+            partial_frames: 0,
+            headless_frames: 0,
+        }
+    }
+
+    /// Parse the payload descriptor; returns it and the VP9 payload behind it.
+    fn parse_descriptor(payload: &[u8]) -> Option<(Descriptor, &[u8])> {
+        let first = *payload.first()?;
+        let picture_id = first & 0x80 != 0;
+        let predicted = first & 0x40 != 0;
+        let layer_indices = first & 0x20 != 0;
+        let flexible = first & 0x10 != 0;
+        let begin = first & 0x08 != 0;
+        let end = first & 0x04 != 0;
+        let scalability = first & 0x02 != 0;
+        let mut offset = 1;
+        let mut spatial_id = 0;
+        let mut resolution = None;
+
+        if picture_id {
+            let m = *payload.get(offset)? & 0x80 != 0;
+            offset += if m { 2 } else { 1 };
+        }
+        if layer_indices {
+            spatial_id = (*payload.get(offset)? >> 1) & 0x07;
+            offset += 1;
+            if !flexible {
+                offset += 1; // TL0PICIDX
+            }
+        }
+        if flexible && predicted {
+            // Up to three reference indices, each flagging whether another follows
+            for _ in 0..3 {
+                let p_diff = *payload.get(offset)?;
+                offset += 1;
+                if p_diff & 0x01 == 0 {
+                    break;
+                }
+            }
+        }
+        if scalability {
+            let ss = *payload.get(offset)?;
+            offset += 1;
+            let spatial_layers = (ss >> 5) as usize + 1;
+            if ss & 0x10 != 0 {
+                for _ in 0..spatial_layers {
+                    let dims = payload.get(offset..offset + 4)?;
+                    resolution = Some((
+                        u16::from_be_bytes([dims[0], dims[1]]) as u32,
+                        u16::from_be_bytes([dims[2], dims[3]]) as u32,
+                    ));
+                    offset += 4;
+                }
+            }
+            if ss & 0x08 != 0 {
+                let groups = *payload.get(offset)?;
+                offset += 1;
+                for _ in 0..groups {
+                    let refs = (*payload.get(offset)? >> 2) & 0x03;
+                    offset += 1 + refs as usize;
+                }
+            }
+        }
+
+        let data = payload.get(offset..).filter(|p| !p.is_empty())?;
+        Some((Descriptor { predicted, begin, end, spatial_id, resolution }, data))
+    }
+
+    fn take_frame(&mut self) -> Option<VideoFrame> {
+        let mut layers = std::mem::take(&mut self.layers);
+        self.in_layer = false;
+        let keyframe = std::mem::replace(&mut self.keyframe, false);
+        layers.retain(|l| !l.is_empty());
+
+        let data = match layers.len() {
+            0 => return None,
+            1 => layers.pop()?,
+            _ => {
+                let mut out = self.pool.take();
+                superframe(&layers, &mut out);
+                out
+            }
+        };
+
+        Some(VideoFrame {
+            data: self.pool.freeze(data),
+            width: self.width,
+            height: self.height,
+            frame_rate: None,
+            codec: Codec::Vp9,
+            keyframe,
+            timing: FrameTiming::new(self.current_timestamp),
+        })
+    }
+}
+
+/// Join layer frames into a superframe with a trailing index (VP9 Annex B).
+fn superframe(layers: &[Vec<u8>], out: &mut Vec<u8>) {
+    let largest = layers.iter().map(Vec::len).max().unwrap_or(0);
+    let size_bytes = match largest {
+        0..=0xFF => 1,
+        0x100..=0xFFFF => 2,
+        0x1_0000..=0xFF_FFFF => 3,
+        _ => 4,
+    };
+    let marker = 0xC0 | ((size_bytes as u8 - 1) << 3) | (layers.len() as u8 - 1);
+
+    out.reserve(layers.iter().map(Vec::len).sum::<usize>() + 2 + layers.len() * size_bytes);
+    for layer in layers {
+        out.extend_from_slice(layer);
+    }
+    out.push(marker);
+    for layer in layers {
+        out.extend_from_slice(&(layer.len() as u32).to_le_bytes()[..size_bytes]);
+    }
+    out.push(marker);
+}
+
+impl Depacketizer for Vp9Depacketizer {
+    fn push(&mut self, payload: &[u8], timestamp: u32, marker: bool) -> Vec<VideoFrame> {
+        let mut completed = Vec::new();
+
+        if self.current_timestamp != timestamp {
+            if !self.layers.is_empty() {
+                self.partial_frames += 1;
+                if self.partial_frames == 1 || self.partial_frames.is_multiple_of(100) {
+                    println!(
+                        "[VP9] Picture ts={} finished without marker bit ({} layers, {} so far)",
+                        self.current_timestamp,
+                        self.layers.len(),
+                        self.partial_frames
+                    );
+                }
+                completed.extend(self.take_frame());
+            }
+            self.current_timestamp = timestamp;
+            self.in_layer = false;
+        }
+
+        if let Some((desc, data)) = Self::parse_descriptor(payload) {
+            if let Some((width, height)) = desc.resolution {
+                self.width = width;
+                self.height = height;
+            }
+            if desc.begin {
+                if desc.spatial_id == 0 {
+                    self.keyframe = !desc.predicted;
+                }
+                self.layers.push(self.pool.take());
+                self.in_layer = true;
+            } else if !self.in_layer {
+                self.headless_frames += 1;
+                if self.headless_frames == 1 || self.headless_frames.is_multiple_of(100) {
+                    println!(
+                        "[VP9] Dropping layer frame ts={} without its first packet ({} so far)",
+                        timestamp, self.headless_frames
+                    );
+                }
+            }
+            if self.in_layer {
+                if let Some(layer) = self.layers.last_mut() {
+                    layer.extend_from_slice(data);
+                }
+                if desc.end {
+                    self.in_layer = false;
+                }
+            }
+        }
+
+        if marker && !self.layers.is_empty() {
+            completed.extend(self.take_frame());
+        }
+
+        completed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn non_flexible_keyframe_with_scalability_structure() {
+        let mut d = Vp9Depacketizer::new();
+        // I=1 (15-bit picture id), L=1, B=1, V=1; TID/SID byte, TL0PICIDX;
+        // SS: one spatial layer with resolution, no picture groups
+        let first = [0xaa, 0x80, 0x01, 0x00, 0x00, 0x10, 0x05, 0x00, 0x02, 0xd0, 0x82, 0x49];
+        assert!(d.push(&first, 9000, false).is_empty());
+        // I=1, L=1, E=1
+        let frames = d.push(&[0xa4, 0x80, 0x01, 0x00, 0x00, 0x83, 0x42], 9000, true);
+
+        assert_eq!(frames.len(), 1);
+        assert!(frames[0].keyframe);
+        assert_eq!((frames[0].width, frames[0].height), (1280, 720));
+        assert_eq!(frames[0].data, vec![0x82, 0x49, 0x83, 0x42]);
+    }
+
+    #[test]
+    fn flexible_mode_reference_indices_are_skipped() {
+        let mut d = Vp9Depacketizer::new();
+        // I=1 (7-bit), P=1, F=1, B=1, E=1; two P_DIFF bytes (first has N set)
+        let frames = d.push(&[0xdc, 0x05, 0x03, 0x04, 0x86, 0x00, 0x11], 3000, true);
+        assert!(!frames[0].keyframe);
+        assert_eq!(frames[0].data, vec![0x86, 0x00, 0x11]);
+    }
+
+    #[test]
+    fn spatial_layers_become_a_superframe() {
+        let mut d = Vp9Depacketizer::new();
+        // L=1, B=1, E=1 on SID 0 then SID 1, non-flexible
+        d.push(&[0x2c, 0x00, 0x00, 0x82, 0x01], 0, false);
+        let frames = d.push(&[0x2c, 0x02, 0x00, 0x86, 0x02, 0x03], 0, true);
+        assert_eq!(frames[0].data, vec![0x82, 0x01, 0x86, 0x02, 0x03, 0xc1, 0x02, 0x03, 0xc1]);
+    }
+
+    #[test]
+    fn layer_frame_missing_first_packet_is_dropped() {
+        let mut d = Vp9Depacketizer::new();
+        assert!(d.push(&[0x04, 0x11, 0x22], 3000, true).is_empty());
+    }
+}
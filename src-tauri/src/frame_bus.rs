@@ -0,0 +1,339 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use arc_swap::{ArcSwap, ArcSwapOption};
+use tokio::sync::mpsc::{self, error::TryRecvError};
+
+use crate::webrtc_client::{MediaEvent, VideoFormat, VideoFrame};
+
+/// Frames a subscriber may have in its channel before it is resynced anyway (~30 s
+/// at 30 fps), so a stuck consumer can't grow memory without bound
+const MAX_BACKLOG: usize = 900;
+
+/// What happens to a subscriber that can't keep up.
+///
+/// Frames are compressed and depend on their predecessors, so frames are never
+/// dropped one at a time: on overflow the whole backlog goes, and the subscriber
+/// resumes at the next keyframe.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LagPolicy {
+    /// Keep at most this many frames queued. For live outputs (virtual camera,
+    /// preview) where staying current matters more than completeness.
+    Latest(usize),
+    /// Queue every frame, for recorders and re-streamers.
+    Lossless,
+}
+
+/// Why a subscription produced no event
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RecvError {
+    /// Nothing queued (`try_recv` only)
+    Empty,
+    /// The subscriber fell behind and this many queued frames were dropped. Frames
+    /// resume at the next keyframe, so this is the moment to ask for one.
+    Lagged(u64),
+    /// The bus is gone and the queue is drained
+    Closed,
+}
+
+/// What travels down a subscriber's channel
+enum Slot {
+    Format(VideoFormat),
+    /// A frame and the overflow epoch it was queued in
+    Frame(u32, VideoFrame),
+    Lagged(u64),
+}
+
+/// Counters a subscription shares with its queue on the bus.
+///
+/// A channel can't be emptied from the sending side, so an overflow starts a new
+/// epoch instead, and the subscriber discards frames queued in an older one as it
+/// reaches them.
+#[derive(Default)]
+struct Backlog {
+    /// Current epoch in the high half, frames queued in it in the low half
+    queued: AtomicU64,
+    /// Frames in the channel, including ones a later overflow made stale
+    in_flight: AtomicUsize,
+    /// Frames this subscriber never got
+    dropped: AtomicU64,
+}
+
+fn epoch(queued: u64) -> u32 {
+    (queued >> 32) as u32
+}
+
+fn queued_frames(queued: u64) -> usize {
+    queued as u32 as usize
+}
+
+impl Backlog {
+    /// A frame of `frame_epoch` left the channel; false if it went stale meanwhile.
+    fn take(&self, frame_epoch: u32) -> bool {
+        self.in_flight.fetch_sub(1, Ordering::AcqRel);
+        self.queued
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |q| (epoch(q) == frame_epoch).then(|| q - 1))
+            .is_ok()
+    }
+}
+
+/// The bus's end of a subscription. Only the publishing track writes to it.
+struct Queue {
+    name: &'static str,
+    policy: LagPolicy,
+    tx: mpsc::UnboundedSender<Slot>,
+    backlog: Arc<Backlog>,
+    /// Skipping frames until the next keyframe
+    resync: AtomicBool,
+}
+
+impl Queue {
+    fn push(&self, event: &MediaEvent) {
+        let frame = match event {
+            MediaEvent::FormatChanged(format) => {
+                let _ = self.tx.send(Slot::Format(*format));
+                return;
+            }
+            MediaEvent::Frame(frame) => frame,
+        };
+        let backlog = &self.backlog;
+        let limit = match self.policy {
+            LagPolicy::Latest(frames) => frames.max(1),
+            LagPolicy::Lossless => MAX_BACKLOG,
+        };
+        let queued = backlog.queued.load(Ordering::Acquire);
+        if queued_frames(queued) >= limit {
+            let next = (epoch(queued).wrapping_add(1) as u64) << 32;
+            let stale = queued_frames(backlog.queued.swap(next, Ordering::AcqRel)) as u64;
+            let dropped = backlog.dropped.fetch_add(stale, Ordering::Relaxed) + stale;
+            self.resync.store(true, Ordering::Relaxed);
+            let _ = self.tx.send(Slot::Lagged(stale));
+            println!(
+                "[Bus] {} fell {} frames behind, skipping to the next keyframe ({} dropped so far)",
+                self.name, stale, dropped
+            );
+        }
+        // A subscriber that isn't even reading its stale frames gets no more
+        let stuck = backlog.in_flight.load(Ordering::Acquire) >= MAX_BACKLOG;
+        if stuck || (self.resync.load(Ordering::Relaxed) && !frame.keyframe) {
+            self.resync.store(true, Ordering::Relaxed);
+            backlog.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        self.resync.store(false, Ordering::Relaxed);
+        let frame_epoch = epoch(backlog.queued.fetch_add(1, Ordering::AcqRel));
+        backlog.in_flight.fetch_add(1, Ordering::AcqRel);
+        let _ = self.tx.send(Slot::Frame(frame_epoch, frame.clone()));
+    }
+}
+
+/// One consumer's view of the stream, created by `FrameBus::subscribe` and owned by
+/// the task that consumes it. Dropping it unsubscribes.
+pub struct Subscription {
+    rx: mpsc::UnboundedReceiver<Slot>,
+    backlog: Arc<Backlog>,
+}
+
+impl Subscription {
+    /// Next event, if one is queued. A lag is reported before the frames after it.
+    pub fn try_recv(&mut self) -> Result<MediaEvent, RecvError> {
+        loop {
+            let slot = match self.rx.try_recv() {
+                Ok(slot) => slot,
+                Err(TryRecvError::Empty) => return Err(RecvError::Empty),
+                Err(TryRecvError::Disconnected) => return Err(RecvError::Closed),
+            };
+            if let Some(result) = self.open(slot) {
+                return result;
+            }
+        }
+    }
+
+    /// Wait for the next event (or lag report).
+    pub async fn recv(&mut self) -> Result<MediaEvent, RecvError> {
+        loop {
+            let Some(slot) = self.rx.recv().await else { return Err(RecvError::Closed) };
+            if let Some(result) = self.open(slot) {
+                return result;
+            }
+        }
+    }
+
+    /// What to hand out for a slot; `None` for a stale frame.
+    fn open(&self, slot: Slot) -> Option<Result<MediaEvent, RecvError>> {
+        match slot {
+            Slot::Format(format) => Some(Ok(MediaEvent::FormatChanged(format))),
+            Slot::Frame(frame_epoch, frame) => self.backlog.take(frame_epoch).then_some(Ok(MediaEvent::Frame(frame))),
+            Slot::Lagged(frames) => Some(Err(RecvError::Lagged(frames))),
+        }
+    }
+
+    /// Frames skipped because this subscriber fell behind (or joined mid-GOP), readable
+    /// from other tasks
+    pub fn dropped(&self) -> DroppedFrames {
+        DroppedFrames(self.backlog.clone())
+    }
+}
+
+/// A subscription's dropped frame count
+#[derive(Clone)]
+pub struct DroppedFrames(Arc<Backlog>);
+
+impl DroppedFrames {
+    pub fn get(&self) -> u64 {
+        self.0.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Default)]
+struct Subscribers {
+    /// Replaced on subscribe and unsubscribe, read lock-free on every publish
+    queues: ArcSwap<Vec<Arc<Queue>>>,
+    /// Last announced format, replayed to late subscribers
+    format: ArcSwapOption<VideoFormat>,
+}
+
+/// Fans the received stream out to independent consumers (virtual camera, recorder,
+/// stats, preview, re-streamers).
+///
+/// Publishing never blocks or locks: each subscriber has its own channel and
+/// `LagPolicy`, so a slow recorder can't stall the camera. Frames are shared, not
+/// copied. Dropping the bus closes every subscription once it is drained.
+#[derive(Clone, Default)]
+pub struct FrameBus {
+    subscribers: Arc<Subscribers>,
+}
+
+impl FrameBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start receiving from the next keyframe on, after the current format.
+    pub fn subscribe(&self, name: &'static str, policy: LagPolicy) -> Subscription {
+        let (tx, rx) = mpsc::unbounded_channel();
+        if let Some(format) = self.subscribers.format.load().as_deref() {
+            let _ = tx.send(Slot::Format(*format));
+        }
+        let backlog = Arc::new(Backlog::default());
+        let queue = Arc::new(Queue { name, policy, tx, backlog: backlog.clone(), resync: AtomicBool::new(true) });
+        self.subscribers.queues.rcu(|queues| {
+            let mut queues = Vec::clone(queues);
+            queues.push(queue.clone());
+            queues
+        });
+        println!("[Bus] {} subscribed ({:?})", name, policy);
+        Subscription { rx, backlog }
+    }
+
+    /// Hand an event to every subscriber. Called from one task at a time, the track
+    /// reader feeding the camera.
+    pub fn publish(&self, event: MediaEvent) {
+        if let MediaEvent::FormatChanged(format) = &event {
+            self.subscribers.format.store(Some(Arc::new(*format)));
+        }
+        let queues = self.subscribers.queues.load();
+        if queues.iter().any(|queue| queue.tx.is_closed()) {
+            for queue in queues.iter().filter(|queue| queue.tx.is_closed()) {
+                println!("[Bus] {} unsubscribed", queue.name);
+            }
+            self.subscribers
+                .queues
+                .rcu(|queues| queues.iter().filter(|queue| !queue.tx.is_closed()).cloned().collect::<Vec<_>>());
+        }
+        for queue in queues.iter().filter(|queue| !queue.tx.is_closed()) {
+            queue.push(&event);
+        }
+    }
+
+    #[cfg(test)]
+    fn subscriber_count(&self) -> usize {
+        self.subscribers.queues.load().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::depacketizer::Codec;
+    use crate::frame_timing::FrameTiming;
+
+    fn frame(n: u32, keyframe: bool) -> MediaEvent {
+        MediaEvent::Frame(VideoFrame::h264(vec![n as u8], 640, 480, keyframe, FrameTiming::new(n * 3000)))
+    }
+
+    fn frames(sub: &mut Subscription) -> Vec<u8> {
+        let mut frames = Vec::new();
+        loop {
+            match sub.try_recv() {
+                Ok(MediaEvent::Frame(f)) => frames.push(f.data[0]),
+                Ok(MediaEvent::FormatChanged(_)) | Err(RecvError::Lagged(_)) => {}
+                Err(_) => return frames,
+            }
+        }
+    }
+
+    #[test]
+    fn subscribers_start_at_a_keyframe_and_share_frames() {
+        let bus = FrameBus::new();
+        let mut a = bus.subscribe("a", LagPolicy::Lossless);
+        bus.publish(frame(0, false));
+        bus.publish(frame(1, true));
+        let mut b = bus.subscribe("b", LagPolicy::Lossless);
+        bus.publish(frame(2, false));
+        bus.publish(frame(3, true));
+
+        assert_eq!(frames(&mut a), vec![1, 2, 3]);
+        assert_eq!(frames(&mut b), vec![3]);
+        assert_eq!(a.dropped().get(), 1);
+    }
+
+    #[test]
+    fn lagging_latest_subscriber_skips_to_keyframe_without_affecting_others() {
+        let bus = FrameBus::new();
+        let mut camera = bus.subscribe("camera", LagPolicy::Latest(2));
+        let mut recorder = bus.subscribe("recorder", LagPolicy::Lossless);
+        bus.publish(frame(0, true));
+        for n in 1..5 {
+            bus.publish(frame(n, false));
+        }
+        bus.publish(frame(5, true));
+        bus.publish(frame(6, false));
+
+        // Backlog of 2 overflowed at frame 2; 2..4 are undecodable without it
+        assert_eq!(camera.try_recv().err(), Some(RecvError::Lagged(2)));
+        assert_eq!(frames(&mut camera), vec![5, 6]);
+        assert_eq!(camera.dropped().get(), 5);
+        assert_eq!(frames(&mut recorder), vec![0, 1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn stuck_subscriber_holds_a_bounded_backlog() {
+        let bus = FrameBus::new();
+        let mut stuck = bus.subscribe("stuck", LagPolicy::Latest(4));
+        for n in 0..2 * MAX_BACKLOG as u32 {
+            bus.publish(frame(n, true));
+        }
+        // Stale frames stay in the channel until read, but no more than the cap
+        assert_eq!(stuck.backlog.in_flight.load(Ordering::Relaxed), MAX_BACKLOG);
+        assert!(frames(&mut stuck).is_empty());
+        assert_eq!(stuck.backlog.in_flight.load(Ordering::Relaxed), 0);
+
+        // Once it reads again, it resumes at the next keyframe
+        bus.publish(frame(7, true));
+        assert_eq!(frames(&mut stuck), vec![7]);
+    }
+
+    #[test]
+    fn late_subscriber_gets_current_format_and_dropped_ones_are_removed() {
+        let bus = FrameBus::new();
+        let format = VideoFormat { codec: Codec::H264, width: 1280, height: 720, frame_rate: None };
+        bus.publish(MediaEvent::FormatChanged(format));
+        let mut sub = bus.subscribe("late", LagPolicy::Latest(1));
+        assert!(matches!(sub.try_recv(), Ok(MediaEvent::FormatChanged(f)) if f == format));
+
+        drop(sub);
+        bus.publish(frame(0, true));
+        assert_eq!(bus.subscriber_count(), 0);
+    }
+}
@@ -0,0 +1,104 @@
+use std::sync::{Arc, Mutex, Weak};
+
+use bytes::Bytes;
+
+/// Free buffers kept per pool; a track rarely has more frames in flight than this
+const MAX_FREE: usize = 8;
+
+type FreeList = Mutex<Vec<Vec<u8>>>;
+
+/// Recycles the allocations frames are assembled in.
+///
+/// A depacketizer fills a buffer from `take` once and `freeze`s it into a `Bytes`,
+/// which every consumer shares without copying. When the last handle drops, the
+/// allocation goes back to the pool for a later frame instead of being freed.
+#[derive(Clone)]
+pub struct FramePool {
+    free: Arc<FreeList>,
+    /// Capacity of newly allocated buffers
+    capacity: usize,
+}
+
+/// Keeps a pooled buffer alive behind a `Bytes` and returns it on drop
+struct Pooled {
+    data: Vec<u8>,
+    pool: Weak<FreeList>,
+}
+
+impl AsRef<[u8]> for Pooled {
+    fn as_ref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl Drop for Pooled {
+    fn drop(&mut self) {
+        let Some(pool) = self.pool.upgrade() else { return };
+        let mut free = pool.lock().unwrap();
+        if free.len() < MAX_FREE {
+            let mut data = std::mem::take(&mut self.data);
+            data.clear();
+            free.push(data);
+        }
+    }
+}
+
+impl FramePool {
+    pub fn new(capacity: usize) -> Self {
+        Self { free: Arc::new(Mutex::new(Vec::new())), capacity }
+    }
+
+    /// An empty buffer to assemble the next frame in.
+    pub fn take(&self) -> Vec<u8> {
+        self.free
+            .lock()
+            .unwrap()
+            .pop()
+            .unwrap_or_else(|| Vec::with_capacity(self.capacity))
+    }
+
+    /// Share a filled buffer. Its allocation returns to the pool once every clone of
+    /// the `Bytes` is dropped.
+    pub fn freeze(&self, data: Vec<u8>) -> Bytes {
+        Bytes::from_owner(Pooled { data, pool: Arc::downgrade(&self.free) })
+    }
+
+    /// Buffers waiting to be reused
+    #[cfg(test)]
+    fn free_buffers(&self) -> usize {
+        self.free.lock().unwrap().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffer_returns_to_pool_after_last_clone_drops() {
+        let pool = FramePool::new(1024);
+        let mut data = pool.take();
+        data.extend_from_slice(&[1, 2, 3]);
+        let ptr = data.as_ptr();
+
+        let frame = pool.freeze(data);
+        let shared = frame.clone();
+        assert_eq!(shared.as_ptr(), ptr);
+        drop(frame);
+        assert_eq!(pool.free_buffers(), 0);
+        drop(shared);
+        assert_eq!(pool.free_buffers(), 1);
+
+        let reused = pool.take();
+        assert!(reused.is_empty());
+        assert_eq!(reused.as_ptr(), ptr);
+    }
+
+    #[test]
+    fn buffer_outliving_its_pool_is_freed() {
+        let pool = FramePool::new(16);
+        let frame = pool.freeze(pool.take());
+        drop(pool);
+        drop(frame);
+    }
+}
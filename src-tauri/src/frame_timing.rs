@@ -0,0 +1,239 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::webrtc_client::VideoFrame;
+
+/// RTP video clock rate
+pub const CLOCK_RATE: u64 = 90_000;
+/// Frames whose packets have been seen but that haven't been completed yet
+const MAX_PENDING: usize = 8;
+/// A PTS step larger than this (e.g. a new track after reconnecting) restarts the
+/// mapping from RTP time to the local clock
+const MAX_PTS_JUMP: u64 = 5 * CLOCK_RATE;
+/// Frame duration when the bitstream doesn't signal a rate
+const DEFAULT_FRAME_RATE: (u32, u32) = (30, 1);
+
+/// When a frame was captured and how it arrived.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FrameTiming {
+    /// RTP timestamp as sent
+    pub rtp_timestamp: u32,
+    /// Presentation time in 90 kHz units, unwrapped to 64 bits. Only differences
+    /// between frames of the same track are meaningful.
+    pub pts: u64,
+    /// When the frame's first packet arrived
+    pub arrival: Option<Instant>,
+    /// Extended sequence numbers of the frame's first and last packet
+    pub first_seq: u64,
+    pub last_seq: u64,
+    /// The last packet carried the RTP marker bit: the frame's tail wasn't lost
+    pub marker: bool,
+    /// When to present the frame on the local clock, at the phone's capture spacing.
+    /// Set by the pipeline as the frame is published, so every output shares it.
+    pub presentation: Option<Instant>,
+    /// How long the frame is shown, from the stream's frame rate
+    pub duration: Duration,
+}
+
+impl FrameTiming {
+    /// Timing known to a depacketizer: just the RTP timestamp. The rest is filled in
+    /// by `FrameClock::stamp`.
+    pub fn new(rtp_timestamp: u32) -> Self {
+        Self { rtp_timestamp, ..Default::default() }
+    }
+
+    /// Number of packets the frame spanned, lost ones included
+    pub fn packets(&self) -> u64 {
+        self.last_seq - self.first_seq + 1
+    }
+}
+
+/// Collects per-frame timing from the packets of one track, in release order, and
+/// stamps it onto the frames the depacketizer completes.
+#[derive(Default)]
+pub struct FrameClock {
+    /// Extended timestamp of the most recent new frame
+    last: Option<u64>,
+    /// Timing of frames still being assembled, oldest first
+    pending: VecDeque<FrameTiming>,
+}
+
+impl FrameClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Extend a 32-bit RTP timestamp relative to the last one seen. B-frames and
+    /// reordering can step backwards, so a timestamp is placed whichever way is closer.
+    fn unwrap_timestamp(&self, timestamp: u32) -> u64 {
+        match self.last {
+            None => timestamp as u64 + (1 << 32), // headroom so stepping back can't underflow
+            Some(last) => {
+                let delta = timestamp.wrapping_sub(last as u32) as i32;
+                (last as i64 + delta as i64).max(0) as u64
+            }
+        }
+    }
+
+    /// Record one packet as it's handed to the depacketizer.
+    pub fn on_packet(&mut self, rtp_timestamp: u32, seq: u64, marker: bool, arrival: Instant) {
+        if let Some(timing) = self.pending.iter_mut().rev().find(|t| t.rtp_timestamp == rtp_timestamp) {
+            timing.first_seq = timing.first_seq.min(seq);
+            timing.last_seq = timing.last_seq.max(seq);
+            timing.marker |= marker;
+            timing.arrival = timing.arrival.min(Some(arrival));
+            return;
+        }
+
+        let pts = self.unwrap_timestamp(rtp_timestamp);
+        self.last = Some(pts);
+        if self.pending.len() == MAX_PENDING {
+            // Never completed (e.g. dropped for a missing first packet)
+            self.pending.pop_front();
+        }
+        self.pending.push_back(FrameTiming {
+            rtp_timestamp,
+            pts,
+            arrival: Some(arrival),
+            first_seq: seq,
+            last_seq: seq,
+            marker,
+            ..Default::default()
+        });
+    }
+
+    /// Fill in the timing of a completed frame from its packets.
+    pub fn stamp(&mut self, frame: &mut VideoFrame) {
+        let rtp_timestamp = frame.timing.rtp_timestamp;
+        if let Some(i) = self.pending.iter().position(|t| t.rtp_timestamp == rtp_timestamp) {
+            if let Some(timing) = self.pending.remove(i) {
+                frame.timing = timing;
+            }
+        }
+    }
+}
+
+/// Places frames on the local monotonic clock: the anchor frame's arrival plus the
+/// PTS distance from it, so outputs see the phone's capture spacing rather than our
+/// network jitter.
+#[derive(Default)]
+pub struct CaptureClock {
+    /// Local time and PTS of the frame the RTP timeline is anchored to
+    anchor: Option<(Instant, u64)>,
+    last_pts: u64,
+}
+
+impl CaptureClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the presentation time and duration of a frame about to be published.
+    pub fn place(&mut self, frame: &mut VideoFrame, now: Instant) {
+        let pts = frame.timing.pts;
+        let (base, base_pts) = match self.anchor {
+            Some(anchor) if pts.abs_diff(self.last_pts) <= MAX_PTS_JUMP => anchor,
+            _ => *self.anchor.insert((frame.timing.arrival.unwrap_or(now), pts)),
+        };
+        self.last_pts = pts;
+
+        let offset = Duration::from_micros(pts.abs_diff(base_pts) * 1_000_000 / CLOCK_RATE);
+        frame.timing.presentation = if pts >= base_pts {
+            base.checked_add(offset)
+        } else {
+            base.checked_sub(offset)
+        };
+        let (num, den) = frame.frame_rate.unwrap_or(DEFAULT_FRAME_RATE);
+        frame.timing.duration = Duration::from_nanos(1_000_000_000 * den as u64 / num.max(1) as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(rtp_timestamp: u32) -> VideoFrame {
+        VideoFrame::h264(bytes::Bytes::new(), 0, 0, false, FrameTiming::new(rtp_timestamp))
+    }
+
+    #[test]
+    fn frame_gets_sequence_range_and_first_arrival() {
+        let mut clock = FrameClock::new();
+        let t = Instant::now();
+        clock.on_packet(3000, 10, false, t);
+        clock.on_packet(3000, 11, true, t + std::time::Duration::from_millis(5));
+        clock.on_packet(6000, 12, false, t); // next frame started before the first completed
+
+        let mut f = frame(3000);
+        clock.stamp(&mut f);
+        assert_eq!((f.timing.first_seq, f.timing.last_seq, f.timing.packets()), (10, 11, 2));
+        assert!(f.timing.marker);
+        assert_eq!(f.timing.arrival, Some(t));
+
+        let mut g = frame(6000);
+        clock.stamp(&mut g);
+        assert!(!g.timing.marker);
+        assert_eq!(g.timing.pts - f.timing.pts, 3000);
+    }
+
+    #[test]
+    fn pts_keeps_increasing_across_timestamp_wraparound() {
+        let mut clock = FrameClock::new();
+        let t = Instant::now();
+        let mut pts = Vec::new();
+        for (seq, ts) in [u32::MAX - 2999, 0, 3000].into_iter().enumerate() {
+            clock.on_packet(ts.wrapping_add(1500), seq as u64, true, t);
+            let mut f = frame(ts.wrapping_add(1500));
+            clock.stamp(&mut f);
+            pts.push(f.timing.pts);
+        }
+        assert_eq!(pts[1] - pts[0], 3000);
+        assert_eq!(pts[2] - pts[1], 3000);
+    }
+
+    #[test]
+    fn earlier_timestamp_does_not_underflow() {
+        let mut clock = FrameClock::new();
+        let t = Instant::now();
+        clock.on_packet(100, 0, true, t);
+        clock.on_packet(50, 1, true, t); // B-frame presented before the previous one
+        let (mut a, mut b) = (frame(100), frame(50));
+        clock.stamp(&mut a);
+        clock.stamp(&mut b);
+        assert_eq!(a.timing.pts - b.timing.pts, 50);
+    }
+
+    #[test]
+    fn capture_clock_keeps_capture_spacing() {
+        let mut clock = CaptureClock::new();
+        let start = Instant::now();
+        let mut placed = Vec::new();
+        // Bunched arrivals, 30 fps capture; then a new track far along the RTP timeline
+        for (pts, arrival_ms) in [(9000u64, 0u64), (12000, 5), (15000, 10), (9_000_000, 200)] {
+            let mut f = frame(0);
+            f.timing.pts = pts;
+            f.timing.arrival = Some(start + Duration::from_millis(arrival_ms));
+            f.frame_rate = Some((25, 1));
+            clock.place(&mut f, start);
+            placed.push(f.timing);
+        }
+        let at = |i: usize| placed[i].presentation.unwrap() - start;
+        assert_eq!((at(0), at(1), at(2)), (Duration::ZERO, Duration::from_micros(33_333), Duration::from_micros(66_666)));
+        // Re-anchored at its own arrival
+        assert_eq!(at(3), Duration::from_millis(200));
+        assert_eq!(placed[0].duration, Duration::from_millis(40));
+    }
+
+    #[test]
+    fn capture_clock_places_earlier_pts_before_the_anchor() {
+        let mut clock = CaptureClock::new();
+        let start = Instant::now() + Duration::from_secs(1);
+        let (mut a, mut b) = (frame(0), frame(0));
+        a.timing.pts = 6000;
+        b.timing.pts = 3000;
+        clock.place(&mut a, start);
+        clock.place(&mut b, start);
+        assert_eq!(start - b.timing.presentation.unwrap(), Duration::from_micros(33_333));
+        assert_eq!(b.timing.duration, Duration::from_nanos(33_333_333));
+    }
+}
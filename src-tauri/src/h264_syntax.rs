@@ -0,0 +1,677 @@
+// Just enough H.264 bitstream syntax for the receive path: Annex-B NAL splitting,
+// RBSP/exp-Golomb reading, and the SPS/PPS/slice-header fields we act on (including the
+// picture size after cropping and the VUI frame rate). Plus the writing side for the one
+// picture we synthesize: an all-skip P picture that repeats the previous one.
+
+pub const NAL_SLICE: u8 = 1;
+pub const NAL_IDR: u8 = 5;
+pub const NAL_SEI: u8 = 6;
+pub const NAL_SPS: u8 = 7;
+pub const NAL_PPS: u8 = 8;
+
+/// SEI payload type for recovery point (H.264 D.1.8)
+const SEI_RECOVERY_POINT: u32 = 6;
+
+pub fn nal_type(nal: &[u8]) -> u8 {
+    nal.first().map_or(0, |h| h & 0x1F)
+}
+
+pub fn nal_ref_idc(nal: &[u8]) -> u8 {
+    nal.first().map_or(0, |h| (h >> 5) & 0x03)
+}
+
+/// Iterate the NAL units of an Annex-B byte stream (3- or 4-byte start codes).
+pub fn nal_units(data: &[u8]) -> NalUnits<'_> {
+    NalUnits { data, pos: 0 }
+}
+
+pub struct NalUnits<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+fn start_code_end(data: &[u8], from: usize) -> Option<usize> {
+    (from..data.len().saturating_sub(2))
+        .find(|&i| data[i] == 0 && data[i + 1] == 0 && data[i + 2] == 1)
+        .map(|i| i + 3)
+}
+
+impl<'a> Iterator for NalUnits<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        let begin = start_code_end(self.data, self.pos)?;
+        let mut end = match start_code_end(self.data, begin) {
+            Some(next) => next - 3,
+            None => self.data.len(),
+        };
+        self.pos = end;
+        // NAL units never end in 0x00; those belong to the next 4-byte start code
+        while end > begin && self.data[end - 1] == 0 {
+            end -= 1;
+        }
+        Some(&self.data[begin..end])
+    }
+}
+
+/// Strip emulation-prevention bytes (00 00 03 -> 00 00) from a NAL payload.
+pub fn rbsp(nal: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(nal.len());
+    let mut zeros = 0;
+    for &b in nal {
+        if zeros >= 2 && b == 3 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if b == 0 { zeros + 1 } else { 0 };
+        out.push(b);
+    }
+    out
+}
+
+/// Insert emulation-prevention bytes into an RBSP (00 00 0x -> 00 00 03 0x for x <= 3).
+pub fn escape(rbsp: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(rbsp.len() + rbsp.len() / 64);
+    let mut zeros = 0;
+    for &b in rbsp {
+        if zeros >= 2 && b <= 3 {
+            out.push(3);
+            zeros = 0;
+        }
+        zeros = if b == 0 { zeros + 1 } else { 0 };
+        out.push(b);
+    }
+    out
+}
+
+/// MSB-first bit reader with exp-Golomb support, over an RBSP.
+pub struct BitReader<'a> {
+    data: &'a [u8],
+    bit: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, bit: 0 }
+    }
+
+    pub fn read_bit(&mut self) -> Option<bool> {
+        let byte = *self.data.get(self.bit / 8)?;
+        let value = (byte >> (7 - self.bit % 8)) & 1;
+        self.bit += 1;
+        Some(value == 1)
+    }
+
+    pub fn read_bits(&mut self, n: u32) -> Option<u32> {
+        let mut value = 0u32;
+        for _ in 0..n {
+            value = (value << 1) | self.read_bit()? as u32;
+        }
+        Some(value)
+    }
+
+    pub fn skip(&mut self, n: usize) -> Option<()> {
+        self.bit += n;
+        (self.bit <= self.data.len() * 8).then_some(())
+    }
+
+    /// Unsigned exp-Golomb, ue(v)
+    pub fn read_ue(&mut self) -> Option<u32> {
+        let mut leading_zeros = 0;
+        while !self.read_bit()? {
+            leading_zeros += 1;
+            if leading_zeros > 31 {
+                return None;
+            }
+        }
+        Some((1u32 << leading_zeros) - 1 + self.read_bits(leading_zeros)?)
+    }
+
+    /// Signed exp-Golomb, se(v)
+    pub fn read_se(&mut self) -> Option<i32> {
+        let k = self.read_ue()? as i64;
+        let value = if k % 2 == 1 { (k + 1) / 2 } else { -(k / 2) };
+        Some(value as i32)
+    }
+}
+
+/// MSB-first bit writer with exp-Golomb support, producing an RBSP.
+#[derive(Default)]
+pub struct BitWriter {
+    data: Vec<u8>,
+    bit: usize,
+}
+
+impl BitWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write_bit(&mut self, bit: bool) {
+        if self.bit.is_multiple_of(8) {
+            self.data.push(0);
+        }
+        if bit {
+            *self.data.last_mut().unwrap() |= 0x80 >> (self.bit % 8);
+        }
+        self.bit += 1;
+    }
+
+    pub fn write_bits(&mut self, value: u32, n: u32) {
+        for i in (0..n).rev() {
+            self.write_bit((value >> i) & 1 == 1);
+        }
+    }
+
+    /// Unsigned exp-Golomb, ue(v)
+    pub fn write_ue(&mut self, value: u32) {
+        let code = value as u64 + 1;
+        let len = 64 - code.leading_zeros();
+        self.write_bits(0, len - 1);
+        for i in (0..len).rev() {
+            self.write_bit((code >> i) & 1 == 1);
+        }
+    }
+
+    /// Signed exp-Golomb, se(v)
+    pub fn write_se(&mut self, value: i32) {
+        let k = if value > 0 { 2 * value as i64 - 1 } else { -2 * value as i64 };
+        self.write_ue(k as u32);
+    }
+
+    /// Append `rbsp_trailing_bits()` and return the RBSP.
+    pub fn finish(mut self) -> Vec<u8> {
+        self.write_bit(true);
+        self.data
+    }
+}
+
+/// The sequence parameter set fields the receive path needs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sps {
+    pub separate_colour_plane: bool,
+    /// 0 for monochrome or separate colour planes, else chroma_format_idc
+    pub chroma_array_type: u32,
+    pub log2_max_frame_num: u32,
+    pub pic_order_cnt_type: u32,
+    /// Only meaningful for `pic_order_cnt_type` 0
+    pub log2_max_pic_order_cnt_lsb: u32,
+    /// Only meaningful for `pic_order_cnt_type` 1
+    pub delta_pic_order_always_zero: bool,
+    pub frame_mbs_only: bool,
+    /// Macroblocks in a frame
+    pub pic_size_in_mbs: u32,
+    /// Display size in pixels, after the cropping window
+    pub width: u32,
+    pub height: u32,
+    /// Frames per second as (numerator, denominator), if the VUI has timing info
+    pub frame_rate: Option<(u32, u32)>,
+}
+
+fn skip_scaling_list(r: &mut BitReader, size: usize) -> Option<()> {
+    let mut last = 8i32;
+    let mut next = 8i32;
+    for _ in 0..size {
+        if next != 0 {
+            next = (last + r.read_se()? + 256) % 256;
+        }
+        if next != 0 {
+            last = next;
+        }
+    }
+    Some(())
+}
+
+impl Sps {
+    /// Parse an SPS NAL unit (including its one-byte header).
+    pub fn parse(nal: &[u8]) -> Option<Self> {
+        if nal_type(nal) != NAL_SPS {
+            return None;
+        }
+        let data = rbsp(&nal[1..]);
+        let mut r = BitReader::new(&data);
+
+        let profile_idc = r.read_bits(8)? as u8;
+        r.skip(16)?; // constraint flags, reserved bits, level_idc
+        r.read_ue()?; // seq_parameter_set_id
+
+        let mut separate_colour_plane = false;
+        let mut chroma_format_idc = 1;
+        if matches!(profile_idc, 100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135) {
+            chroma_format_idc = r.read_ue()?;
+            if chroma_format_idc == 3 {
+                separate_colour_plane = r.read_bit()?;
+            }
+            r.read_ue()?; // bit_depth_luma_minus8
+            r.read_ue()?; // bit_depth_chroma_minus8
+            r.skip(1)?; // qpprime_y_zero_transform_bypass_flag
+            if r.read_bit()? {
+                let lists = if chroma_format_idc == 3 { 12 } else { 8 };
+                for i in 0..lists {
+                    if r.read_bit()? {
+                        skip_scaling_list(&mut r, if i < 6 { 16 } else { 64 })?;
+                    }
+                }
+            }
+        }
+
+        let log2_max_frame_num = r.read_ue()? + 4;
+
+        let pic_order_cnt_type = r.read_ue()?;
+        let mut log2_max_pic_order_cnt_lsb = 0;
+        let mut delta_pic_order_always_zero = false;
+        if pic_order_cnt_type == 0 {
+            log2_max_pic_order_cnt_lsb = r.read_ue()? + 4;
+        } else if pic_order_cnt_type == 1 {
+            delta_pic_order_always_zero = r.read_bit()?;
+            r.read_se()?; // offset_for_non_ref_pic
+            r.read_se()?; // offset_for_top_to_bottom_field
+            for _ in 0..r.read_ue()? {
+                r.read_se()?; // offset_for_ref_frame
+            }
+        }
+        r.read_ue()?; // max_num_ref_frames
+        r.skip(1)?; // gaps_in_frame_num_value_allowed_flag
+
+        let width_in_mbs = r.read_ue()? + 1;
+        let height_in_map_units = r.read_ue()? + 1;
+        let frame_mbs_only = r.read_bit()?;
+        if !frame_mbs_only {
+            r.skip(1)?; // mb_adaptive_frame_field_flag
+        }
+        r.skip(1)?; // direct_8x8_inference_flag
+
+        let height_in_mbs = height_in_map_units * if frame_mbs_only { 1 } else { 2 };
+        let chroma_array_type = if separate_colour_plane { 0 } else { chroma_format_idc };
+
+        let mut width = width_in_mbs * 16;
+        let mut height = height_in_mbs * 16;
+        if r.read_bit()? {
+            // frame_cropping: offsets are in chroma sample units (7.4.2.1.1)
+            let (sub_width, sub_height) = match chroma_array_type {
+                1 => (2, 2),
+                2 => (2, 1),
+                _ => (1, 1),
+            };
+            let crop_x = sub_width;
+            let crop_y = sub_height * if frame_mbs_only { 1 } else { 2 };
+            let (left, right) = (r.read_ue()?, r.read_ue()?);
+            let (top, bottom) = (r.read_ue()?, r.read_ue()?);
+            width = width.saturating_sub(crop_x * (left + right));
+            height = height.saturating_sub(crop_y * (top + bottom));
+        }
+
+        let frame_rate = if r.read_bit()? { vui_frame_rate(&mut r) } else { None };
+
+        Some(Self {
+            separate_colour_plane,
+            chroma_array_type,
+            log2_max_frame_num,
+            pic_order_cnt_type,
+            log2_max_pic_order_cnt_lsb,
+            delta_pic_order_always_zero,
+            frame_mbs_only,
+            pic_size_in_mbs: width_in_mbs * height_in_mbs,
+            width,
+            height,
+            frame_rate,
+        })
+    }
+}
+
+/// Frame rate from the timing info of `vui_parameters()` (E.1.1). Two ticks per frame,
+/// since `time_scale / num_units_in_tick` counts fields.
+fn vui_frame_rate(r: &mut BitReader) -> Option<(u32, u32)> {
+    if r.read_bit()? {
+        // aspect_ratio_info
+        if r.read_bits(8)? == 255 {
+            r.skip(32)?; // sar_width, sar_height
+        }
+    }
+    if r.read_bit()? {
+        r.skip(1)?; // overscan_appropriate_flag
+    }
+    if r.read_bit()? {
+        // video_signal_type: video_format, video_full_range_flag
+        r.skip(4)?;
+        if r.read_bit()? {
+            r.skip(24)?; // colour_primaries, transfer_characteristics, matrix_coefficients
+        }
+    }
+    if r.read_bit()? {
+        r.read_ue()?; // chroma_sample_loc_type_top_field
+        r.read_ue()?; // chroma_sample_loc_type_bottom_field
+    }
+    if !r.read_bit()? {
+        return None; // no timing_info
+    }
+    let num_units_in_tick = r.read_bits(32)?;
+    let time_scale = r.read_bits(32)?;
+    (num_units_in_tick > 0 && time_scale > 0).then(|| (time_scale, num_units_in_tick.saturating_mul(2)))
+}
+
+/// The picture parameter set fields needed to write a slice header.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pps {
+    pub id: u32,
+    /// CABAC rather than CAVLC
+    pub entropy_coding_mode: bool,
+    pub bottom_field_pic_order_in_frame_present: bool,
+    pub num_slice_groups: u32,
+    pub weighted_pred: bool,
+    pub deblocking_filter_control_present: bool,
+    pub redundant_pic_cnt_present: bool,
+}
+
+impl Pps {
+    /// Parse a PPS NAL unit (including its one-byte header).
+    pub fn parse(nal: &[u8]) -> Option<Self> {
+        if nal_type(nal) != NAL_PPS {
+            return None;
+        }
+        let data = rbsp(&nal[1..]);
+        let mut r = BitReader::new(&data);
+
+        let id = r.read_ue()?;
+        r.read_ue()?; // seq_parameter_set_id
+        let entropy_coding_mode = r.read_bit()?;
+        let bottom_field_pic_order_in_frame_present = r.read_bit()?;
+        let num_slice_groups = r.read_ue()? + 1;
+        if num_slice_groups > 1 {
+            // FMO: the slice group map isn't needed, we never repeat such streams
+            return Some(Self {
+                id,
+                entropy_coding_mode,
+                bottom_field_pic_order_in_frame_present,
+                num_slice_groups,
+                weighted_pred: false,
+                deblocking_filter_control_present: false,
+                redundant_pic_cnt_present: false,
+            });
+        }
+        r.read_ue()?; // num_ref_idx_l0_default_active_minus1
+        r.read_ue()?; // num_ref_idx_l1_default_active_minus1
+        let weighted_pred = r.read_bit()?;
+        r.skip(2)?; // weighted_bipred_idc
+        r.read_se()?; // pic_init_qp_minus26
+        r.read_se()?; // pic_init_qs_minus26
+        r.read_se()?; // chroma_qp_index_offset
+        let deblocking_filter_control_present = r.read_bit()?;
+        r.skip(1)?; // constrained_intra_pred_flag
+        let redundant_pic_cnt_present = r.read_bit()?;
+
+        Some(Self {
+            id,
+            entropy_coding_mode,
+            bottom_field_pic_order_in_frame_present,
+            num_slice_groups,
+            weighted_pred,
+            deblocking_filter_control_present,
+            redundant_pic_cnt_present,
+        })
+    }
+}
+
+/// The leading fields of a slice header, up to the picture order count.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SliceHeader {
+    pub first_mb_in_slice: u32,
+    pub pps_id: u32,
+    pub frame_num: u32,
+    /// `pic_order_cnt_lsb`, for `pic_order_cnt_type` 0
+    pub pic_order_cnt_lsb: Option<u32>,
+}
+
+impl SliceHeader {
+    /// Parse the header of a slice NAL unit (type 1 or 5), using the active SPS.
+    pub fn parse(nal: &[u8], sps: &Sps) -> Option<Self> {
+        let idr = match nal_type(nal) {
+            NAL_SLICE => false,
+            NAL_IDR => true,
+            _ => return None,
+        };
+        // The fields we need sit within the first few bytes; avoid un-escaping the whole slice
+        let data = rbsp(&nal[1..nal.len().min(32)]);
+        let mut r = BitReader::new(&data);
+
+        let first_mb_in_slice = r.read_ue()?;
+        r.read_ue()?; // slice_type
+        let pps_id = r.read_ue()?;
+        if sps.separate_colour_plane {
+            r.skip(2)?; // colour_plane_id
+        }
+        let frame_num = r.read_bits(sps.log2_max_frame_num)?;
+        if !sps.frame_mbs_only && r.read_bit()? {
+            r.skip(1)?; // field_pic_flag set: bottom_field_flag
+        }
+        if idr {
+            r.read_ue()?; // idr_pic_id
+        }
+        let pic_order_cnt_lsb = match sps.pic_order_cnt_type {
+            0 => Some(r.read_bits(sps.log2_max_pic_order_cnt_lsb)?),
+            _ => None,
+        };
+
+        Some(Self { first_mb_in_slice, pps_id, frame_num, pic_order_cnt_lsb })
+    }
+}
+
+/// An Annex-B access unit holding one non-reference P slice in which every macroblock
+/// is skipped: decoders show it as an exact repeat of the last reference picture.
+/// `frame_num` must be the last reference picture's plus one; `pic_order_cnt_lsb` is
+/// only used for `pic_order_cnt_type` 0.
+///
+/// Only CAVLC streams without slice groups and with progressive frames are supported;
+/// `None` otherwise.
+pub fn skip_picture(sps: &Sps, pps: &Pps, frame_num: u32, pic_order_cnt_lsb: u32) -> Option<Vec<u8>> {
+    if pps.entropy_coding_mode || pps.num_slice_groups > 1 || sps.separate_colour_plane || !sps.frame_mbs_only {
+        return None;
+    }
+    let mut w = BitWriter::new();
+    // slice_header()
+    w.write_ue(0); // first_mb_in_slice
+    w.write_ue(5); // slice_type: P, all slices of the picture
+    w.write_ue(pps.id);
+    w.write_bits(frame_num % (1 << sps.log2_max_frame_num), sps.log2_max_frame_num);
+    match sps.pic_order_cnt_type {
+        0 => {
+            let lsb_bits = sps.log2_max_pic_order_cnt_lsb;
+            w.write_bits(pic_order_cnt_lsb % (1 << lsb_bits), lsb_bits);
+            if pps.bottom_field_pic_order_in_frame_present {
+                w.write_se(0); // delta_pic_order_cnt_bottom
+            }
+        }
+        1 if !sps.delta_pic_order_always_zero => {
+            w.write_se(0); // delta_pic_order_cnt[0]
+            if pps.bottom_field_pic_order_in_frame_present {
+                w.write_se(0); // delta_pic_order_cnt[1]
+            }
+        }
+        _ => {}
+    }
+    if pps.redundant_pic_cnt_present {
+        w.write_ue(0); // redundant_pic_cnt
+    }
+    w.write_bit(true); // num_ref_idx_active_override_flag
+    w.write_ue(0); // num_ref_idx_l0_active_minus1: only the last reference picture
+    w.write_bit(false); // ref_pic_list_modification_flag_l0
+    if pps.weighted_pred {
+        // pred_weight_table() with default weights
+        w.write_ue(0); // luma_log2_weight_denom
+        if sps.chroma_array_type != 0 {
+            w.write_ue(0); // chroma_log2_weight_denom
+        }
+        w.write_bit(false); // luma_weight_l0_flag
+        if sps.chroma_array_type != 0 {
+            w.write_bit(false); // chroma_weight_l0_flag
+        }
+    }
+    // nal_ref_idc 0: no dec_ref_pic_marking()
+    w.write_se(0); // slice_qp_delta
+    if pps.deblocking_filter_control_present {
+        w.write_ue(1); // disable_deblocking_filter_idc: nothing to filter
+    }
+    // slice_data(): one skip run over the whole picture
+    w.write_ue(sps.pic_size_in_mbs);
+
+    let mut access_unit = vec![0, 0, 0, 1, NAL_SLICE]; // nal_ref_idc 0
+    access_unit.extend_from_slice(&escape(&w.finish()));
+    Some(access_unit)
+}
+
+/// True if an SEI NAL unit carries a recovery point message.
+pub fn has_recovery_point(nal: &[u8]) -> bool {
+    if nal_type(nal) != NAL_SEI {
+        return false;
+    }
+    let data = rbsp(&nal[1..]);
+    let mut pos = 0;
+    // sei_message(): ff-extended payload type and size, then the payload
+    while pos < data.len() && data[pos] != 0x80 {
+        let mut payload_type = 0u32;
+        while pos < data.len() && data[pos] == 0xFF {
+            payload_type += 255;
+            pos += 1;
+        }
+        let Some(&last) = data.get(pos) else { return false };
+        payload_type += last as u32;
+        pos += 1;
+
+        let mut payload_size = 0usize;
+        while pos < data.len() && data[pos] == 0xFF {
+            payload_size += 255;
+            pos += 1;
+        }
+        let Some(&last) = data.get(pos) else { return false };
+        payload_size += last as usize;
+        pos += 1;
+
+        if payload_type == SEI_RECOVERY_POINT {
+            return true;
+        }
+        pos += payload_size;
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 640x480 constrained baseline, pic_order_cnt_type 2 (as in the depacketizer tests)
+    const SPS: [u8; 9] = [0x67, 0x42, 0xc0, 0x1e, 0xda, 0x02, 0x80, 0xf6, 0x84];
+    const PPS: [u8; 4] = [0x68, 0xce, 0x3c, 0x80];
+
+    fn slice(nal_type: u8, frame_num: u32, log2_max_frame_num: u32) -> Vec<u8> {
+        let mut w = BitWriter::new();
+        w.write_ue(0); // first_mb_in_slice
+        w.write_ue(if nal_type == NAL_IDR { 7 } else { 5 });
+        w.write_ue(0); // pic_parameter_set_id
+        w.write_bits(frame_num, log2_max_frame_num);
+        w.write_ue(0); // idr_pic_id, or whatever follows
+        let mut nal = vec![0x40 | nal_type];
+        nal.extend(escape(&w.finish()));
+        nal
+    }
+
+    #[test]
+    fn exp_golomb_round_trip() {
+        let mut w = BitWriter::new();
+        for v in [0, 1, 2, 7, 255, 1 << 20] {
+            w.write_ue(v);
+        }
+        for v in [0, 1, -1, 5, -100] {
+            w.write_se(v);
+        }
+        let data = w.finish();
+        let mut r = BitReader::new(&data);
+        for v in [0, 1, 2, 7, 255, 1 << 20] {
+            assert_eq!(r.read_ue(), Some(v));
+        }
+        for v in [0, 1, -1, 5, -100] {
+            assert_eq!(r.read_se(), Some(v));
+        }
+        assert_eq!(r.read_bit(), Some(true)); // rbsp_stop_one_bit
+    }
+
+    #[test]
+    fn escape_is_undone_by_rbsp() {
+        let raw = [0, 0, 0, 0, 0, 1, 0, 0, 3, 0, 0, 4, 0, 0];
+        let escaped = escape(&raw);
+        assert_eq!(escaped, [0, 0, 3, 0, 0, 3, 0, 1, 0, 0, 3, 3, 0, 0, 4, 0, 0]);
+        assert_eq!(rbsp(&escaped), raw);
+    }
+
+    #[test]
+    fn parameter_sets() {
+        let sps = Sps::parse(&SPS).unwrap();
+        assert_eq!((sps.width, sps.height, sps.pic_size_in_mbs), (640, 480, 1200));
+        assert_eq!((sps.log2_max_frame_num, sps.pic_order_cnt_type), (4, 2));
+        assert!(sps.frame_mbs_only);
+        assert_eq!(sps.chroma_array_type, 1);
+
+        let pps = Pps::parse(&PPS).unwrap();
+        assert_eq!(pps.id, 0);
+        assert!(!pps.entropy_coding_mode && !pps.weighted_pred && !pps.redundant_pic_cnt_present);
+        assert!(pps.deblocking_filter_control_present);
+        assert_eq!(pps.num_slice_groups, 1);
+        assert!(Pps::parse(&SPS).is_none());
+    }
+
+    #[test]
+    fn slice_header_frame_num() {
+        let mut sps = Sps::parse(&SPS).unwrap();
+        assert_eq!(SliceHeader::parse(&slice(NAL_SLICE, 9, 4), &sps).unwrap().frame_num, 9);
+
+        sps.log2_max_frame_num = 16;
+        let header = SliceHeader::parse(&slice(NAL_IDR, 0xfffe, 16), &sps).unwrap();
+        assert_eq!((header.first_mb_in_slice, header.pps_id, header.frame_num), (0, 0, 0xfffe));
+        assert_eq!(header.pic_order_cnt_lsb, None);
+
+        assert!(SliceHeader::parse(&SPS, &sps).is_none());
+        assert!(SliceHeader::parse(&[0x41], &sps).is_none());
+    }
+
+    #[test]
+    fn recovery_point_sei() {
+        assert!(has_recovery_point(&[0x06, 0x06, 0x01, 0xc4, 0x80]));
+        // After a user data message
+        assert!(has_recovery_point(&[0x06, 0x05, 0x02, 0xaa, 0xbb, 0x06, 0x01, 0xc4, 0x80]));
+        // Payload type 6 + 255, not a recovery point
+        assert!(!has_recovery_point(&[0x06, 0xff, 0x06, 0x01, 0x00, 0x80]));
+        assert!(!has_recovery_point(&[0x06, 0x05, 0x01, 0xaa, 0x80]));
+        // Truncated
+        assert!(!has_recovery_point(&[0x06, 0x05]));
+        assert!(!has_recovery_point(&[0x41, 0x06, 0x01, 0xc4]));
+    }
+
+    #[test]
+    fn skip_picture_covers_the_frame() {
+        let sps = Sps::parse(&SPS).unwrap();
+        let pps = Pps::parse(&PPS).unwrap();
+        let access_unit = skip_picture(&sps, &pps, 17, 0).unwrap();
+        let nals: Vec<_> = nal_units(&access_unit).collect();
+        assert_eq!(nals.len(), 1);
+        assert_eq!((nal_type(nals[0]), nal_ref_idc(nals[0])), (NAL_SLICE, 0));
+
+        let data = rbsp(&nals[0][1..]);
+        let mut r = BitReader::new(&data);
+        assert_eq!(r.read_ue(), Some(0)); // first_mb_in_slice
+        assert_eq!(r.read_ue(), Some(5)); // P
+        assert_eq!(r.read_ue(), Some(0)); // pic_parameter_set_id
+        assert_eq!(r.read_bits(4), Some(1)); // frame_num, wrapped
+        assert_eq!(r.read_bit(), Some(true)); // num_ref_idx_active_override_flag
+        assert_eq!(r.read_ue(), Some(0));
+        assert_eq!(r.read_bit(), Some(false)); // ref_pic_list_modification_flag_l0
+        assert_eq!(r.read_se(), Some(0)); // slice_qp_delta
+        assert_eq!(r.read_ue(), Some(1)); // disable_deblocking_filter_idc
+        assert_eq!(r.read_ue(), Some(1200)); // mb_skip_run
+        assert_eq!(r.read_bit(), Some(true)); // rbsp_stop_one_bit
+        while let Some(bit) = r.read_bit() {
+            assert!(!bit);
+        }
+
+        let cabac = Pps { entropy_coding_mode: true, ..pps };
+        assert_eq!(skip_picture(&sps, &cabac, 1, 0), None);
+    }
+}
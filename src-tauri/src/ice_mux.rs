@@ -0,0 +1,307 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::sync::mpsc;
+use webrtc::ice::udp_mux::{UDPMux, UDPMuxDefault, UDPMuxParams};
+use webrtc::util::Conn;
+
+use crate::settings::NetworkSettings;
+
+/// Long-lived single-port ICE transport shared by every WebRTC client session.
+pub struct IceMux {
+    mux: Arc<UDPMuxDefault>,
+    udp_port: u16,
+    tcp_port: Option<u16>,
+}
+
+impl IceMux {
+    /// Start the mux described by `network`, or `None` if single-port mode is off.
+    /// ICE-TCP rides on the UDP mux, so enabling it implies the mux.
+    pub async fn from_settings(network: &NetworkSettings) -> Result<Option<Self>> {
+        let tcp_port = network.ice_tcp_enabled.then_some(network.ice_tcp_port);
+        if !network.udp_mux_enabled && tcp_port.is_none() {
+            return Ok(None);
+        }
+        let socket = MuxSocket::bind(network.udp_mux_port, tcp_port).await?;
+        Ok(Some(Self {
+            mux: UDPMuxDefault::new(UDPMuxParams::new(socket)),
+            udp_port: network.udp_mux_port,
+            tcp_port,
+        }))
+    }
+
+    /// Whether this mux still reflects `network` (ports and enabled flags).
+    pub fn matches(&self, network: &NetworkSettings) -> bool {
+        let tcp_port = network.ice_tcp_enabled.then_some(network.ice_tcp_port);
+        (network.udp_mux_enabled || tcp_port.is_some())
+            && self.udp_port == network.udp_mux_port
+            && self.tcp_port == tcp_port
+    }
+
+    pub fn udp_mux(&self) -> Arc<UDPMuxDefault> {
+        self.mux.clone()
+    }
+
+    pub fn tcp_port(&self) -> Option<u16> {
+        self.tcp_port
+    }
+
+    pub async fn close(&self) {
+        if let Err(e) = self.mux.close().await {
+            eprintln!("[ICE Mux] Close error: {}", e);
+        }
+        println!("[ICE Mux] Stopped");
+    }
+}
+
+type TcpPeers = Arc<Mutex<HashMap<SocketAddr, mpsc::UnboundedSender<Vec<u8>>>>>;
+type Inbound = (Vec<u8>, SocketAddr);
+
+/// One fixed-port socket for all ICE traffic, so a firewall needs a single rule.
+///
+/// UDP datagrams are passed straight through. If ICE-TCP is enabled, passive TCP
+/// connections on a second fixed port are unframed (RFC 4571) and surfaced to the
+/// UDP mux as datagrams from the TCP peer's address; replies to that address go
+/// back down the same stream. The ICE agent just sees a peer-reflexive candidate.
+struct MuxSocket {
+    udp: UdpSocket,
+    tcp_peers: TcpPeers,
+    tcp_rx: tokio::sync::Mutex<mpsc::UnboundedReceiver<Inbound>>,
+    tcp_task: Mutex<Option<tokio::task::JoinHandle<()>>>,
+}
+
+impl MuxSocket {
+    /// Bind UDP on `0.0.0.0:udp_port` and, if given, a passive ICE-TCP listener on `tcp_port`.
+    async fn bind(udp_port: u16, tcp_port: Option<u16>) -> Result<Self> {
+        let udp = UdpSocket::bind(("0.0.0.0", udp_port)).await?;
+        let tcp_peers: TcpPeers = Arc::new(Mutex::new(HashMap::new()));
+        let (tcp_tx, tcp_rx) = mpsc::unbounded_channel();
+
+        let tcp_task = match tcp_port {
+            Some(port) => {
+                let listener = TcpListener::bind(("0.0.0.0", port)).await?;
+                println!("[ICE Mux] ICE-TCP listening on 0.0.0.0:{}", port);
+                Some(tokio::spawn(accept_loop(listener, tcp_peers.clone(), tcp_tx)))
+            }
+            None => None,
+        };
+
+        println!("[ICE Mux] UDP mux listening on 0.0.0.0:{}", udp_port);
+        Ok(Self {
+            udp,
+            tcp_peers,
+            tcp_rx: tokio::sync::Mutex::new(tcp_rx),
+            tcp_task: Mutex::new(tcp_task),
+        })
+    }
+}
+
+impl Drop for MuxSocket {
+    fn drop(&mut self) {
+        if let Some(task) = self.tcp_task.lock().unwrap().take() {
+            task.abort();
+        }
+    }
+}
+
+/// Trickle-ICE candidate line advertising the passive ICE-TCP listener.
+/// Priority follows RFC 6544 (host, passive direction) so it ranks below UDP host candidates.
+pub fn passive_tcp_candidate(ip: IpAddr, port: u16) -> String {
+    let local_pref: u32 = (1 << 13) * 4 + 8191;
+    let priority: u32 = (126 << 24) + (local_pref << 8) + 255;
+    format!("candidate:tcpmux 1 tcp {} {} {} typ host tcptype passive", priority, ip, port)
+}
+
+/// Prefix `packet` with its 16-bit length (RFC 4571), or `None` if it is too
+/// long for the prefix.
+fn frame_packet(packet: &[u8]) -> Option<Vec<u8>> {
+    let len = u16::try_from(packet.len()).ok()?;
+    let mut framed = Vec::with_capacity(packet.len() + 2);
+    framed.extend_from_slice(&len.to_be_bytes());
+    framed.extend_from_slice(packet);
+    Some(framed)
+}
+
+/// Read one RFC 4571 frame, however the stream splits it up.
+async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> std::io::Result<Vec<u8>> {
+    let mut len = [0u8; 2];
+    reader.read_exact(&mut len).await?;
+    let mut packet = vec![0u8; u16::from_be_bytes(len) as usize];
+    reader.read_exact(&mut packet).await?;
+    Ok(packet)
+}
+
+async fn accept_loop(listener: TcpListener, peers: TcpPeers, inbound: mpsc::UnboundedSender<Inbound>) {
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!("[ICE Mux] TCP accept error: {}", e);
+                continue;
+            }
+        };
+        println!("[ICE Mux] ICE-TCP connection from {}", addr);
+        let _ = stream.set_nodelay(true);
+        let (mut reader, mut writer) = stream.into_split();
+
+        let (tx, mut rx) = mpsc::unbounded_channel::<Vec<u8>>();
+        peers.lock().unwrap().insert(addr, tx);
+
+        // Writer: frame each outgoing packet with its 16-bit length (`send_to`
+        // only queues packets that fit)
+        tokio::spawn(async move {
+            while let Some(framed) = rx.recv().await {
+                if writer.write_all(&framed).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        // Reader: unframe and hand packets to the mux as if they were datagrams
+        let peers = peers.clone();
+        let inbound = inbound.clone();
+        tokio::spawn(async move {
+            while let Ok(packet) = read_frame(&mut reader).await {
+                if inbound.send((packet, addr)).is_err() {
+                    break;
+                }
+            }
+            peers.lock().unwrap().remove(&addr);
+            println!("[ICE Mux] ICE-TCP connection closed: {}", addr);
+        });
+    }
+}
+
+fn unsupported(what: &str) -> webrtc::util::Error {
+    std::io::Error::new(std::io::ErrorKind::Unsupported, format!("MuxSocket: {} unsupported", what)).into()
+}
+
+#[async_trait]
+impl Conn for MuxSocket {
+    async fn connect(&self, _addr: SocketAddr) -> webrtc::util::Result<()> {
+        Err(unsupported("connect"))
+    }
+
+    async fn recv(&self, buf: &mut [u8]) -> webrtc::util::Result<usize> {
+        let (n, _) = self.recv_from(buf).await?;
+        Ok(n)
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> webrtc::util::Result<(usize, SocketAddr)> {
+        let mut tcp_rx = self.tcp_rx.lock().await;
+        tokio::select! {
+            res = self.udp.recv_from(buf) => Ok(res?),
+            Some((packet, addr)) = tcp_rx.recv() => {
+                let n = packet.len().min(buf.len());
+                buf[..n].copy_from_slice(&packet[..n]);
+                Ok((n, addr))
+            }
+        }
+    }
+
+    async fn send(&self, _buf: &[u8]) -> webrtc::util::Result<usize> {
+        Err(unsupported("send without target"))
+    }
+
+    async fn send_to(&self, buf: &[u8], target: SocketAddr) -> webrtc::util::Result<usize> {
+        let tcp = self.tcp_peers.lock().unwrap().get(&target).cloned();
+        match tcp {
+            Some(tx) => {
+                let framed = frame_packet(buf).ok_or_else(|| {
+                    webrtc::util::Error::from(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        "packet too long for an ICE-TCP frame",
+                    ))
+                })?;
+                tx.send(framed).map_err(|_| {
+                    webrtc::util::Error::from(std::io::Error::from(std::io::ErrorKind::BrokenPipe))
+                })?;
+                Ok(buf.len())
+            }
+            None => Ok(self.udp.send_to(buf, target).await?),
+        }
+    }
+
+    fn local_addr(&self) -> webrtc::util::Result<SocketAddr> {
+        Ok(self.udp.local_addr()?)
+    }
+
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+
+    async fn close(&self) -> webrtc::util::Result<()> {
+        if let Some(task) = self.tcp_task.lock().unwrap().take() {
+            task.abort();
+        }
+        self.tcp_peers.lock().unwrap().clear();
+        Ok(())
+    }
+
+    fn as_any(&self) -> &(dyn std::any::Any + Send + Sync) {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn frames_survive_split_reads() {
+        let (mut client, mut server) = tokio::io::duplex(64);
+        let mut bytes = frame_packet(b"stun binding request").unwrap();
+        bytes.extend(frame_packet(b"").unwrap());
+        bytes.extend(frame_packet(&[7; 300]).unwrap());
+
+        // Dribble the stream in uneven pieces, splitting the length prefixes too
+        let writer = tokio::spawn(async move {
+            for chunk in bytes.chunks(3) {
+                client.write_all(chunk).await.unwrap();
+                tokio::task::yield_now().await;
+            }
+        });
+        assert_eq!(read_frame(&mut server).await.unwrap(), b"stun binding request");
+        assert_eq!(read_frame(&mut server).await.unwrap(), b"");
+        assert_eq!(read_frame(&mut server).await.unwrap(), vec![7; 300]);
+        writer.await.unwrap();
+
+        // Stream closed mid-frame
+        let (mut client, mut server) = tokio::io::duplex(64);
+        client.write_all(&[0, 10, 1, 2, 3]).await.unwrap();
+        drop(client);
+        assert!(read_frame(&mut server).await.is_err());
+    }
+
+    #[test]
+    fn oversized_packets_are_not_framed() {
+        let largest = frame_packet(&vec![1; u16::MAX as usize]).unwrap();
+        assert_eq!(&largest[..2], &[0xff, 0xff]);
+        assert_eq!(largest.len(), u16::MAX as usize + 2);
+        assert!(frame_packet(&vec![1; u16::MAX as usize + 1]).is_none());
+    }
+
+    #[tokio::test]
+    async fn oversized_packets_to_tcp_peers_are_rejected() {
+        let socket = MuxSocket::bind(0, None).await.unwrap();
+        let peer: SocketAddr = "192.168.1.20:50000".parse().unwrap();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        socket.tcp_peers.lock().unwrap().insert(peer, tx);
+
+        assert!(socket.send_to(&vec![0; 70_000], peer).await.is_err());
+        assert_eq!(socket.send_to(b"ok", peer).await.unwrap(), 2);
+        assert_eq!(rx.recv().await.unwrap(), [0, 2, b'o', b'k']);
+    }
+
+    #[test]
+    fn passive_tcp_candidate_line() {
+        assert_eq!(
+            passive_tcp_candidate("192.168.1.10".parse().unwrap(), 3003),
+            "candidate:tcpmux 1 tcp 2124414975 192.168.1.10 3003 typ host tcptype passive"
+        );
+    }
+}
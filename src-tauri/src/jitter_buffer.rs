@@ -0,0 +1,224 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use serde::Serialize;
+use webrtc::rtp::packet::Packet;
+
+/// Default time a packet may wait for a missing predecessor before we give up on it
+pub const DEFAULT_TARGET_LATENCY: Duration = Duration::from_millis(50);
+/// Hard cap on buffered packets (~0.5s of 1080p at high bitrate)
+const MAX_PACKETS: usize = 512;
+
+/// Counters reported by the jitter buffer, part of `PipelineStats`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JitterStats {
+    /// Packets that arrived after a higher sequence number but still in time
+    pub reordered: u64,
+    /// Packets that arrived after their slot was already released (discarded)
+    pub late: u64,
+    pub duplicates: u64,
+    /// Sequence numbers skipped because they never arrived within the target latency
+    pub lost: u64,
+}
+
+struct Buffered {
+    packet: Packet,
+    arrival: Instant,
+}
+
+/// A packet released in sequence order
+pub struct Released {
+    pub packet: Packet,
+    /// Extended (64-bit) sequence number
+    pub seq: u64,
+    pub arrival: Instant,
+}
+
+/// Reorders RTP packets by sequence number before they reach the depacketizer.
+///
+/// Sequence numbers are unwrapped to 64 bits so wraparound at 65535 is transparent.
+/// A packet is released as soon as all its predecessors have been released, or once
+/// it has waited `target_latency` for a gap that never filled.
+pub struct JitterBuffer {
+    packets: BTreeMap<u64, Buffered>,
+    /// Extended sequence number of the next packet to release
+    next_seq: Option<u64>,
+    /// Highest extended sequence number seen so far
+    highest: Option<u64>,
+    target_latency: Duration,
+    stats: JitterStats,
+}
+
+impl JitterBuffer {
+    pub fn new(target_latency: Duration) -> Self {
+        Self {
+            packets: BTreeMap::new(),
+            next_seq: None,
+            highest: None,
+            target_latency,
+            stats: JitterStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> JitterStats {
+        self.stats
+    }
+
+    /// Number of packets currently held
+    pub fn depth(&self) -> usize {
+        self.packets.len()
+    }
+
+    /// Extend a 16-bit sequence number relative to the highest one seen.
+    fn unwrap_seq(&self, seq: u16) -> u64 {
+        match self.highest {
+            None => seq as u64 + (1 << 16), // headroom so early reordering can't underflow
+            Some(highest) => {
+                let delta = seq.wrapping_sub(highest as u16) as i16;
+                (highest as i64 + delta as i64).max(0) as u64
+            }
+        }
+    }
+
+    pub fn push(&mut self, packet: Packet, now: Instant) {
+        let seq = self.unwrap_seq(packet.header.sequence_number);
+
+        if self.next_seq.is_some_and(|next| seq < next) {
+            self.stats.late += 1;
+            return;
+        }
+        if self.packets.contains_key(&seq) {
+            self.stats.duplicates += 1;
+            return;
+        }
+        match self.highest {
+            Some(highest) if seq < highest => self.stats.reordered += 1,
+            _ => self.highest = Some(seq),
+        }
+        if self.next_seq.is_none() {
+            self.next_seq = Some(seq);
+        }
+
+        self.packets.insert(seq, Buffered { packet, arrival: now });
+    }
+
+    /// Release the next packet in sequence order, if it is ready.
+    pub fn pop(&mut self, now: Instant) -> Option<Released> {
+        let (&first, oldest) = self.packets.iter().next()?;
+        let next = self.next_seq.unwrap_or(first);
+
+        // Skip over a gap once the packet after it has waited long enough
+        // (or the buffer is full), counting the missing ones as lost
+        if first != next {
+            let expired = now.duration_since(oldest.arrival) >= self.target_latency;
+            if !expired && self.packets.len() < MAX_PACKETS {
+                return None;
+            }
+            self.stats.lost += first - next;
+        }
+
+        let buffered = self.packets.remove(&first)?;
+        self.next_seq = Some(first + 1);
+        Some(Released { packet: buffered.packet, seq: first, arrival: buffered.arrival })
+    }
+
+    /// When the head-of-line packet will be released if its gap never fills.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let (&first, oldest) = self.packets.iter().next()?;
+        if self.next_seq == Some(first) {
+            Some(oldest.arrival)
+        } else {
+            Some(oldest.arrival + self.target_latency)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LATENCY: Duration = Duration::from_millis(50);
+
+    fn packet(seq: u16) -> Packet {
+        let mut packet = Packet::default();
+        packet.header.sequence_number = seq;
+        packet
+    }
+
+    /// Push `seqs` at `now`, releasing whatever is ready after each one
+    fn feed(jitter: &mut JitterBuffer, seqs: &[u16], now: Instant) -> Vec<u16> {
+        let mut released = Vec::new();
+        for &seq in seqs {
+            jitter.push(packet(seq), now);
+            while let Some(r) = jitter.pop(now) {
+                released.push(r.packet.header.sequence_number);
+            }
+        }
+        released
+    }
+
+    #[test]
+    fn sequence_numbers_wrap_at_65535() {
+        let mut jitter = JitterBuffer::new(LATENCY);
+        let now = Instant::now();
+        assert_eq!(feed(&mut jitter, &[65533, 65534, 65535, 0, 1], now), [65533, 65534, 65535, 0, 1]);
+        assert_eq!(jitter.stats(), JitterStats::default());
+
+        // Extended numbers keep counting up across the wrap
+        jitter.push(packet(2), now);
+        let released = jitter.pop(now).unwrap();
+        assert_eq!(released.seq, (1 << 16) + 65533 + 5);
+    }
+
+    #[test]
+    fn reorders_within_the_window() {
+        let mut jitter = JitterBuffer::new(LATENCY);
+        let now = Instant::now();
+        // 65535 and 0 swap places across the wrap, 3 arrives before 2
+        assert_eq!(feed(&mut jitter, &[65534, 0, 65535, 1, 3], now), [65534, 65535, 0, 1]);
+        assert_eq!(jitter.next_deadline(), Some(now + LATENCY));
+        assert_eq!(feed(&mut jitter, &[2], now), [2, 3]);
+        assert_eq!(jitter.stats(), JitterStats { reordered: 2, ..Default::default() });
+        assert_eq!(jitter.depth(), 0);
+    }
+
+    #[test]
+    fn packets_after_their_release_are_late() {
+        let mut jitter = JitterBuffer::new(LATENCY);
+        let now = Instant::now();
+        assert_eq!(feed(&mut jitter, &[10, 12], now), [10]);
+        // 11 never showed up in time
+        assert_eq!(jitter.pop(now + LATENCY).map(|r| r.packet.header.sequence_number), Some(12));
+        assert!(feed(&mut jitter, &[11, 9], now + LATENCY).is_empty());
+        assert_eq!(jitter.stats(), JitterStats { late: 2, lost: 1, ..Default::default() });
+    }
+
+    #[test]
+    fn duplicates_are_dropped() {
+        let mut jitter = JitterBuffer::new(LATENCY);
+        let now = Instant::now();
+        assert_eq!(feed(&mut jitter, &[1, 3, 3], now), [1]);
+        assert_eq!(feed(&mut jitter, &[2], now), [2, 3]);
+        // Already released: late, not a duplicate
+        assert!(feed(&mut jitter, &[3], now).is_empty());
+        assert_eq!(jitter.stats(), JitterStats { reordered: 1, duplicates: 1, late: 1, lost: 0 });
+    }
+
+    #[test]
+    fn gaps_are_skipped_after_the_target_latency() {
+        let mut jitter = JitterBuffer::new(LATENCY);
+        let start = Instant::now();
+        assert_eq!(feed(&mut jitter, &[100], start), [100]);
+        jitter.push(packet(104), start + Duration::from_millis(10));
+        jitter.push(packet(105), start + Duration::from_millis(20));
+
+        // Held until the packet after the gap has waited the full latency
+        assert!(jitter.pop(start + Duration::from_millis(59)).is_none());
+        assert_eq!(jitter.next_deadline(), Some(start + Duration::from_millis(60)));
+        let released = jitter.pop(start + Duration::from_millis(60)).unwrap();
+        assert_eq!(released.packet.header.sequence_number, 104);
+        assert_eq!(jitter.stats().lost, 3);
+        // The rest follows at once
+        assert_eq!(jitter.pop(start + Duration::from_millis(60)).map(|r| r.packet.header.sequence_number), Some(105));
+    }
+}
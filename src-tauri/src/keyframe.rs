@@ -0,0 +1,188 @@
+use std::sync::{Arc, LazyLock, Mutex, Weak};
+use std::time::{Duration, Instant};
+use webrtc::peer_connection::RTCPeerConnection;
+use webrtc::rtcp::payload_feedbacks::full_intra_request::{FirEntry, FullIntraRequest};
+use webrtc::rtcp::payload_feedbacks::picture_loss_indication::PictureLossIndication;
+
+/// Never send keyframe requests closer together than this
+const MIN_INTERVAL: Duration = Duration::from_millis(300);
+/// Re-send while a request is outstanding at this spacing
+const RETRY_INTERVAL: Duration = Duration::from_millis(1000);
+/// Switch from PLI to FIR if a request has gone unanswered this long
+const FIR_AFTER: Duration = Duration::from_millis(1500);
+
+#[derive(Default)]
+struct RequestState {
+    last_sent: Option<Instant>,
+    /// Set while we're waiting for the sender to produce an IDR
+    pending_since: Option<Instant>,
+    fir_seq: u8,
+}
+
+#[derive(Debug, PartialEq)]
+enum Feedback {
+    Pli,
+    Fir(u8),
+}
+
+impl RequestState {
+    /// The feedback to send for a request at `now`, or `None` if the last one
+    /// went out less than `MIN_INTERVAL` ago.
+    fn request(&mut self, now: Instant) -> Option<Feedback> {
+        if self.last_sent.is_some_and(|t| now.duration_since(t) < MIN_INTERVAL) {
+            return None;
+        }
+        let pending_since = *self.pending_since.get_or_insert(now);
+        self.last_sent = Some(now);
+        if now.duration_since(pending_since) >= FIR_AFTER {
+            self.fir_seq = self.fir_seq.wrapping_add(1);
+            Some(Feedback::Fir(self.fir_seq))
+        } else {
+            Some(Feedback::Pli)
+        }
+    }
+
+    /// An unanswered request is due to be sent again.
+    fn retry_due(&self, now: Instant) -> bool {
+        self.pending_since.is_some()
+            && self.last_sent.is_some_and(|t| now.duration_since(t) >= RETRY_INTERVAL)
+    }
+}
+
+/// Sends rate-limited RTCP keyframe requests for one incoming video track.
+///
+/// Starts with PLI; if no keyframe shows up within `FIR_AFTER` it escalates to FIR,
+/// which some encoders honour more reliably.
+pub struct KeyframeRequester {
+    pc: Weak<RTCPeerConnection>,
+    media_ssrc: u32,
+    state: Mutex<RequestState>,
+}
+
+/// Requester for the track currently feeding the virtual camera, for `request_keyframe`
+static ACTIVE: LazyLock<Mutex<Option<Arc<KeyframeRequester>>>> = LazyLock::new(|| Mutex::new(None));
+
+impl KeyframeRequester {
+    pub fn new(pc: Weak<RTCPeerConnection>, media_ssrc: u32) -> Self {
+        Self {
+            pc,
+            media_ssrc,
+            state: Mutex::new(RequestState::default()),
+        }
+    }
+
+    /// Make this the requester `request_active` talks to.
+    pub fn set_active(requester: &Arc<Self>) {
+        *ACTIVE.lock().unwrap() = Some(requester.clone());
+    }
+
+    /// Clear the active requester if it is still this one.
+    pub fn clear_active(requester: &Arc<Self>) {
+        let mut active = ACTIVE.lock().unwrap();
+        if active.as_ref().is_some_and(|a| Arc::ptr_eq(a, requester)) {
+            *active = None;
+        }
+    }
+
+    /// Ask for a keyframe. Dropped silently if the last request went out less than
+    /// `MIN_INTERVAL` ago.
+    pub async fn request(&self, reason: &str) {
+        let feedback = self.state.lock().unwrap().request(Instant::now());
+        if let Some(feedback) = feedback {
+            self.send(feedback, reason).await;
+        }
+    }
+
+    /// Re-send an outstanding request that hasn't been answered yet.
+    pub async fn poll(&self) {
+        let due = self.state.lock().unwrap().retry_due(Instant::now());
+        if due {
+            self.request("no keyframe yet").await;
+        }
+    }
+
+    /// A keyframe arrived; stop retrying.
+    pub fn keyframe_received(&self) {
+        self.state.lock().unwrap().pending_since = None;
+    }
+
+    async fn send(&self, feedback: Feedback, reason: &str) {
+        let Some(pc) = self.pc.upgrade() else { return };
+        let result = match feedback {
+            Feedback::Pli => {
+                println!("[Keyframe] PLI ssrc={} ({})", self.media_ssrc, reason);
+                pc.write_rtcp(&[Box::new(PictureLossIndication {
+                    sender_ssrc: 0,
+                    media_ssrc: self.media_ssrc,
+                })]).await
+            }
+            Feedback::Fir(seq) => {
+                println!("[Keyframe] FIR ssrc={} seq={} ({})", self.media_ssrc, seq, reason);
+                pc.write_rtcp(&[Box::new(FullIntraRequest {
+                    sender_ssrc: 0,
+                    media_ssrc: self.media_ssrc,
+                    fir: vec![FirEntry { ssrc: self.media_ssrc, sequence_number: seq }],
+                })]).await
+            }
+        };
+        if let Err(e) = result {
+            eprintln!("[Keyframe] write_rtcp error: {}", e);
+        }
+    }
+}
+
+/// Manually request a keyframe from the active track (used by the `request_keyframe` command).
+pub async fn request_active(reason: &str) -> Result<(), String> {
+    let requester = ACTIVE.lock().map_err(|e| e.to_string())?.clone();
+    match requester {
+        Some(r) => {
+            r.request(reason).await;
+            Ok(())
+        }
+        None => Err("No active video track".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    #[test]
+    fn requests_are_rate_limited() {
+        let mut state = RequestState::default();
+        let start = Instant::now();
+        assert_eq!(state.request(start), Some(Feedback::Pli));
+        assert_eq!(state.request(start + ms(100)), None);
+        assert_eq!(state.request(start + ms(299)), None);
+        assert_eq!(state.request(start + ms(300)), Some(Feedback::Pli));
+
+        // Retries wait for RETRY_INTERVAL after the last one sent
+        assert!(!state.retry_due(start + ms(1299)));
+        assert!(state.retry_due(start + ms(1300)));
+        state.pending_since = None;
+        assert!(!state.retry_due(start + ms(5000)));
+    }
+
+    #[test]
+    fn unanswered_requests_escalate_to_fir() {
+        let mut state = RequestState::default();
+        let start = Instant::now();
+        assert_eq!(state.request(start), Some(Feedback::Pli));
+        assert_eq!(state.request(start + ms(1000)), Some(Feedback::Pli));
+        // FIR_AFTER without a keyframe: FIR, with a new sequence number each time
+        assert_eq!(state.request(start + ms(1500)), Some(Feedback::Fir(1)));
+        assert_eq!(state.request(start + ms(2500)), Some(Feedback::Fir(2)));
+
+        // A keyframe starts over at PLI; the FIR sequence carries on
+        state.pending_since = None;
+        assert_eq!(state.request(start + ms(3000)), Some(Feedback::Pli));
+        assert_eq!(state.request(start + ms(4500)), Some(Feedback::Fir(3)));
+
+        state.fir_seq = u8::MAX;
+        assert_eq!(state.request(start + ms(5000)), Some(Feedback::Fir(0)));
+    }
+}
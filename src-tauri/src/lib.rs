@@ -1,106 +1,169 @@
 use tokio::sync::mpsc;
 use std::sync::{Arc, Mutex};
-use std::sync::LazyLock;
 use std::collections::HashMap;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use warp::Filter;
 use futures::{StreamExt, SinkExt};
-use windows::Win32::Media::MediaFoundation::{IMFVirtualCamera, IMFMediaSource};
 
 mod virtual_cam;
 mod media_stream;
 mod webrtc_client;
-
-use virtual_cam::{register_virtual_camera, OpticLinkMediaSource};
-use media_stream::OpticLinkFrameSink;
+mod turn_relay;
+mod ice_mux;
+mod jitter_buffer;
+mod keyframe;
+mod h264_syntax;
+mod concealment;
+mod frame_timing;
+mod frame_pool;
+mod frame_bus;
+mod stream_meter;
+mod transport_stats;
+mod pipeline;
+mod codec_policy;
+mod bandwidth;
+mod recovery;
+mod negotiation;
+mod control;
+mod telemetry;
+mod watchdog;
+pub mod depacketizer;
+mod settings;
+
+use tauri::{Emitter, Manager};
+use control::{CameraState, ControlLink, ControlRequest, Facing, FocusMode, PhoneEvent};
+use pipeline::{PhoneLink, Pipeline, PipelineStatus, Transition};
+use recovery::Backoff;
+use settings::{NetworkSettings, StreamSettings, VideoSettings};
+use telemetry::{DeviceTelemetry, Telemetry, LOW_BATTERY_EVENT, TELEMETRY_EVENT};
+use transport_stats::TransportStats;
+use turn_relay::TurnRelay;
+use ice_mux::IceMux;
 
 // HTTP port (loopback only) — desktop WebSocket + Rust WebRTC client
 pub const HTTP_PORT: u16 = 3001;
 // HTTPS port (all interfaces) — phone app + phone WebSocket
 pub const HTTPS_PORT: u16 = 3002;
 
-struct SendVirtualCamera(IMFVirtualCamera);
-unsafe impl Send for SendVirtualCamera {}
-unsafe impl Sync for SendVirtualCamera {}
+/// A client session that lasted this long resets the reconnect backoff
+const STABLE_SESSION: std::time::Duration = std::time::Duration::from_secs(30);
 
-struct SendIMFMediaSource(IMFMediaSource);
-unsafe impl Send for SendIMFMediaSource {}
-unsafe impl Sync for SendIMFMediaSource {}
+static NEXT_USER_ID: AtomicUsize = AtomicUsize::new(1);
+type Users = Arc<Mutex<HashMap<usize, mpsc::UnboundedSender<warp::ws::Message>>>>;
+
+// ─── Tauri commands ──────────────────────────────────────────────────────────
 
-struct VirtualCamState {
-    active: bool,
-    frames_processed: u64,
-    sink: Option<OpticLinkFrameSink>,
-    _cam: Option<SendVirtualCamera>,
-    _source: Option<SendIMFMediaSource>,
+/// Current state; changes after this arrive as `pipeline-state` events.
+#[tauri::command]
+fn get_virtual_cam_status(pipeline: tauri::State<'_, Pipeline>) -> PipelineStatus {
+    pipeline.status()
 }
 
-static VCAM_STATE: LazyLock<Mutex<VirtualCamState>> = LazyLock::new(|| {
-    Mutex::new(VirtualCamState {
-        active: false,
-        frames_processed: 0,
-        sink: None,
-        _cam: None,
-        _source: None,
-    })
-});
+#[tauri::command]
+async fn start_virtual_cam(pipeline: tauri::State<'_, Pipeline>) -> Result<Transition, String> {
+    pipeline.start_camera()
+}
 
-static NEXT_USER_ID: AtomicUsize = AtomicUsize::new(1);
-type Users = Arc<Mutex<HashMap<usize, mpsc::UnboundedSender<warp::ws::Message>>>>;
+#[tauri::command]
+async fn stop_virtual_cam(pipeline: tauri::State<'_, Pipeline>) -> Result<Transition, String> {
+    Ok(pipeline.stop_camera())
+}
 
-// ─── Tauri commands ──────────────────────────────────────────────────────────
+/// RTT, jitter, loss, feedback counts and the selected candidate pair, with up to
+/// five minutes of history for graphs.
+#[tauri::command]
+fn get_transport_stats(pipeline: tauri::State<'_, Pipeline>) -> TransportStats {
+    pipeline.transport_stats()
+}
 
+/// The phone's last report: battery, CPU pressure, camera and capture fps, or
+/// `None` if it hasn't sent one. New reports arrive as `device-telemetry` events.
 #[tauri::command]
-fn get_virtual_cam_status() -> Result<String, String> {
-    let state = VCAM_STATE.lock().map_err(|e| e.to_string())?;
-    Ok(serde_json::json!({
-        "active": state.active,
-        "frames": state.frames_processed
-    }).to_string())
+fn get_device_telemetry(telemetry: tauri::State<'_, Arc<Telemetry>>) -> Option<DeviceTelemetry> {
+    telemetry.latest(std::time::Instant::now())
 }
 
+/// Ask the phone for a fresh keyframe (manual recovery from a smeared picture).
 #[tauri::command]
-async fn start_virtual_cam() -> Result<(), String> {
-    let mut state = VCAM_STATE.lock().map_err(|e| e.to_string())?;
-    if state.active {
-        return Ok(());
+async fn request_keyframe() -> Result<(), String> {
+    keyframe::request_active("manual").await
+}
+
+// ─── Phone camera controls ───────────────────────────────────────────────────
+// Each returns the camera's capability ranges and settings as they are afterwards,
+// so the UI can build its sliders from the reply.
+
+/// Validate a number before it goes to the phone
+fn finite(name: &str, value: f64) -> Result<f64, String> {
+    if value.is_finite() {
+        Ok(value)
+    } else {
+        Err(format!("{} must be a number", name))
     }
+}
 
-    println!("[VCam] Registering Virtual Camera...");
-    let (source, sink) = OpticLinkMediaSource::new().map_err(|e| e.to_string())?;
-    let cam = register_virtual_camera(&source).map_err(|e| {
-        format!(
-            "MFCreateVirtualCamera failed: {}. \
-             Virtual Camera requires Windows 11 build 22000+ and a registered COM server. \
-             This feature needs additional system setup — see the docs for details.",
-            e
-        )
-    })?;
-
-    state.sink = Some(sink);
-    state._cam = Some(SendVirtualCamera(cam));
-    state._source = Some(SendIMFMediaSource(source));
-    state.active = true;
-    state.frames_processed = 0;
-
-    println!("[VCam] Virtual Camera started.");
-    Ok(())
+#[tauri::command]
+async fn get_camera_state(control: tauri::State<'_, Arc<ControlLink>>) -> Result<CameraState, String> {
+    control.request(ControlRequest::GetCameraState).await
+}
+
+/// Switch to the camera facing `facing`, or to the other one if `None`.
+#[tauri::command]
+async fn switch_camera(
+    control: tauri::State<'_, Arc<ControlLink>>,
+    facing: Option<Facing>,
+) -> Result<CameraState, String> {
+    control.request(ControlRequest::SwitchCamera { facing }).await
+}
+
+#[tauri::command]
+async fn set_zoom(control: tauri::State<'_, Arc<ControlLink>>, zoom: f64) -> Result<CameraState, String> {
+    let zoom = finite("Zoom", zoom)?;
+    control.request(ControlRequest::SetZoom { zoom }).await
+}
+
+#[tauri::command]
+async fn set_torch(control: tauri::State<'_, Arc<ControlLink>>, on: bool) -> Result<CameraState, String> {
+    control.request(ControlRequest::SetTorch { on }).await
+}
+
+#[tauri::command]
+async fn set_focus_mode(
+    control: tauri::State<'_, Arc<ControlLink>>,
+    mode: FocusMode,
+) -> Result<CameraState, String> {
+    control.request(ControlRequest::SetFocusMode { mode }).await
 }
 
+/// `value` in EV stops, within the camera's `exposureCompensation` range.
 #[tauri::command]
-async fn stop_virtual_cam() -> Result<(), String> {
-    let mut state = VCAM_STATE.lock().map_err(|e| e.to_string())?;
-    if !state.active {
-        return Ok(());
+async fn set_exposure_compensation(
+    control: tauri::State<'_, Arc<ControlLink>>,
+    value: f64,
+) -> Result<CameraState, String> {
+    let value = finite("Exposure compensation", value)?;
+    control.request(ControlRequest::SetExposureCompensation { value }).await
+}
+
+#[tauri::command]
+async fn set_resolution(
+    control: tauri::State<'_, Arc<ControlLink>>,
+    width: u32,
+    height: u32,
+) -> Result<CameraState, String> {
+    if width == 0 || height == 0 {
+        return Err("Resolution must not be zero".to_string());
     }
+    control.request(ControlRequest::SetResolution { width, height }).await
+}
 
-    println!("[VCam] Stopping Virtual Camera...");
-    state.sink = None;
-    state._cam = None;
-    state._source = None;
-    state.active = false;
-    println!("[VCam] Virtual Camera stopped.");
-    Ok(())
+#[tauri::command]
+async fn set_framerate(control: tauri::State<'_, Arc<ControlLink>>, fps: f64) -> Result<CameraState, String> {
+    let fps = finite("Frame rate", fps)?;
+    if fps <= 0.0 {
+        return Err("Frame rate must be positive".to_string());
+    }
+    control.request(ControlRequest::SetFramerate { fps }).await
 }
 
 #[tauri::command]
@@ -124,6 +187,51 @@ fn get_connection_info() -> Result<String, String> {
     }).to_string())
 }
 
+#[tauri::command]
+fn get_network_settings() -> NetworkSettings {
+    settings::network()
+}
+
+/// Takes effect the next time the WebRTC client reconnects.
+#[tauri::command]
+fn set_network_settings(network: NetworkSettings) -> Result<(), String> {
+    settings::set_network(network)
+}
+
+#[tauri::command]
+fn get_video_settings() -> VideoSettings {
+    settings::video()
+}
+
+/// Codecs apply on reconnect and the output format the next time the virtual camera
+/// starts. Receive limits are re-offered to a connected phone right away, and the
+/// bitrate cap also goes straight to its encoder.
+#[tauri::command]
+async fn set_video_settings(
+    pipeline: tauri::State<'_, Pipeline>,
+    control: tauri::State<'_, Arc<ControlLink>>,
+    video: VideoSettings,
+) -> Result<(), String> {
+    let max_bitrate_kbps = video.codec_policy.max_bitrate_kbps;
+    settings::set_video(video)?;
+    pipeline.request_offer();
+    if let Err(e) = control.request::<()>(ControlRequest::SetQuality { max_bitrate_kbps }).await {
+        println!("[Control] Bitrate cap not sent to the phone: {}", e);
+    }
+    Ok(())
+}
+
+#[tauri::command]
+fn get_stream_settings() -> StreamSettings {
+    settings::stream()
+}
+
+/// Takes effect right away.
+#[tauri::command]
+fn set_stream_settings(stream: StreamSettings) -> Result<(), String> {
+    settings::set_stream(stream)
+}
+
 // ─── Signaling server ────────────────────────────────────────────────────────
 
 async fn user_connected(ws: warp::ws::WebSocket, users: Users) {
@@ -203,7 +311,11 @@ fn generate_self_signed_cert(local_ip: &str) -> Result<(Vec<u8>, Vec<u8>), Strin
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
-        .setup(|_app| {
+        .setup(|app| {
+            if let Ok(dir) = app.path().app_config_dir() {
+                settings::load(dir.join("settings.json"));
+            }
+
             let local_ip = local_ip_address::local_ip()
                 .map(|ip| ip.to_string())
                 .unwrap_or_else(|_| "127.0.0.1".to_string());
@@ -291,39 +403,104 @@ pub fn run() {
             }
 
             // ── WebRTC frame pipeline ────────────────────────────────────────
-            let (frame_tx, mut frame_rx) = mpsc::unbounded_channel::<webrtc_client::VideoFrame>();
-
-            // Consumer: push decoded frames into the virtual camera sink
+            // Owns the frame bus and the virtual camera; it's managed state so
+            // commands, recorders, previews and the like can reach it. State and
+            // stats reach the UI as events.
+            let pipeline = Pipeline::new(app.handle().clone());
+            app.manage(pipeline.clone());
+            // Requests to the phone's camera, over the control data channel
+            let control = Arc::new(ControlLink::new());
+            app.manage(control.clone());
+
+            // Telemetry the phone pushes over the same channel
+            let telemetry = Arc::new(Telemetry::new());
+            app.manage(telemetry.clone());
+            let (events_tx, mut events) = mpsc::unbounded_channel();
+            control.set_events(events_tx);
+            let handle = app.handle().clone();
             tauri::async_runtime::spawn(async move {
-                println!("[Pipe] Frame consumer started");
-                while let Some(frame) = frame_rx.recv().await {
-                    let sink = {
-                        let state = VCAM_STATE.lock().unwrap();
-                        if state.active { state.sink.clone() } else { None }
-                    };
-
-                    if let Some(sink) = sink {
-                        if let Err(e) = sink.push_frame(frame.data) {
-                            eprintln!("[Pipe] push_frame error: {}", e);
-                        } else {
-                            if let Ok(mut state) = VCAM_STATE.lock() {
-                                state.frames_processed += 1;
-                            }
+                while let Some(event) = events.recv().await {
+                    let PhoneEvent::Telemetry(report) = event;
+                    let threshold = f64::from(settings::stream().low_battery_percent) / 100.0;
+                    let now = std::time::Instant::now();
+                    if let Some(warning) = telemetry.record(*report, threshold, now) {
+                        println!("[Telemetry] Phone battery low: {:.0}%", warning.level * 100.0);
+                        if let Err(e) = handle.emit(LOW_BATTERY_EVENT, warning) {
+                            eprintln!("[Telemetry] Failed to emit warning: {}", e);
+                        }
+                    }
+                    if let Some(latest) = telemetry.latest(now) {
+                        if let Err(e) = handle.emit(TELEMETRY_EVENT, latest) {
+                            eprintln!("[Telemetry] Failed to emit telemetry: {}", e);
                         }
                     }
                 }
             });
 
             // Rust WebRTC client (auto-reconnects, connects to HTTP loopback WS)
+            let relay_ip = local_ip_address::local_ip()
+                .unwrap_or(std::net::IpAddr::from([127, 0, 0, 1]));
             tauri::async_runtime::spawn(async move {
+                let mut turn_relay: Option<Arc<TurnRelay>> = None;
+                let mut ice_mux: Option<Arc<IceMux>> = None;
+                let mut backoff = Backoff::new(std::time::Duration::ZERO, std::time::Duration::ZERO);
                 loop {
-                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                    // Bring the TURN relay in line with the current settings
+                    let network = settings::network();
+                    let stale = turn_relay.as_ref().is_some_and(|r| {
+                        !network.turn_enabled || r.port() != network.turn_port
+                    });
+                    if stale {
+                        if let Some(relay) = turn_relay.take() {
+                            relay.close().await;
+                        }
+                    }
+                    if network.turn_enabled && turn_relay.is_none() {
+                        match TurnRelay::start(relay_ip, network.turn_port).await {
+                            Ok(relay) => turn_relay = Some(Arc::new(relay)),
+                            Err(e) => eprintln!("[TURN] Failed to start relay: {}", e),
+                        }
+                    }
+
+                    // Same for the single-port ICE mux (UDP mux + ICE-TCP)
+                    if ice_mux.as_ref().is_some_and(|m| !m.matches(&network)) {
+                        if let Some(mux) = ice_mux.take() {
+                            mux.close().await;
+                        }
+                    }
+                    if ice_mux.is_none() {
+                        match IceMux::from_settings(&network).await {
+                            Ok(mux) => ice_mux = mux.map(Arc::new),
+                            Err(e) => eprintln!("[ICE Mux] Failed to start: {}", e),
+                        }
+                    }
+
                     println!("[WebRTC Client] Starting...");
-                    if let Err(e) = webrtc_client::start_virtual_cam_client(frame_tx.clone()).await {
+                    let started = std::time::Instant::now();
+                    if let Err(e) = webrtc_client::start_virtual_cam_client(
+                        pipeline.clone(),
+                        control.clone(),
+                        turn_relay.clone(),
+                        ice_mux.clone(),
+                    ).await {
                         eprintln!("[WebRTC Client] Error: {}", e);
                     }
-                    println!("[WebRTC Client] Disconnected, retrying in 2s...");
-                    tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+                    pipeline.set_phone(PhoneLink::Absent);
+                    control.set_signaling(None);
+
+                    // Exponential with jitter, so a signaling server that is down
+                    // (or still starting) isn't hammered
+                    let network = settings::network();
+                    backoff.set_range(
+                        std::time::Duration::from_millis(network.reconnect_initial_ms as u64),
+                        std::time::Duration::from_millis(network.reconnect_max_ms as u64),
+                    );
+                    if started.elapsed() >= STABLE_SESSION {
+                        backoff.reset();
+                    }
+                    let delay = backoff.next_delay();
+                    println!("[WebRTC Client] Disconnected, retrying in {:.1}s...", delay.as_secs_f64());
+                    tokio::time::sleep(delay).await;
                 }
             });
 
@@ -334,8 +511,25 @@ pub fn run() {
             get_virtual_cam_status,
             start_virtual_cam,
             stop_virtual_cam,
+            request_keyframe,
+            get_transport_stats,
+            get_device_telemetry,
+            get_camera_state,
+            switch_camera,
+            set_zoom,
+            set_torch,
+            set_focus_mode,
+            set_exposure_compensation,
+            set_resolution,
+            set_framerate,
             get_ip,
-            get_connection_info
+            get_connection_info,
+            get_network_settings,
+            set_network_settings,
+            get_video_settings,
+            set_video_settings,
+            get_stream_settings,
+            set_stream_settings
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
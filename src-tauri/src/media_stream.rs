@@ -4,12 +4,86 @@ use windows::Win32::Foundation::*;
 use windows::Win32::System::Com::StructuredStorage::PROPVARIANT;
 use std::sync::{Arc, Mutex};
 use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use bytes::Bytes;
 
 use windows::core::implement;
 
+use crate::depacketizer::Codec;
+use crate::webrtc_client::{VideoFormat, VideoFrame};
+
+/// Media Foundation time unit (100 ns) per second
+const MF_UNITS_PER_SECOND: i64 = 10_000_000;
+
+/// A duration in Media Foundation time units
+fn mf_units(duration: Duration) -> i64 {
+    (duration.as_nanos() / 100) as i64
+}
+
+/// A frame waiting for a sample request, with its timing already on the MF clock
+struct PendingSample {
+    data: Bytes,
+    /// Sample time and duration in 100 ns units
+    time: i64,
+    duration: i64,
+    clean_point: bool,
+}
+
 pub struct SharedState {
     requests: VecDeque<()>,
-    frame_buffer: Option<Vec<u8>>,
+    frame_buffer: Option<PendingSample>,
+    /// Format currently advertised to apps
+    format: VideoFormat,
+}
+
+impl SharedState {
+    /// Move a frame's presentation time, placed on the local clock by the pipeline,
+    /// onto the MF clock.
+    fn pending_sample(&mut self, frame: VideoFrame) -> PendingSample {
+        let now = Instant::now();
+        let system_time = unsafe { MFGetSystemTime() };
+        let time = match frame.timing.presentation {
+            Some(at) if at >= now => system_time + mf_units(at - now),
+            Some(at) => system_time - mf_units(now - at),
+            None => system_time,
+        };
+        let duration = if frame.timing.duration.is_zero() {
+            let (rate_num, rate_den) = self.format.frame_rate.unwrap_or((30, 1));
+            MF_UNITS_PER_SECOND * rate_den as i64 / rate_num.max(1) as i64
+        } else {
+            mf_units(frame.timing.duration)
+        };
+        PendingSample {
+            data: frame.data,
+            time,
+            duration,
+            clean_point: frame.keyframe,
+        }
+    }
+}
+
+/// The sample handed to apps. Its buffer is a copy in memory Media Foundation owns:
+/// whoever holds the sample may write through `Lock`, which the shared frame, still
+/// held by other consumers, must never see.
+fn create_sample(pending: &PendingSample) -> Result<IMFSample> {
+    let frame = &pending.data;
+    unsafe {
+        let buffer = MFCreateMemoryBuffer(frame.len() as u32)?;
+        let mut ptr = std::ptr::null_mut();
+        buffer.Lock(&mut ptr, None, None)?;
+        std::ptr::copy_nonoverlapping(frame.as_ptr(), ptr, frame.len());
+        buffer.Unlock()?;
+        buffer.SetCurrentLength(frame.len() as u32)?;
+
+        let sample = MFCreateSample()?;
+        sample.AddBuffer(&buffer)?;
+        sample.SetSampleTime(pending.time)?;
+        sample.SetSampleDuration(pending.duration)?;
+        if pending.clean_point {
+            sample.SetUINT32(&MFSampleExtension_CleanPoint, 1)?;
+        }
+        Ok(sample)
+    }
 }
 
 #[derive(Clone)]
@@ -22,12 +96,48 @@ unsafe impl Send for OpticLinkFrameSink {}
 unsafe impl Sync for OpticLinkFrameSink {}
 
 impl OpticLinkFrameSink {
-    pub fn push_frame(&self, frame: Vec<u8>) -> Result<()> {
+    pub fn format(&self) -> VideoFormat {
+        self.state.lock().unwrap().format
+    }
+
+    /// Follow a change in the phone's picture size or frame rate: the stream
+    /// descriptor reports the new format from now on, and apps already streaming get
+    /// MEStreamFormatChanged with the new media type. The codec stays the one the
+    /// stream was created with.
+    pub fn set_format(&self, format: VideoFormat) -> Result<()> {
         let mut state = self.state.lock().unwrap();
+        let format = VideoFormat {
+            codec: state.format.codec,
+            frame_rate: format.frame_rate.or(state.format.frame_rate),
+            ..format
+        };
+        if state.format == format {
+            return Ok(());
+        }
+        state.format = format;
+        // A buffered frame belongs to the old format
+        state.frame_buffer = None;
+        drop(state);
+
+        let media_type = create_media_type(&format)?;
+        unsafe {
+            self.event_queue.QueueEventParamUnk(
+                MEStreamFormatChanged.0 as u32,
+                &GUID::zeroed(),
+                S_OK,
+                Some(&media_type.cast()?),
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn push_frame(&self, frame: VideoFrame) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let pending = state.pending_sample(frame);
 
         if let Some(_token) = state.requests.pop_front() {
             drop(state);
-            let sample = self.create_sample(&frame)?;
+            let sample = create_sample(&pending)?;
             unsafe {
                 self.event_queue.QueueEventParamUnk(
                     MEMediaSample.0 as u32,
@@ -37,28 +147,10 @@ impl OpticLinkFrameSink {
                 )?;
             }
         } else {
-            state.frame_buffer = Some(frame);
+            state.frame_buffer = Some(pending);
         }
         Ok(())
     }
-
-    fn create_sample(&self, frame: &[u8]) -> Result<IMFSample> {
-        unsafe {
-            let sample = MFCreateSample()?;
-            let buffer = MFCreateMemoryBuffer(frame.len() as u32)?;
-
-            let mut ptr = std::ptr::null_mut();
-            let mut _len = 0;
-            let mut _current_len = 0;
-            buffer.Lock(&mut ptr, Some(&mut _len), Some(&mut _current_len))?;
-            std::ptr::copy_nonoverlapping(frame.as_ptr(), ptr, frame.len());
-            buffer.Unlock()?;
-            buffer.SetCurrentLength(frame.len() as u32)?;
-
-            sample.AddBuffer(&buffer)?;
-            Ok(sample)
-        }
-    }
 }
 
 #[implement(IMFMediaStream, IMFMediaEventGenerator)]
@@ -67,30 +159,45 @@ pub struct OpticLinkMediaStream {
     state: Arc<Mutex<SharedState>>,
 }
 
-fn create_h264_stream_descriptor() -> Result<IMFStreamDescriptor> {
+/// Media type for passing frames of `format` straight through (H.264 or HEVC).
+fn create_media_type(format: &VideoFormat) -> Result<IMFMediaType> {
+    let subtype = match format.codec {
+        Codec::H265 => MFVideoFormat_HEVC,
+        Codec::H264 => MFVideoFormat_H264,
+        // No transcoder: the camera output reports these before they get here
+        _ => return Err(Error::from(MF_E_INVALIDMEDIATYPE)),
+    };
+    let (rate_num, rate_den) = format.frame_rate.unwrap_or((30, 1));
     unsafe {
         let media_type = MFCreateMediaType()?;
         media_type.SetGUID(&MF_MT_MAJOR_TYPE, &MFMediaType_Video)?;
-        media_type.SetGUID(&MF_MT_SUBTYPE, &MFVideoFormat_H264)?;
-        media_type.SetUINT64(&MF_MT_FRAME_SIZE, (1920u64 << 32) | 1080u64)?;
-        media_type.SetUINT64(&MF_MT_FRAME_RATE, (30u64 << 32) | 1u64)?;
+        media_type.SetGUID(&MF_MT_SUBTYPE, &subtype)?;
+        media_type.SetUINT64(&MF_MT_FRAME_SIZE, ((format.width as u64) << 32) | format.height as u64)?;
+        media_type.SetUINT64(&MF_MT_FRAME_RATE, ((rate_num as u64) << 32) | rate_den as u64)?;
         media_type.SetUINT64(&MF_MT_PIXEL_ASPECT_RATIO, (1u64 << 32) | 1u64)?;
         media_type.SetUINT32(&MF_MT_INTERLACE_MODE, MFVideoInterlace_Progressive.0 as u32)?;
         media_type.SetUINT32(&MF_MT_ALL_SAMPLES_INDEPENDENT, 1)?;
-        let media_types = [Some(media_type)];
-        MFCreateStreamDescriptor(0, &media_types)
+        Ok(media_type)
     }
 }
 
+/// Stream descriptor advertising `format` as the only media type.
+pub fn create_stream_descriptor(format: &VideoFormat) -> Result<IMFStreamDescriptor> {
+    let media_types = [Some(create_media_type(format)?)];
+    unsafe { MFCreateStreamDescriptor(0, &media_types) }
+}
+
 impl OpticLinkMediaStream {
     /// Returns (IMFMediaStream, stream_event_queue_clone, OpticLinkFrameSink).
     /// The event queue clone is kept by the source so it can fire MEStreamStarted/etc.
-    pub fn new() -> Result<(IMFMediaStream, IMFMediaEventQueue, OpticLinkFrameSink)> {
+    pub fn new(codec: Codec) -> Result<(IMFMediaStream, IMFMediaEventQueue, OpticLinkFrameSink)> {
         let event_queue = unsafe { MFCreateEventQueue()? };
 
         let state = Arc::new(Mutex::new(SharedState {
             requests: VecDeque::new(),
             frame_buffer: None,
+            // Until the phone's first frame tells us its actual size
+            format: VideoFormat { codec, width: 1920, height: 1080, frame_rate: Some((30, 1)) },
         }));
 
         let sink = OpticLinkFrameSink {
@@ -115,15 +222,16 @@ impl IMFMediaStream_Impl for OpticLinkMediaStream {
     }
 
     fn GetStreamDescriptor(&self) -> Result<IMFStreamDescriptor> {
-        create_h264_stream_descriptor()
+        let format = self.state.lock().unwrap().format;
+        create_stream_descriptor(&format)
     }
 
     fn RequestSample(&self, _punktoken: Option<&IUnknown>) -> Result<()> {
         let mut state = self.state.lock().unwrap();
 
-        if let Some(frame) = state.frame_buffer.take() {
+        if let Some(pending) = state.frame_buffer.take() {
             drop(state);
-            let sample = self.create_sample(&frame)?;
+            let sample = create_sample(&pending)?;
             unsafe {
                 self.event_queue.QueueEventParamUnk(
                     MEMediaSample.0 as u32,
@@ -169,23 +277,3 @@ impl IMFMediaEventGenerator_Impl for OpticLinkMediaStream {
         unsafe { self.event_queue.QueueEventParamVar(met, guidextendedtype, hrstatus, pvalue) }
     }
 }
-
-impl OpticLinkMediaStream {
-    fn create_sample(&self, frame: &[u8]) -> Result<IMFSample> {
-        unsafe {
-            let sample = MFCreateSample()?;
-            let buffer = MFCreateMemoryBuffer(frame.len() as u32)?;
-
-            let mut ptr = std::ptr::null_mut();
-            let mut _len = 0;
-            let mut _current_len = 0;
-            buffer.Lock(&mut ptr, Some(&mut _len), Some(&mut _current_len))?;
-            std::ptr::copy_nonoverlapping(frame.as_ptr(), ptr, frame.len());
-            buffer.Unlock()?;
-            buffer.SetCurrentLength(frame.len() as u32)?;
-
-            sample.AddBuffer(&buffer)?;
-            Ok(sample)
-        }
-    }
-}
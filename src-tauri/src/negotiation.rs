@@ -0,0 +1,81 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Offer/answer bookkeeping for one peer connection that both sides may
+/// renegotiate, following the "perfect negotiation" pattern from the WebRTC spec.
+///
+/// When both sides offer at once (glare), the polite side rolls its offer back and
+/// answers, while the impolite side ignores the colliding offer and waits for the
+/// answer to its own. The desktop is the impolite side: browsers roll back
+/// implicitly in `setRemoteDescription`, webrtc-rs doesn't.
+#[derive(Debug)]
+pub struct Negotiation {
+    polite: bool,
+    making_offer: AtomicBool,
+    /// The last remote offer was dropped because it collided with ours; its ICE
+    /// candidates will fail to apply, which is expected
+    ignore_offer: AtomicBool,
+}
+
+impl Negotiation {
+    pub fn new(polite: bool) -> Self {
+        Self {
+            polite,
+            making_offer: AtomicBool::new(false),
+            ignore_offer: AtomicBool::new(false),
+        }
+    }
+
+    /// Start making an offer. False if one is already being made.
+    pub fn begin_offer(&self) -> bool {
+        !self.making_offer.swap(true, Ordering::AcqRel)
+    }
+
+    /// The offer went out (or failed); the next one may start.
+    pub fn end_offer(&self) {
+        self.making_offer.store(false, Ordering::Release);
+    }
+
+    /// A remote offer arrived while the connection's signaling state is `stable`
+    /// or not. Returns whether to apply it.
+    pub fn accept_offer(&self, stable: bool) -> bool {
+        let collision = self.making_offer.load(Ordering::Acquire) || !stable;
+        let ignore = collision && !self.polite;
+        self.ignore_offer.store(ignore, Ordering::Release);
+        !ignore
+    }
+
+    pub fn ignoring_offer(&self) -> bool {
+        self.ignore_offer.load(Ordering::Acquire)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn impolite_side_ignores_a_colliding_offer() {
+        let desktop = Negotiation::new(false);
+        assert!(desktop.accept_offer(true));
+        assert!(!desktop.ignoring_offer());
+
+        assert!(desktop.begin_offer());
+        assert!(!desktop.begin_offer());
+        assert!(!desktop.accept_offer(true));
+        assert!(desktop.ignoring_offer());
+
+        // Our offer is out and waiting for its answer: still a collision
+        desktop.end_offer();
+        assert!(!desktop.accept_offer(false));
+        assert!(desktop.accept_offer(true));
+        assert!(!desktop.ignoring_offer());
+    }
+
+    #[test]
+    fn polite_side_always_accepts() {
+        let phone = Negotiation::new(true);
+        assert!(phone.begin_offer());
+        assert!(phone.accept_offer(false));
+        assert!(!phone.ignoring_offer());
+    }
+}
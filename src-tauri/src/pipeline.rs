@@ -0,0 +1,764 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use serde::Serialize;
+use tauri::{AppHandle, Emitter};
+use tokio::sync::{mpsc, Notify};
+use windows::Win32::Media::MediaFoundation::{IMFMediaSource, IMFVirtualCamera};
+
+use crate::depacketizer::Codec;
+use crate::frame_bus::{DroppedFrames, FrameBus, LagPolicy, RecvError, Subscription};
+use crate::jitter_buffer::JitterStats;
+use crate::keyframe;
+use crate::media_stream::OpticLinkFrameSink;
+use crate::recovery::RecoveryAction;
+use crate::settings;
+use crate::stream_meter::StreamMeter;
+use crate::transport_stats::{TransportHistory, TransportSample, TransportStats};
+use crate::virtual_cam::{register_virtual_camera, OpticLinkMediaSource};
+use crate::watchdog::{self, Problem, StreamWatchdog, WatchdogAction};
+use crate::webrtc_client::MediaEvent;
+
+/// Frames the virtual camera may have queued: stay live, a few frames of slack at most
+const CAMERA_BACKLOG: usize = 4;
+/// `latency_us` while no frame has reached the camera
+const NO_LATENCY: u64 = u64::MAX;
+/// How often `STATS_EVENT` is emitted
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+/// How often the stream watchdog checks on the stream
+const WATCHDOG_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Emitted with a `PipelineStatus` whenever the state or error changes
+pub const STATE_EVENT: &str = "pipeline-state";
+/// Emitted with `PipelineStats` every `STATS_INTERVAL`
+pub const STATS_EVENT: &str = "pipeline-stats";
+/// Emitted with a `StreamAlert` when the watchdog gives up on recovering a stalled
+/// or frozen stream, and again once frames move
+pub const ALERT_EVENT: &str = "stream-alert";
+
+/// Where the phone → virtual camera pipeline is, as shown to the user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+#[repr(u8)]
+pub enum PipelineState {
+    /// The virtual camera hasn't been started
+    Idle,
+    /// Camera registered, no phone connected
+    WaitingForPhone,
+    /// A phone is setting up (or resuming) its stream
+    Negotiating,
+    /// Frames are reaching the camera
+    Streaming,
+    /// Connected, but frames can't reach the camera: the phone's connection dropped
+    /// out, its stream stalled, or it sends a format the camera can't take
+    Degraded,
+    /// The virtual camera was stopped
+    Stopped,
+}
+
+impl PipelineState {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Self::WaitingForPhone,
+            2 => Self::Negotiating,
+            3 => Self::Streaming,
+            4 => Self::Degraded,
+            5 => Self::Stopped,
+            _ => Self::Idle,
+        }
+    }
+}
+
+/// A state change caused by a command (`from == to` if it changed nothing)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Transition {
+    pub from: PipelineState,
+    pub to: PipelineState,
+}
+
+impl Transition {
+    /// Whether `STATE_EVENT` goes out: on every change, and when `notify` asks for
+    /// it although the state stayed the same
+    fn emits(&self, notify: bool) -> bool {
+        self.from != self.to || notify
+    }
+}
+
+/// What the WebRTC client knows about the phone
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+#[repr(u8)]
+pub enum PhoneLink {
+    Absent,
+    /// Hello or offer received, peer connection not up yet
+    Negotiating,
+    Connected,
+    /// ICE lost connectivity; may still recover
+    Interrupted,
+}
+
+/// Payload of `STATE_EVENT` and `get_virtual_cam_status`
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PipelineStatus {
+    pub state: PipelineState,
+    pub camera_active: bool,
+    /// Why incoming frames can't be fed to the camera (e.g. unsupported codec)
+    pub error: Option<String>,
+}
+
+/// Payload of `ALERT_EVENT`
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamAlert {
+    /// `None` once frames move again
+    pub problem: Option<Problem>,
+}
+
+/// Payload of `STATS_EVENT`
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PipelineStats {
+    pub state: PipelineState,
+    pub connection: PhoneLink,
+    /// Frames received from the phone per second
+    pub fps_in: f64,
+    /// Frames handed to the virtual camera per second
+    pub fps_out: f64,
+    pub bitrate_kbps: f64,
+    pub width: u32,
+    pub height: u32,
+    pub codec: Option<&'static str>,
+    pub keyframe_interval_ms: Option<f64>,
+    /// Frames the camera never got since the camera started or the phone connected:
+    /// withheld after packet loss until the next keyframe, or skipped because the
+    /// camera fell behind
+    pub dropped_frames: u64,
+    pub jitter_buffer_depth: usize,
+    /// Reordered, late, duplicate and lost packets on the current video track
+    pub jitter_buffer: JitterStats,
+    /// Last frame's time from first packet arriving to reaching the camera
+    pub latency_ms: Option<f64>,
+}
+
+const CAMERA_IDLE: u8 = 0;
+const CAMERA_RUNNING: u8 = 1;
+const CAMERA_STOPPED: u8 = 2;
+
+/// A snapshot of what the published state is derived from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct StateInputs {
+    camera: u8,
+    phone: PhoneLink,
+    /// Frames can't be fed to the camera (`error` is set)
+    degraded: bool,
+    /// The watchdog found the stream stalled (a frozen picture still reaches the camera)
+    stalled: bool,
+    /// A frame reached the camera since the phone (re)connected
+    delivering: bool,
+}
+
+impl StateInputs {
+    fn state(&self) -> PipelineState {
+        match self.camera {
+            CAMERA_IDLE => PipelineState::Idle,
+            CAMERA_STOPPED => PipelineState::Stopped,
+            _ => match self.phone {
+                PhoneLink::Absent => PipelineState::WaitingForPhone,
+                PhoneLink::Interrupted => PipelineState::Degraded,
+                _ if self.degraded || self.stalled => PipelineState::Degraded,
+                PhoneLink::Connected if self.delivering => PipelineState::Streaming,
+                _ => PipelineState::Negotiating,
+            },
+        }
+    }
+}
+
+struct SendVirtualCamera(IMFVirtualCamera);
+unsafe impl Send for SendVirtualCamera {}
+unsafe impl Sync for SendVirtualCamera {}
+
+struct SendIMFMediaSource(IMFMediaSource);
+unsafe impl Send for SendIMFMediaSource {}
+unsafe impl Sync for SendIMFMediaSource {}
+
+/// COM objects of a running virtual camera; dropping them unregisters it
+struct CameraSession {
+    _cam: SendVirtualCamera,
+    _source: SendIMFMediaSource,
+}
+
+/// Sent to the camera output task when the camera starts or stops
+enum CameraOutput {
+    Attach { sink: OpticLinkFrameSink, codec: Codec },
+    Detach,
+}
+
+struct Inner {
+    app: AppHandle,
+    bus: FrameBus,
+    /// Frames the virtual camera's subscription skipped; the subscription itself
+    /// belongs to the output task
+    camera_dropped: DroppedFrames,
+    /// Start/stop only; the frame path never takes this
+    session: Mutex<Option<CameraSession>>,
+    outputs: mpsc::UnboundedSender<CameraOutput>,
+    /// Published state and the inputs it is derived from
+    state: AtomicU8,
+    camera: AtomicU8,
+    phone: AtomicU8,
+    /// A frame reached the camera since the phone (re)connected
+    delivering: AtomicBool,
+    /// Serializes derive-and-publish of `state`, so concurrent inputs can't leave a
+    /// stale state behind. Taken on changes only, never per frame.
+    transitions: Mutex<()>,
+    frames: AtomicU64,
+    /// Last frame's time from first packet arriving to reaching the camera
+    latency_us: AtomicU64,
+    /// Why incoming frames can't be fed to the camera; written only when it changes
+    error: Mutex<Option<String>>,
+    degraded: AtomicBool,
+    /// The watchdog found the stream stalled (a frozen picture still reaches the camera)
+    stalled: AtomicBool,
+    /// Frames the WebRTC client held back from the bus (broken reference chain)
+    /// since the session started
+    withheld: AtomicU64,
+    /// `camera_dropped` when the session started
+    dropped_before: AtomicU64,
+    jitter_depth: AtomicUsize,
+    /// Written only when a counter moves
+    jitter_stats: Mutex<JitterStats>,
+    /// Sampled once a second by the WebRTC client
+    transport: Mutex<TransportHistory>,
+    /// Recovery requests to the current peer connection's supervisor
+    recovery: Mutex<Option<mpsc::UnboundedSender<RecoveryAction>>>,
+    /// Asks the WebRTC client to re-offer on the current connection
+    offers: Notify,
+}
+
+/// The phone → virtual camera pipeline, registered as Tauri managed state: the frame
+/// bus, the camera session and its sink, stats, and the state shown to the user.
+///
+/// Frames reach the camera without pipeline locks: the track reader owns the capture
+/// clock, the bus hands frames to each subscriber over a lock-free channel, the
+/// output task owns the sink (start/stop hand it over on a channel), and stats are
+/// atomics. What remains are short critical sections outside the pipeline: the frame
+/// pool's free list and the Media Foundation sink's sample queue.
+#[derive(Clone)]
+pub struct Pipeline {
+    inner: Arc<Inner>,
+}
+
+impl Pipeline {
+    /// Create the pipeline and spawn its camera output, stats and watchdog tasks.
+    pub fn new(app: AppHandle) -> Self {
+        let bus = FrameBus::new();
+        let camera_frames = bus.subscribe("virtual-camera", LagPolicy::Latest(CAMERA_BACKLOG));
+        let (outputs, controls) = mpsc::unbounded_channel();
+        let pipeline = Self {
+            inner: Arc::new(Inner {
+                app,
+                bus,
+                camera_dropped: camera_frames.dropped(),
+                session: Mutex::new(None),
+                outputs,
+                state: AtomicU8::new(PipelineState::Idle as u8),
+                camera: AtomicU8::new(CAMERA_IDLE),
+                phone: AtomicU8::new(PhoneLink::Absent as u8),
+                delivering: AtomicBool::new(false),
+                transitions: Mutex::new(()),
+                frames: AtomicU64::new(0),
+                latency_us: AtomicU64::new(NO_LATENCY),
+                error: Mutex::new(None),
+                degraded: AtomicBool::new(false),
+                stalled: AtomicBool::new(false),
+                withheld: AtomicU64::new(0),
+                dropped_before: AtomicU64::new(0),
+                jitter_depth: AtomicUsize::new(0),
+                jitter_stats: Mutex::new(JitterStats::default()),
+                transport: Mutex::new(TransportHistory::new()),
+                recovery: Mutex::new(None),
+                offers: Notify::new(),
+            }),
+        };
+        tauri::async_runtime::spawn(pipeline.clone().run_camera_output(camera_frames, controls));
+        tauri::async_runtime::spawn(pipeline.clone().run_stats());
+        tauri::async_runtime::spawn(pipeline.clone().run_watchdog());
+        pipeline
+    }
+
+    /// Publish on the bus. Frames come already placed on the capture clock by the
+    /// track reader, so every output presents them at the phone's capture spacing.
+    pub fn publish(&self, event: MediaEvent) {
+        self.inner.bus.publish(event);
+    }
+
+    pub fn state(&self) -> PipelineState {
+        PipelineState::from_u8(self.inner.state.load(Ordering::Acquire))
+    }
+
+    pub fn latency_ms(&self) -> Option<f64> {
+        match self.inner.latency_us.load(Ordering::Relaxed) {
+            NO_LATENCY => None,
+            us => Some(us as f64 / 1000.0),
+        }
+    }
+
+    pub fn error(&self) -> Option<String> {
+        self.inner.error.lock().unwrap().clone()
+    }
+
+    pub fn camera_active(&self) -> bool {
+        self.inner.camera.load(Ordering::Acquire) == CAMERA_RUNNING
+    }
+
+    pub fn status(&self) -> PipelineStatus {
+        PipelineStatus {
+            state: self.state(),
+            camera_active: self.camera_active(),
+            error: self.error(),
+        }
+    }
+
+    /// Register the virtual camera with the output format from the settings.
+    pub fn start_camera(&self) -> Result<Transition, String> {
+        let mut session = self.inner.session.lock().unwrap();
+        if session.is_some() {
+            let state = self.state();
+            return Ok(Transition { from: state, to: state });
+        }
+
+        let output = settings::video().output_codec;
+        println!("[VCam] Registering Virtual Camera ({})...", output.name());
+        let (source, sink) = OpticLinkMediaSource::new(output).map_err(|e| e.to_string())?;
+        let cam = register_virtual_camera(&source).map_err(|e| {
+            format!(
+                "MFCreateVirtualCamera failed: {}. \
+                 Virtual Camera requires Windows 11 build 22000+ and a registered COM server. \
+                 This feature needs additional system setup — see the docs for details.",
+                e
+            )
+        })?;
+        *session = Some(CameraSession {
+            _cam: SendVirtualCamera(cam),
+            _source: SendIMFMediaSource(source),
+        });
+        drop(session);
+
+        self.inner.frames.store(0, Ordering::Relaxed);
+        self.inner.latency_us.store(NO_LATENCY, Ordering::Relaxed);
+        self.reset_dropped();
+        self.set_error(None);
+        self.inner.delivering.store(false, Ordering::Release);
+        let _ = self.inner.outputs.send(CameraOutput::Attach { sink, codec: output });
+        self.inner.camera.store(CAMERA_RUNNING, Ordering::Release);
+        println!("[VCam] Virtual Camera started.");
+        Ok(self.update(true))
+    }
+
+    /// Unregister the virtual camera.
+    pub fn stop_camera(&self) -> Transition {
+        let Some(session) = self.inner.session.lock().unwrap().take() else {
+            let state = self.state();
+            return Transition { from: state, to: state };
+        };
+
+        println!("[VCam] Stopping Virtual Camera...");
+        let _ = self.inner.outputs.send(CameraOutput::Detach);
+        drop(session);
+        self.inner.camera.store(CAMERA_STOPPED, Ordering::Release);
+        self.inner.delivering.store(false, Ordering::Release);
+        println!("[VCam] Virtual Camera stopped.");
+        self.update(true)
+    }
+
+    /// Report what the WebRTC client knows about the phone.
+    pub fn set_phone(&self, link: PhoneLink) {
+        let previous = self.inner.phone.swap(link as u8, Ordering::AcqRel);
+        if previous == link as u8 {
+            return;
+        }
+        if link != PhoneLink::Connected {
+            self.inner.delivering.store(false, Ordering::Release);
+        }
+        // A new phone session: its drop count starts over
+        if previous == PhoneLink::Absent as u8 {
+            self.reset_dropped();
+        }
+        self.update(false);
+    }
+
+    /// Count a frame the WebRTC client kept off the bus.
+    pub fn frame_withheld(&self) {
+        self.inner.withheld.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Frames the camera never got in this camera and phone session
+    fn dropped_frames(&self) -> u64 {
+        let before = self.inner.dropped_before.load(Ordering::Relaxed);
+        let skipped = self.inner.camera_dropped.get().saturating_sub(before);
+        self.inner.withheld.load(Ordering::Relaxed) + skipped
+    }
+
+    fn reset_dropped(&self) {
+        self.inner.withheld.store(0, Ordering::Relaxed);
+        self.inner.dropped_before.store(self.inner.camera_dropped.get(), Ordering::Relaxed);
+    }
+
+    pub fn set_jitter_depth(&self, packets: usize) {
+        self.inner.jitter_depth.store(packets, Ordering::Relaxed);
+    }
+
+    /// The feeding track's jitter buffer counters; called when they change.
+    pub fn set_jitter_stats(&self, stats: JitterStats) {
+        *self.inner.jitter_stats.lock().unwrap() = stats;
+    }
+
+    pub fn record_transport(&self, sample: TransportSample) {
+        self.inner.transport.lock().unwrap().push(sample);
+    }
+
+    /// Latest transport sample and the rolling history behind it
+    pub fn transport_stats(&self) -> TransportStats {
+        self.inner.transport.lock().unwrap().snapshot()
+    }
+
+    /// Where the watchdog sends ICE restart and renegotiation requests; set by the
+    /// WebRTC client for each new peer connection.
+    pub fn set_recovery_requests(&self, requests: mpsc::UnboundedSender<RecoveryAction>) {
+        *self.inner.recovery.lock().unwrap() = Some(requests);
+    }
+
+    /// Have the WebRTC client renegotiate the current connection from our side, e.g.
+    /// after the receive limits changed. Kept until the client picks it up.
+    pub fn request_offer(&self) {
+        self.inner.offers.notify_one();
+    }
+
+    pub async fn offer_requested(&self) {
+        self.inner.offers.notified().await;
+    }
+
+    fn phone(&self) -> PhoneLink {
+        match self.inner.phone.load(Ordering::Acquire) {
+            1 => PhoneLink::Negotiating,
+            2 => PhoneLink::Connected,
+            3 => PhoneLink::Interrupted,
+            _ => PhoneLink::Absent,
+        }
+    }
+
+    fn set_error(&self, error: Option<String>) {
+        self.inner.degraded.store(error.is_some(), Ordering::Release);
+        *self.inner.error.lock().unwrap() = error;
+    }
+
+    fn inputs(&self) -> StateInputs {
+        StateInputs {
+            camera: self.inner.camera.load(Ordering::Acquire),
+            phone: self.phone(),
+            degraded: self.inner.degraded.load(Ordering::Acquire),
+            stalled: self.inner.stalled.load(Ordering::Acquire),
+            delivering: self.inner.delivering.load(Ordering::Acquire),
+        }
+    }
+
+    /// Derive the state from its inputs and publish it. `notify` emits `STATE_EVENT`
+    /// even if the state stays the same (camera toggled, error changed).
+    fn update(&self, notify: bool) -> Transition {
+        let _guard = self.inner.transitions.lock().unwrap();
+        let to = self.inputs().state();
+        let from = PipelineState::from_u8(self.inner.state.swap(to as u8, Ordering::AcqRel));
+        let transition = Transition { from, to };
+        if from != to {
+            println!("[Pipeline] {:?} -> {:?}", from, to);
+        }
+        if transition.emits(notify) {
+            if let Err(e) = self.inner.app.emit(STATE_EVENT, self.status()) {
+                eprintln!("[Pipeline] Failed to emit state: {}", e);
+            }
+        }
+        transition
+    }
+
+    /// Feeds the virtual camera from the bus. Owns the subscription and the sink, so
+    /// frames take no pipeline locks.
+    async fn run_camera_output(self, mut camera: Subscription, mut controls: mpsc::UnboundedReceiver<CameraOutput>) {
+        println!("[Pipe] Frame consumer started");
+        let mut output: Option<(OpticLinkFrameSink, Codec)> = None;
+        // Latest stream format, applied to the sink ahead of the next frame (also
+        // covers a camera started after the phone's format was announced)
+        let mut format = None;
+        // What the attached sink was last given
+        let mut applied = None;
+        let mut error: Option<String> = None;
+        loop {
+            let event = tokio::select! {
+                biased;
+                Some(control) = controls.recv() => {
+                    output = match control {
+                        CameraOutput::Attach { sink, codec } => Some((sink, codec)),
+                        CameraOutput::Detach => None,
+                    };
+                    applied = None;
+                    error = None;
+                    continue;
+                }
+                event = camera.recv() => event,
+            };
+            let frame = match event {
+                Ok(MediaEvent::Frame(frame)) => frame,
+                Ok(MediaEvent::FormatChanged(changed)) => {
+                    format = Some(changed);
+                    continue;
+                }
+                // Fell behind and is waiting for a keyframe: ask for one now
+                Err(RecvError::Lagged(_)) => {
+                    let _ = keyframe::request_active("virtual camera fell behind").await;
+                    continue;
+                }
+                Err(_) => break,
+            };
+            let Some((sink, codec)) = &output else { continue };
+
+            // The Media Foundation stream is declared with one compressed format
+            // and there is no transcoder, so anything else would reach apps as
+            // a corrupt stream
+            let mismatch = if !frame.codec.can_output() {
+                Some(format!(
+                    "Phone is sending {}, which the virtual camera can't output: it passes \
+                     H.264 or H.265 through and has no transcoder. Remove {} from the codecs \
+                     accepted from the phone.",
+                    frame.codec.name(),
+                    frame.codec.name()
+                ))
+            } else {
+                (frame.codec != *codec).then(|| format!(
+                    "Phone is sending {}, but the virtual camera outputs {}. \
+                     Change the output format or the codecs offered to the phone.",
+                    frame.codec.name(),
+                    codec.name()
+                ))
+            };
+            if mismatch != error {
+                if let Some(e) = &mismatch {
+                    eprintln!("[Pipe] {}", e);
+                }
+                error = mismatch;
+                self.set_error(error.clone());
+                self.update(true);
+            }
+            if error.is_some() {
+                continue;
+            }
+
+            if format != applied {
+                applied = format;
+                if let Some(format) = format {
+                    if let Err(e) = sink.set_format(format) {
+                        eprintln!("[Pipe] set_format error: {}", e);
+                    }
+                }
+            }
+            let arrival = frame.timing.arrival;
+            if let Err(e) = sink.push_frame(frame) {
+                eprintln!("[Pipe] push_frame error: {}", e);
+                continue;
+            }
+            self.inner.frames.fetch_add(1, Ordering::Relaxed);
+            let latency = arrival.map_or(NO_LATENCY, |a| a.elapsed().as_micros() as u64);
+            self.inner.latency_us.store(latency, Ordering::Relaxed);
+            if !self.inner.delivering.swap(true, Ordering::AcqRel) {
+                self.update(false);
+            }
+        }
+    }
+
+    /// Measures the stream on its own subscription and emits `STATS_EVENT`.
+    async fn run_stats(self) {
+        let mut received = self.inner.bus.subscribe("stats", LagPolicy::Lossless);
+        let mut meter = StreamMeter::new(Instant::now());
+        let mut ticks = tokio::time::interval(STATS_INTERVAL);
+        let mut frames_out = self.inner.frames.load(Ordering::Relaxed);
+        let mut window_start = Instant::now();
+        loop {
+            tokio::select! {
+                event = received.recv() => match event {
+                    Ok(event) => meter.on_event(&event),
+                    Err(RecvError::Lagged(_)) => {}
+                    Err(_) => break,
+                },
+                _ = ticks.tick() => {
+                    let now = Instant::now();
+                    let connection = self.phone();
+                    if connection == PhoneLink::Absent {
+                        meter.reset(now);
+                    }
+                    let rates = meter.sample(now);
+                    let seconds = now.saturating_duration_since(window_start).as_secs_f64();
+                    window_start = now;
+                    let frames = self.inner.frames.load(Ordering::Relaxed);
+                    // The counter restarts with the camera
+                    let delivered = frames.checked_sub(frames_out).unwrap_or(frames);
+                    frames_out = frames;
+
+                    let stats = PipelineStats {
+                        state: self.state(),
+                        connection,
+                        fps_in: rates.fps,
+                        fps_out: if seconds > 0.0 { delivered as f64 / seconds } else { 0.0 },
+                        bitrate_kbps: rates.bitrate_kbps,
+                        width: rates.format.map_or(0, |f| f.width),
+                        height: rates.format.map_or(0, |f| f.height),
+                        codec: rates.format.map(|f| f.codec.name()),
+                        keyframe_interval_ms: rates.keyframe_interval_ms,
+                        dropped_frames: self.dropped_frames(),
+                        jitter_buffer_depth: self.inner.jitter_depth.load(Ordering::Relaxed),
+                        jitter_buffer: *self.inner.jitter_stats.lock().unwrap(),
+                        latency_ms: self.latency_ms(),
+                    };
+                    if let Err(e) = self.inner.app.emit(STATS_EVENT, stats) {
+                        eprintln!("[Pipeline] Failed to emit stats: {}", e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Watches the bus for a stalled or frozen stream and escalates recovery.
+    async fn run_watchdog(self) {
+        let mut frames = self.inner.bus.subscribe("watchdog", LagPolicy::Lossless);
+        let stall_after = || Duration::from_millis(settings::stream().stall_timeout_ms as u64);
+        let mut watchdog = StreamWatchdog::new(stall_after(), Instant::now());
+        let mut ticks = tokio::time::interval(WATCHDOG_INTERVAL);
+        let mut alerted = false;
+        loop {
+            tokio::select! {
+                event = frames.recv() => match event {
+                    Ok(MediaEvent::Frame(frame)) => watchdog.on_frame(watchdog::repeats_picture(&frame), Instant::now()),
+                    Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    Err(_) => break,
+                },
+                _ = ticks.tick() => {
+                    let now = Instant::now();
+                    watchdog.set_stall_after(stall_after());
+                    watchdog.set_watching(self.phone() == PhoneLink::Connected, now);
+                    match watchdog.poll(now) {
+                        Some(WatchdogAction::RequestKeyframe(problem)) => {
+                            println!("[Watchdog] Stream {:?}, requesting a keyframe", problem);
+                            self.set_stalled(problem == Problem::Stalled);
+                            let _ = keyframe::request_active("stream watchdog").await;
+                        }
+                        Some(WatchdogAction::Recover(action, problem)) => {
+                            println!("[Watchdog] Stream still {:?}, escalating to {:?}", problem, action);
+                            self.set_stalled(true);
+                            let requests = self.inner.recovery.lock().unwrap().clone();
+                            if let Some(requests) = requests {
+                                let _ = requests.send(action);
+                            }
+                        }
+                        Some(WatchdogAction::Alert(problem)) => {
+                            eprintln!("[Watchdog] Stream still {:?} after recovery, alerting the user", problem);
+                            self.emit_alert(StreamAlert { problem: Some(problem) });
+                            alerted = true;
+                        }
+                        Some(WatchdogAction::Recovered) => {
+                            println!("[Watchdog] Stream recovered");
+                            self.set_stalled(false);
+                            if std::mem::take(&mut alerted) {
+                                self.emit_alert(StreamAlert { problem: None });
+                            }
+                        }
+                        None => {}
+                    }
+                }
+            }
+        }
+    }
+
+    fn set_stalled(&self, stalled: bool) {
+        if self.inner.stalled.swap(stalled, Ordering::AcqRel) != stalled {
+            self.update(false);
+        }
+    }
+
+    fn emit_alert(&self, alert: StreamAlert) {
+        if let Err(e) = self.inner.app.emit(ALERT_EVENT, alert) {
+            eprintln!("[Pipeline] Failed to emit alert: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inputs() -> StateInputs {
+        StateInputs {
+            camera: CAMERA_IDLE,
+            phone: PhoneLink::Absent,
+            degraded: false,
+            stalled: false,
+            delivering: false,
+        }
+    }
+
+    #[test]
+    fn started_camera_streams_once_a_connected_phone_delivers() {
+        let mut i = inputs();
+        assert_eq!(i.state(), PipelineState::Idle);
+        i.camera = CAMERA_RUNNING;
+        assert_eq!(i.state(), PipelineState::WaitingForPhone);
+        i.phone = PhoneLink::Negotiating;
+        assert_eq!(i.state(), PipelineState::Negotiating);
+        i.phone = PhoneLink::Connected;
+        assert_eq!(i.state(), PipelineState::Negotiating);
+        i.delivering = true;
+        assert_eq!(i.state(), PipelineState::Streaming);
+    }
+
+    #[test]
+    fn interruption_errors_and_stalls_degrade_a_connected_stream() {
+        let streaming = StateInputs {
+            camera: CAMERA_RUNNING,
+            phone: PhoneLink::Connected,
+            delivering: true,
+            ..inputs()
+        };
+        let interrupted = StateInputs { phone: PhoneLink::Interrupted, delivering: false, ..streaming };
+        assert_eq!(interrupted.state(), PipelineState::Degraded);
+        assert_eq!(StateInputs { degraded: true, ..streaming }.state(), PipelineState::Degraded);
+        assert_eq!(StateInputs { stalled: true, ..streaming }.state(), PipelineState::Degraded);
+        // A negotiating phone with an unusable format is degraded too
+        let negotiating = StateInputs { phone: PhoneLink::Negotiating, delivering: false, ..streaming };
+        assert_eq!(StateInputs { degraded: true, ..negotiating }.state(), PipelineState::Degraded);
+        // Nothing is wrong with a phone that isn't there
+        let absent = StateInputs { phone: PhoneLink::Absent, stalled: true, ..streaming };
+        assert_eq!(absent.state(), PipelineState::WaitingForPhone);
+    }
+
+    #[test]
+    fn camera_state_wins_over_the_phone() {
+        let streaming = StateInputs {
+            camera: CAMERA_RUNNING,
+            phone: PhoneLink::Connected,
+            delivering: true,
+            ..inputs()
+        };
+        assert_eq!(StateInputs { camera: CAMERA_STOPPED, ..streaming }.state(), PipelineState::Stopped);
+        assert_eq!(StateInputs { camera: CAMERA_IDLE, ..streaming }.state(), PipelineState::Idle);
+        // Restarted while the phone is still connected: no frames reached the new camera yet
+        let restarted = StateInputs { delivering: false, ..streaming };
+        assert_eq!(restarted.state(), PipelineState::Negotiating);
+    }
+
+    #[test]
+    fn state_event_only_on_change_unless_notified() {
+        let same = Transition { from: PipelineState::Streaming, to: PipelineState::Streaming };
+        assert!(!same.emits(false));
+        assert!(same.emits(true));
+        let changed = Transition { from: PipelineState::Streaming, to: PipelineState::Degraded };
+        assert!(changed.emits(false));
+        assert!(changed.emits(true));
+    }
+}
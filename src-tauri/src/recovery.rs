@@ -0,0 +1,214 @@
+use std::time::{Duration, Instant};
+
+use uuid::Uuid;
+
+/// ICE often recovers from `disconnected` by itself; wait this long before restarting
+const DISCONNECT_GRACE: Duration = Duration::from_secs(2);
+/// Time an ICE restart gets to reconnect before the next step
+const RESTART_TIMEOUT: Duration = Duration::from_secs(5);
+/// ICE restarts before falling back to renegotiating from scratch
+const MAX_RESTARTS: u32 = 2;
+
+/// Connectivity of the peer connection, as far as recovery cares
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Link {
+    Connected,
+    Disconnected,
+    Failed,
+}
+
+/// What the client should ask the phone for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecoveryAction {
+    /// Re-offer on the same connection with new ICE credentials. Decoder, jitter
+    /// buffer and camera sink stay as they are.
+    RestartIce,
+    /// Start over with a new peer connection
+    Renegotiate,
+}
+
+/// Decides when a peer connection that lost connectivity gets an ICE restart, and
+/// when to give up on it and renegotiate.
+#[derive(Debug, Default)]
+pub struct IceRecovery {
+    /// When connectivity was lost, while it still is
+    lost_since: Option<Instant>,
+    failed: bool,
+    /// When the last ICE restart was requested
+    restarted: Option<Instant>,
+    restarts: u32,
+    renegotiated: bool,
+}
+
+impl IceRecovery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn on_link(&mut self, link: Link, now: Instant) {
+        match link {
+            Link::Connected => {
+                if let Some(since) = self.lost_since {
+                    println!(
+                        "[Recovery] Reconnected after {:.1}s ({} ICE restart(s))",
+                        now.duration_since(since).as_secs_f64(),
+                        self.restarts
+                    );
+                }
+                *self = Self::default();
+            }
+            Link::Disconnected => {
+                self.lost_since.get_or_insert(now);
+            }
+            Link::Failed => {
+                self.lost_since.get_or_insert(now);
+                self.failed = true;
+            }
+        }
+    }
+
+    /// When `poll` may next have something to do
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let since = self.lost_since?;
+        if self.renegotiated {
+            return None;
+        }
+        Some(match self.restarted {
+            None if self.failed => since,
+            None => since + DISCONNECT_GRACE,
+            Some(restarted) => restarted + RESTART_TIMEOUT,
+        })
+    }
+
+    pub fn poll(&mut self, now: Instant) -> Option<RecoveryAction> {
+        if now < self.next_deadline()? {
+            return None;
+        }
+        if self.restarts < MAX_RESTARTS {
+            self.restarts += 1;
+            self.restarted = Some(now);
+            Some(RecoveryAction::RestartIce)
+        } else {
+            self.renegotiated = true;
+            Some(RecoveryAction::Renegotiate)
+        }
+    }
+}
+
+fn fingerprint(sdp: &str) -> Option<&str> {
+    sdp.lines().find_map(|l| l.trim().strip_prefix("a=fingerprint:"))
+}
+
+/// Whether `offer` comes from the same remote peer connection as `current` (an ICE
+/// restart or other re-offer) rather than a new one: a browser keeps its DTLS
+/// certificate for the lifetime of a connection.
+pub fn same_session(current: &str, offer: &str) -> bool {
+    matches!((fingerprint(current), fingerprint(offer)), (Some(a), Some(b)) if a == b)
+}
+
+/// Uniform in [0, 1). A v4 UUID is drawn from the OS random source, and its first
+/// six bytes carry no version or variant bits.
+fn random_unit() -> f64 {
+    let bytes = Uuid::new_v4().into_bytes();
+    let mut random = [0; 8];
+    random[2..].copy_from_slice(&bytes[..6]);
+    u64::from_be_bytes(random) as f64 / (1u64 << 48) as f64
+}
+
+/// Exponential reconnect delay with jitter, so several desktops (or a desktop and
+/// its phone) don't retry in lockstep.
+#[derive(Debug)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    attempt: u32,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self { initial, max: max.max(initial), attempt: 0 }
+    }
+
+    /// Pick up changed settings without starting over
+    pub fn set_range(&mut self, initial: Duration, max: Duration) {
+        self.initial = initial;
+        self.max = max.max(initial);
+    }
+
+    /// Delay before the next attempt: uniformly in the upper half of
+    /// `initial * 2^attempt`, capped at `max`.
+    pub fn next_delay(&mut self) -> Duration {
+        let ceiling = self.initial.saturating_mul(1 << self.attempt.min(16)).min(self.max);
+        self.attempt = self.attempt.saturating_add(1);
+        ceiling.mul_f64(0.5 + 0.5 * random_unit())
+    }
+
+    /// The connection held up; start from `initial` again next time.
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disconnect_escalates_from_ice_restarts_to_renegotiation() {
+        let start = Instant::now();
+        let mut recovery = IceRecovery::new();
+        recovery.on_link(Link::Disconnected, start);
+        assert_eq!(recovery.poll(start + Duration::from_secs(1)), None);
+
+        let mut now = start + DISCONNECT_GRACE;
+        assert_eq!(recovery.poll(now), Some(RecoveryAction::RestartIce));
+        assert_eq!(recovery.poll(now + Duration::from_secs(1)), None);
+        now += RESTART_TIMEOUT;
+        assert_eq!(recovery.poll(now), Some(RecoveryAction::RestartIce));
+        now += RESTART_TIMEOUT;
+        assert_eq!(recovery.poll(now), Some(RecoveryAction::Renegotiate));
+        assert_eq!(recovery.next_deadline(), None);
+    }
+
+    #[test]
+    fn failure_restarts_at_once_and_reconnecting_resets() {
+        let start = Instant::now();
+        let mut recovery = IceRecovery::new();
+        recovery.on_link(Link::Failed, start);
+        assert_eq!(recovery.poll(start), Some(RecoveryAction::RestartIce));
+
+        recovery.on_link(Link::Connected, start + Duration::from_secs(1));
+        assert_eq!(recovery.next_deadline(), None);
+        recovery.on_link(Link::Disconnected, start + Duration::from_secs(30));
+        assert_eq!(recovery.next_deadline(), Some(start + Duration::from_secs(30) + DISCONNECT_GRACE));
+    }
+
+    #[test]
+    fn re_offer_is_recognised_by_its_fingerprint() {
+        let current = "v=0\r\na=fingerprint:sha-256 AB:CD\r\na=ice-ufrag:one\r\n";
+        let restart = "v=0\r\na=fingerprint:sha-256 AB:CD\r\na=ice-ufrag:two\r\n";
+        let fresh = "v=0\r\na=fingerprint:sha-256 EF:01\r\na=ice-ufrag:one\r\n";
+        assert!(same_session(current, restart));
+        assert!(!same_session(current, fresh));
+        assert!(!same_session("v=0\r\n", "v=0\r\n"));
+    }
+
+    #[test]
+    fn backoff_grows_with_jitter_up_to_the_cap_and_resets() {
+        let mut backoff = Backoff::new(Duration::from_millis(500), Duration::from_secs(4));
+        for ceiling_ms in [500, 1000, 2000, 4000, 4000] {
+            let delay = backoff.next_delay();
+            let ceiling = Duration::from_millis(ceiling_ms);
+            assert!(delay >= ceiling / 2 && delay <= ceiling, "{:?} vs {:?}", delay, ceiling);
+        }
+        backoff.reset();
+        assert!(backoff.next_delay() <= Duration::from_millis(500));
+    }
+
+    #[test]
+    fn jitter_covers_the_unit_interval() {
+        let units: Vec<f64> = (0..200).map(|_| random_unit()).collect();
+        assert!(units.iter().all(|u| (0.0..1.0).contains(u)));
+        assert!(units.iter().any(|&u| u < 0.25) && units.iter().any(|&u| u > 0.75));
+    }
+}
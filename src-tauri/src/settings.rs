@@ -0,0 +1,196 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::{LazyLock, Mutex};
+
+use crate::codec_policy::CodecPolicy;
+use crate::depacketizer::Codec;
+
+/// Network options the WebRTC client needs before it can answer an offer.
+/// Persisted as JSON in the app config dir; changes apply when the WebRTC client next reconnects.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, rename_all = "camelCase")]
+pub struct NetworkSettings {
+    /// Run the embedded TURN relay and advertise it to the phone
+    pub turn_enabled: bool,
+    /// UDP port the TURN relay listens on
+    pub turn_port: u16,
+    /// Carry all ICE traffic over one fixed UDP port instead of ephemeral ports
+    pub udp_mux_enabled: bool,
+    pub udp_mux_port: u16,
+    /// Also accept passive ICE-TCP on a fixed port (implies the UDP mux)
+    pub ice_tcp_enabled: bool,
+    pub ice_tcp_port: u16,
+    /// How long the jitter buffer waits for a missing packet before skipping it
+    pub jitter_buffer_ms: u32,
+    /// First delay before reconnecting to signaling; doubles per failed attempt
+    pub reconnect_initial_ms: u32,
+    /// Ceiling for the reconnect delay
+    pub reconnect_max_ms: u32,
+}
+
+impl Default for NetworkSettings {
+    fn default() -> Self {
+        Self {
+            turn_enabled: false,
+            turn_port: 3478,
+            udp_mux_enabled: false,
+            udp_mux_port: 3003,
+            ice_tcp_enabled: false,
+            ice_tcp_port: 3003,
+            jitter_buffer_ms: crate::jitter_buffer::DEFAULT_TARGET_LATENCY.as_millis() as u32,
+            reconnect_initial_ms: 500,
+            reconnect_max_ms: 30_000,
+        }
+    }
+}
+
+/// Codec options for negotiation and the virtual camera output.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, rename_all = "camelCase")]
+pub struct VideoSettings {
+    /// Offer H.265 to the phone (Safari on recent iPhones); applies on reconnect
+    pub hevc_enabled: bool,
+    /// Codecs and limits negotiated with the phone; applies on reconnect
+    pub codec_policy: CodecPolicy,
+    /// Media type the virtual camera advertises: `h264` or `h265`.
+    /// Applies the next time the virtual camera starts.
+    pub output_codec: Codec,
+}
+
+impl Default for VideoSettings {
+    fn default() -> Self {
+        Self {
+            hevc_enabled: false,
+            codec_policy: CodecPolicy::default(),
+            output_codec: Codec::H264,
+        }
+    }
+}
+
+impl VideoSettings {
+    /// The codec policy to negotiate with: H.265 only when opted into, and H.264 if
+    /// that leaves nothing.
+    pub fn negotiated_policy(&self) -> CodecPolicy {
+        let mut policy = self.codec_policy.clone();
+        if !self.hevc_enabled {
+            policy.codecs.retain(|&c| c != Codec::H265);
+        }
+        if policy.codecs.is_empty() {
+            policy.codecs.push(Codec::H264);
+        }
+        policy
+    }
+}
+
+/// Stream health and alert options; they apply right away.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, rename_all = "camelCase")]
+pub struct StreamSettings {
+    /// No frames for this long counts as a stalled stream
+    pub stall_timeout_ms: u32,
+    /// Warn when the phone's battery drops below this percentage mid-stream (0 = never)
+    pub low_battery_percent: u8,
+}
+
+impl Default for StreamSettings {
+    fn default() -> Self {
+        Self { stall_timeout_ms: 2000, low_battery_percent: 20 }
+    }
+}
+
+/// On-disk layout of settings.json
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+struct SettingsFile {
+    network: NetworkSettings,
+    video: VideoSettings,
+    stream: StreamSettings,
+}
+
+struct SettingsStore {
+    path: Option<PathBuf>,
+    file: SettingsFile,
+}
+
+impl SettingsStore {
+    /// Write `file` to disk, then make it current.
+    fn save(&mut self, file: SettingsFile) -> Result<(), String> {
+        if let Some(path) = &self.path {
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+            }
+            let json = serde_json::to_string_pretty(&file).map_err(|e| e.to_string())?;
+            std::fs::write(path, json).map_err(|e| e.to_string())?;
+        }
+        self.file = file;
+        Ok(())
+    }
+}
+
+static SETTINGS: LazyLock<Mutex<SettingsStore>> = LazyLock::new(|| {
+    Mutex::new(SettingsStore {
+        path: None,
+        file: SettingsFile::default(),
+    })
+});
+
+/// Load settings from `path`, falling back to defaults if the file is missing or invalid.
+pub fn load(path: PathBuf) {
+    let file = std::fs::read_to_string(&path)
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default();
+
+    let mut store = SETTINGS.lock().unwrap();
+    store.path = Some(path);
+    store.file = file;
+}
+
+pub fn network() -> NetworkSettings {
+    SETTINGS.lock().unwrap().file.network.clone()
+}
+
+pub fn set_network(network: NetworkSettings) -> Result<(), String> {
+    let mut store = SETTINGS.lock().map_err(|e| e.to_string())?;
+    let file = SettingsFile { network, ..store.file.clone() };
+    store.save(file)
+}
+
+pub fn video() -> VideoSettings {
+    SETTINGS.lock().unwrap().file.video.clone()
+}
+
+pub fn set_video(video: VideoSettings) -> Result<(), String> {
+    if !video.output_codec.can_output() {
+        return Err(format!("The virtual camera can't output {}", video.output_codec.name()));
+    }
+    let mut store = SETTINGS.lock().map_err(|e| e.to_string())?;
+    let file = SettingsFile { video, ..store.file.clone() };
+    store.save(file)
+}
+
+pub fn stream() -> StreamSettings {
+    SETTINGS.lock().unwrap().file.stream.clone()
+}
+
+pub fn set_stream(stream: StreamSettings) -> Result<(), String> {
+    let mut store = SETTINGS.lock().map_err(|e| e.to_string())?;
+    let file = SettingsFile { stream, ..store.file.clone() };
+    store.save(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn h265_is_only_negotiated_when_enabled() {
+        let mut video = VideoSettings::default();
+        video.codec_policy.codecs = vec![Codec::H265, Codec::H264];
+        assert_eq!(video.negotiated_policy().codecs, [Codec::H264]);
+        video.codec_policy.codecs = vec![Codec::H265];
+        assert_eq!(video.negotiated_policy().codecs, [Codec::H264]);
+        video.hevc_enabled = true;
+        assert_eq!(video.negotiated_policy().codecs, [Codec::H265]);
+    }
+}
@@ -0,0 +1,119 @@
+use std::time::Instant;
+
+use crate::frame_timing::CLOCK_RATE;
+use crate::webrtc_client::{MediaEvent, VideoFormat};
+
+/// What arrived on the bus over one measuring window
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamRates {
+    pub fps: f64,
+    pub bitrate_kbps: f64,
+    /// Time between the last two keyframes, from their PTS
+    pub keyframe_interval_ms: Option<f64>,
+    pub format: Option<VideoFormat>,
+}
+
+/// Measures the received stream from a bus subscription: frame and bit rate over
+/// the window since the last `sample`, plus the current format and GOP length.
+pub struct StreamMeter {
+    window_start: Instant,
+    frames: u64,
+    bytes: u64,
+    format: Option<VideoFormat>,
+    last_keyframe_pts: Option<u64>,
+    keyframe_interval_ms: Option<f64>,
+}
+
+impl StreamMeter {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            window_start: now,
+            frames: 0,
+            bytes: 0,
+            format: None,
+            last_keyframe_pts: None,
+            keyframe_interval_ms: None,
+        }
+    }
+
+    pub fn on_event(&mut self, event: &MediaEvent) {
+        match event {
+            MediaEvent::FormatChanged(format) => self.format = Some(*format),
+            MediaEvent::Frame(frame) => {
+                self.frames += 1;
+                self.bytes += frame.data.len() as u64;
+                if frame.keyframe {
+                    let pts = frame.timing.pts;
+                    if let Some(last) = self.last_keyframe_pts.filter(|&last| pts > last) {
+                        self.keyframe_interval_ms = Some((pts - last) as f64 * 1000.0 / CLOCK_RATE as f64);
+                    }
+                    self.last_keyframe_pts = Some(pts);
+                }
+            }
+        }
+    }
+
+    /// Rates since the previous call; starts a new window.
+    pub fn sample(&mut self, now: Instant) -> StreamRates {
+        let seconds = now.saturating_duration_since(self.window_start).as_secs_f64();
+        let per_second = |v: f64| if seconds > 0.0 { v / seconds } else { 0.0 };
+        let rates = StreamRates {
+            fps: per_second(self.frames as f64),
+            bitrate_kbps: per_second(self.bytes as f64 * 8.0 / 1000.0),
+            keyframe_interval_ms: self.keyframe_interval_ms,
+            format: self.format,
+        };
+        self.window_start = now;
+        self.frames = 0;
+        self.bytes = 0;
+        rates
+    }
+
+    /// Forget the stream, e.g. when the phone disconnects.
+    pub fn reset(&mut self, now: Instant) {
+        *self = Self::new(now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::depacketizer::Codec;
+    use crate::frame_timing::FrameTiming;
+    use crate::webrtc_client::VideoFrame;
+    use std::time::Duration;
+
+    fn frame(pts: u64, size: usize, keyframe: bool) -> MediaEvent {
+        MediaEvent::Frame(VideoFrame::h264(vec![0; size], 1280, 720, keyframe, FrameTiming { pts, ..Default::default() }))
+    }
+
+    #[test]
+    fn rates_cover_the_window_since_the_last_sample() {
+        let start = Instant::now();
+        let mut meter = StreamMeter::new(start);
+        for n in 0..30 {
+            meter.on_event(&frame(n * 3000, 1250, n == 0));
+        }
+        let rates = meter.sample(start + Duration::from_secs(1));
+        assert_eq!(rates.fps, 30.0);
+        assert_eq!(rates.bitrate_kbps, 300.0);
+
+        let rates = meter.sample(start + Duration::from_secs(2));
+        assert_eq!((rates.fps, rates.bitrate_kbps), (0.0, 0.0));
+    }
+
+    #[test]
+    fn keyframe_interval_and_format_persist_across_windows() {
+        let start = Instant::now();
+        let mut meter = StreamMeter::new(start);
+        let format = VideoFormat { codec: Codec::H264, width: 1280, height: 720, frame_rate: Some((30, 1)) };
+        meter.on_event(&MediaEvent::FormatChanged(format));
+        meter.on_event(&frame(0, 10, true));
+        meter.on_event(&frame(180_000, 10, true));
+        meter.sample(start + Duration::from_secs(1));
+
+        let rates = meter.sample(start + Duration::from_secs(2));
+        assert_eq!(rates.keyframe_interval_ms, Some(2000.0));
+        assert_eq!(rates.format, Some(format));
+    }
+}
@@ -0,0 +1,163 @@
+use std::sync::Mutex;
+use std::time::Instant;
+use serde::{Deserialize, Serialize};
+
+use crate::control::CameraState;
+
+/// Emitted with a `DeviceTelemetry` whenever the phone reports
+pub const TELEMETRY_EVENT: &str = "device-telemetry";
+/// Emitted with a `LowBattery` when the phone's battery runs low mid-stream
+pub const LOW_BATTERY_EVENT: &str = "low-battery";
+/// How far the battery has to recover past the threshold before it warns again
+const REARM_MARGIN: f64 = 0.05;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Battery {
+    /// 0.0 to 1.0
+    pub level: f64,
+    pub charging: bool,
+}
+
+/// CPU pressure as the browser's Compute Pressure API reports it. Browsers don't
+/// expose the phone's temperature, so this stands in for it: phones that run hot
+/// get throttled, which shows up as `serious` or `critical`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ThermalState {
+    Nominal,
+    Fair,
+    Serious,
+    Critical,
+}
+
+/// What the phone sends every few seconds; `None` where its browser can't tell
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct TelemetryReport {
+    /// The phone is sending video
+    pub streaming: bool,
+    pub battery: Option<Battery>,
+    pub thermal: Option<ThermalState>,
+    /// The active camera's label, capability ranges and settings
+    pub camera: Option<CameraState>,
+    /// Frames per second the camera actually delivers
+    pub capture_fps: Option<f64>,
+}
+
+/// Payload of `TELEMETRY_EVENT` and `get_device_telemetry`
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceTelemetry {
+    #[serde(flatten)]
+    pub report: TelemetryReport,
+    /// Time since the phone sent it
+    pub age_ms: u64,
+}
+
+/// Payload of `LOW_BATTERY_EVENT`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct LowBattery {
+    pub level: f64,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    latest: Option<(TelemetryReport, Instant)>,
+    /// Warned about the current discharge already
+    warned: bool,
+}
+
+/// The phone's latest telemetry, plus the low-battery alarm. Registered as Tauri
+/// managed state.
+#[derive(Debug, Default)]
+pub struct Telemetry {
+    inner: Mutex<Inner>,
+}
+
+impl Telemetry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn latest(&self, now: Instant) -> Option<DeviceTelemetry> {
+        let inner = self.inner.lock().unwrap();
+        inner.latest.as_ref().map(|(report, at)| DeviceTelemetry {
+            report: report.clone(),
+            age_ms: now.saturating_duration_since(*at).as_millis() as u64,
+        })
+    }
+
+    /// Store `report`. Returns a warning the first time the battery is below
+    /// `threshold` (0.0 to 1.0) while streaming; it warns again only after the
+    /// phone charged or the level came back up.
+    pub fn record(&self, report: TelemetryReport, threshold: f64, now: Instant) -> Option<LowBattery> {
+        let mut inner = self.inner.lock().unwrap();
+        let mut warning = None;
+        if let Some(battery) = report.battery {
+            if battery.charging || battery.level >= threshold + REARM_MARGIN {
+                inner.warned = false;
+            } else if battery.level < threshold && report.streaming && !inner.warned {
+                inner.warned = true;
+                warning = Some(LowBattery { level: battery.level });
+            }
+        }
+        inner.latest = Some((report, now));
+        warning
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn report(level: f64, charging: bool, streaming: bool) -> TelemetryReport {
+        TelemetryReport {
+            streaming,
+            battery: Some(Battery { level, charging }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn warns_once_per_discharge_while_streaming() {
+        let telemetry = Telemetry::new();
+        let now = Instant::now();
+        assert_eq!(telemetry.record(report(0.15, false, false), 0.2, now), None);
+        assert_eq!(telemetry.record(report(0.15, false, true), 0.2, now), Some(LowBattery { level: 0.15 }));
+        assert_eq!(telemetry.record(report(0.14, false, true), 0.2, now), None);
+        // Hovering around the threshold doesn't re-arm it
+        assert_eq!(telemetry.record(report(0.21, false, true), 0.2, now), None);
+        assert_eq!(telemetry.record(report(0.19, false, true), 0.2, now), None);
+
+        // Charging for a while does
+        assert_eq!(telemetry.record(report(0.19, true, true), 0.2, now), None);
+        assert_eq!(telemetry.record(report(0.18, false, true), 0.2, now), Some(LowBattery { level: 0.18 }));
+
+        // A threshold of 0 never warns
+        let quiet = Telemetry::new();
+        assert_eq!(quiet.record(report(0.0, false, true), 0.0, now), None);
+    }
+
+    #[test]
+    fn keeps_the_latest_report_with_its_age() {
+        let telemetry = Telemetry::new();
+        let start = Instant::now();
+        assert!(telemetry.latest(start).is_none());
+
+        let report = TelemetryReport {
+            thermal: Some(ThermalState::Serious),
+            capture_fps: Some(29.5),
+            ..Default::default()
+        };
+        telemetry.record(report.clone(), 0.2, start);
+        let latest = telemetry.latest(start + Duration::from_secs(3)).unwrap();
+        assert_eq!(latest.report, report);
+        assert_eq!(latest.age_ms, 3000);
+
+        let json = serde_json::to_value(&latest).unwrap();
+        assert_eq!(json["thermal"], "serious");
+        assert_eq!(json["captureFps"], 29.5);
+        assert_eq!(json["ageMs"], 3000);
+    }
+}
@@ -0,0 +1,188 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+use serde::Serialize;
+
+use crate::frame_timing::CLOCK_RATE;
+
+/// Samples kept for graphs: 5 minutes at one per second
+pub const HISTORY_LEN: usize = 300;
+
+/// One reading of the phone → desktop transport, taken from the peer connection's
+/// stats plus what the track reader measures itself.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransportSample {
+    /// Milliseconds since the Unix epoch
+    pub timestamp_ms: u64,
+    /// ICE round-trip time on the selected candidate pair
+    pub rtt_ms: Option<f64>,
+    /// RFC 3550 interarrival jitter of the video track
+    pub jitter_ms: f64,
+    pub packets_received: u64,
+    pub packets_lost: u64,
+    pub bytes_received: u64,
+    /// Receive rate since the previous sample
+    pub bitrate_kbps: f64,
+    /// Our bandwidth estimate, as last sent to the phone in REMB
+    pub estimated_bitrate_kbps: Option<f64>,
+    /// Feedback we sent the phone
+    pub nack_count: u64,
+    pub pli_count: u64,
+    pub fir_count: u64,
+    /// Candidate types of the selected pair (host, srflx, prflx, relay)
+    pub local_candidate_type: Option<String>,
+    pub remote_candidate_type: Option<String>,
+    pub local_address: Option<String>,
+    pub remote_address: Option<String>,
+    /// Network type of the local candidate (udp4, tcp4, ...)
+    pub protocol: Option<String>,
+}
+
+/// Rolling window of samples, oldest first
+#[derive(Debug, Default)]
+pub struct TransportHistory {
+    samples: VecDeque<TransportSample>,
+}
+
+/// Returned by `get_transport_stats`
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransportStats {
+    pub latest: Option<TransportSample>,
+    pub history: Vec<TransportSample>,
+}
+
+impl TransportHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a sample, deriving its bitrate from the previous one. Counters that went
+    /// backwards belong to a new peer connection, so the rate restarts there.
+    pub fn push(&mut self, mut sample: TransportSample) {
+        if let Some(last) = self.samples.back() {
+            let elapsed_ms = sample.timestamp_ms.saturating_sub(last.timestamp_ms);
+            if elapsed_ms > 0 && sample.bytes_received >= last.bytes_received {
+                let bits = (sample.bytes_received - last.bytes_received) as f64 * 8.0;
+                sample.bitrate_kbps = bits / elapsed_ms as f64;
+            }
+        }
+        if self.samples.len() == HISTORY_LEN {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    pub fn snapshot(&self) -> TransportStats {
+        TransportStats {
+            latest: self.samples.back().cloned(),
+            history: self.samples.iter().cloned().collect(),
+        }
+    }
+}
+
+/// RFC 3550 §6.4.1 interarrival jitter, fed with packets in arrival order.
+#[derive(Debug, Default)]
+pub struct InterarrivalJitter {
+    last: Option<(u32, Instant)>,
+    /// In RTP timestamp units
+    jitter: f64,
+}
+
+impl InterarrivalJitter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn on_packet(&mut self, rtp_timestamp: u32, arrival: Instant) {
+        if let Some((last_ts, last_arrival)) = self.last {
+            let arrived = match arrival.checked_duration_since(last_arrival) {
+                Some(d) => d.as_secs_f64(),
+                None => -last_arrival.duration_since(arrival).as_secs_f64(),
+            } * CLOCK_RATE as f64;
+            let sent = rtp_timestamp.wrapping_sub(last_ts) as i32 as f64;
+            self.jitter += ((arrived - sent).abs() - self.jitter) / 16.0;
+        }
+        self.last = Some((rtp_timestamp, arrival));
+    }
+
+    pub fn ms(&self) -> f64 {
+        self.jitter * 1000.0 / CLOCK_RATE as f64
+    }
+}
+
+/// What the track reader measures, read by the stats sampler of the same connection
+#[derive(Debug, Default)]
+pub struct ReceiveCounters {
+    jitter_us: AtomicU64,
+    lost: AtomicU64,
+    /// Bandwidth estimate in bps; 0 until the first one
+    estimate: AtomicU64,
+}
+
+impl ReceiveCounters {
+    pub fn update(&self, jitter: &InterarrivalJitter, lost: u64) {
+        self.jitter_us.store((jitter.ms() * 1000.0) as u64, Ordering::Relaxed);
+        self.lost.store(lost, Ordering::Relaxed);
+    }
+
+    pub fn set_estimate(&self, bps: u64) {
+        self.estimate.store(bps, Ordering::Relaxed);
+    }
+
+    pub fn estimate_bps(&self) -> Option<u64> {
+        Some(self.estimate.load(Ordering::Relaxed)).filter(|&bps| bps > 0)
+    }
+
+    pub fn jitter_ms(&self) -> f64 {
+        self.jitter_us.load(Ordering::Relaxed) as f64 / 1000.0
+    }
+
+    pub fn lost(&self) -> u64 {
+        self.lost.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn history_derives_bitrate_and_keeps_a_bounded_window() {
+        let mut history = TransportHistory::new();
+        for n in 0..HISTORY_LEN as u64 + 10 {
+            history.push(TransportSample {
+                timestamp_ms: n * 1000,
+                bytes_received: n * 125_000,
+                ..Default::default()
+            });
+        }
+        let stats = history.snapshot();
+        assert_eq!(stats.history.len(), HISTORY_LEN);
+        assert_eq!(stats.history[0].timestamp_ms, 10_000);
+        assert_eq!(stats.latest.map(|s| s.bitrate_kbps), Some(1000.0));
+
+        // A new connection starts its counters over
+        history.push(TransportSample { timestamp_ms: 400_000, bytes_received: 5, ..Default::default() });
+        assert_eq!(history.snapshot().latest.map(|s| s.bitrate_kbps), Some(0.0));
+    }
+
+    #[test]
+    fn jitter_is_zero_for_evenly_paced_packets_and_grows_with_variation() {
+        let start = Instant::now();
+        let mut jitter = InterarrivalJitter::new();
+        for n in 0..50u32 {
+            jitter.on_packet(n * 3000, start + Duration::from_millis(n as u64 * 33 + n as u64 / 3));
+        }
+        assert!(jitter.ms() < 1.0, "{}", jitter.ms());
+
+        let mut jitter = InterarrivalJitter::new();
+        for n in 0..200u32 {
+            let wobble = if n % 2 == 0 { 0 } else { 20 };
+            jitter.on_packet(n * 3000, start + Duration::from_millis(n as u64 * 33 + wobble));
+        }
+        assert!((jitter.ms() - 20.0).abs() < 2.0, "{}", jitter.ms());
+    }
+}
@@ -0,0 +1,133 @@
+use anyhow::Result;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use webrtc::turn::auth::{generate_long_term_credentials, LongTermAuthHandler};
+use webrtc::turn::relay::relay_static::RelayAddressGeneratorStatic;
+use webrtc::turn::server::config::{ConnConfig, ServerConfig};
+use webrtc::turn::server::Server;
+use webrtc::util::vnet::net::Net;
+
+const REALM: &str = "opticlink";
+/// Lifetime of the credentials handed to the phone for one session
+const CREDENTIAL_TTL: Duration = Duration::from_secs(12 * 60 * 60);
+/// Credentials this close to expiring are replaced instead of handed out again
+const CREDENTIAL_REFRESH: Duration = Duration::from_secs(60 * 60);
+
+/// Mints long-term TURN credentials once and hands out the same pair until it
+/// nears `CREDENTIAL_TTL`, so the phone's ICE config stays put for a session even
+/// though it re-announces itself every few seconds.
+struct CredentialIssuer {
+    shared_secret: String,
+    /// Username, password and when they were minted
+    issued: Mutex<Option<(String, String, Instant)>>,
+}
+
+impl CredentialIssuer {
+    fn new(shared_secret: String) -> Self {
+        Self { shared_secret, issued: Mutex::new(None) }
+    }
+
+    fn credentials(&self, now: Instant) -> Result<(String, String)> {
+        let mut issued = self.issued.lock().unwrap();
+        if let Some((username, password, at)) = issued.as_ref() {
+            if now.saturating_duration_since(*at) + CREDENTIAL_REFRESH < CREDENTIAL_TTL {
+                return Ok((username.clone(), password.clone()));
+            }
+        }
+        let (username, password) = generate_long_term_credentials(&self.shared_secret, CREDENTIAL_TTL)?;
+        *issued = Some((username.clone(), password.clone(), now));
+        Ok((username, password))
+    }
+}
+
+/// Embedded TURN server for networks that block peer-to-peer UDP between clients
+/// (Wi-Fi client isolation) but still let the phone reach the desktop directly.
+pub struct TurnRelay {
+    server: Server,
+    credentials: CredentialIssuer,
+    host: IpAddr,
+    port: u16,
+}
+
+impl TurnRelay {
+    /// Bind the relay on `0.0.0.0:port`, handing out `host` as the relayed address.
+    pub async fn start(host: IpAddr, port: u16) -> Result<Self> {
+        let conn = Arc::new(tokio::net::UdpSocket::bind(("0.0.0.0", port)).await?);
+        // Random per-run secret: credentials from a previous run are never valid
+        let shared_secret = uuid::Uuid::new_v4().to_string();
+
+        let server = Server::new(ServerConfig {
+            conn_configs: vec![ConnConfig {
+                conn,
+                relay_addr_generator: Box::new(RelayAddressGeneratorStatic {
+                    relay_address: host,
+                    address: "0.0.0.0".to_string(),
+                    net: Arc::new(Net::new(None)),
+                }),
+            }],
+            realm: REALM.to_string(),
+            auth_handler: Arc::new(LongTermAuthHandler::new(shared_secret.clone())),
+            channel_bind_timeout: Duration::from_secs(0),
+            alloc_close_notify: None,
+        })
+        .await?;
+
+        println!("[TURN] Relay listening on 0.0.0.0:{} (relay address {})", port, host);
+        Ok(Self { server, credentials: CredentialIssuer::new(shared_secret), host, port })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// The session's time-limited credential pair as a browser-style
+    /// `RTCIceServer` entry for the signaling handshake.
+    pub fn session_ice_server(&self) -> Result<serde_json::Value> {
+        let (username, credential) = self.credentials.credentials(Instant::now())?;
+        Ok(serde_json::json!({
+            "urls": [format!("turn:{}:{}?transport=udp", self.host, self.port)],
+            "username": username,
+            "credential": credential
+        }))
+    }
+
+    pub async fn close(&self) {
+        if let Err(e) = self.server.close().await {
+            eprintln!("[TURN] Close error: {}", e);
+        }
+        println!("[TURN] Relay stopped");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use webrtc::turn::auth::{generate_auth_key, AuthHandler};
+
+    #[test]
+    fn credentials_are_reused_until_they_near_expiry() {
+        let issuer = CredentialIssuer::new("secret".to_string());
+        let start = Instant::now();
+        let first = issuer.credentials(start).unwrap();
+        assert_eq!(issuer.credentials(start + Duration::from_secs(5)).unwrap(), first);
+        assert_eq!(issuer.credentials(start + CREDENTIAL_TTL / 2).unwrap(), first);
+
+        let renew_at = start + CREDENTIAL_TTL - CREDENTIAL_REFRESH;
+        issuer.credentials(renew_at).unwrap();
+        assert_eq!(issuer.issued.lock().unwrap().as_ref().map(|(.., at)| *at), Some(renew_at));
+    }
+
+    #[test]
+    fn issued_credentials_pass_the_relays_auth_handler() {
+        let issuer = CredentialIssuer::new("secret".to_string());
+        let (username, password) = issuer.credentials(Instant::now()).unwrap();
+        let handler = LongTermAuthHandler::new("secret".to_string());
+        let key = handler.auth_handle(&username, REALM, "192.168.1.20:50000".parse().unwrap()).unwrap();
+        assert_eq!(key, generate_auth_key(&username, REALM, &password));
+
+        let stranger = LongTermAuthHandler::new("another run".to_string());
+        let key = stranger.auth_handle(&username, REALM, "192.168.1.20:50000".parse().unwrap()).unwrap();
+        assert_ne!(key, generate_auth_key(&username, REALM, &password));
+    }
+}
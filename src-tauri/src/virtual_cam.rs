@@ -3,7 +3,8 @@ use windows::Win32::Media::MediaFoundation::*;
 use windows::Win32::System::Com::StructuredStorage::PROPVARIANT;
 use windows::Win32::Foundation::*;
 
-use crate::media_stream::{OpticLinkMediaStream, OpticLinkFrameSink};
+use crate::depacketizer::Codec;
+use crate::media_stream::{self, OpticLinkMediaStream, OpticLinkFrameSink};
 
 use windows::core::implement;
 
@@ -15,25 +16,28 @@ pub struct OpticLinkMediaSource {
     /// Clone of the stream's event queue, so Start/Shutdown can fire stream events
     stream_eq: IMFMediaEventQueue,
     attributes: IMFAttributes,
+    /// Handle on the stream's state, for the format currently advertised to apps
+    sink: OpticLinkFrameSink,
 }
 
 impl OpticLinkMediaSource {
     /// Returns (IMFMediaSource, OpticLinkFrameSink).
     /// Both the source and stream are properly COM-heap-allocated via .into::<IUnknown>().
-    pub fn new() -> Result<(IMFMediaSource, OpticLinkFrameSink)> {
+    pub fn new(codec: Codec) -> Result<(IMFMediaSource, OpticLinkFrameSink)> {
         let event_queue = unsafe { MFCreateEventQueue()? };
 
         let mut attributes = None;
         unsafe { MFCreateAttributes(&mut attributes, 0)? };
         let attributes = attributes.ok_or(Error::from(E_FAIL))?;
 
-        let (mf_stream, stream_eq, sink) = OpticLinkMediaStream::new()?;
+        let (mf_stream, stream_eq, sink) = OpticLinkMediaStream::new(codec)?;
 
         let source = Self {
             event_queue,
             stream: Some(mf_stream),
             stream_eq,
             attributes,
+            sink: sink.clone(),
         };
         let unknown: IUnknown = source.into(); // COM heap allocation
         let mf_source: IMFMediaSource = unknown.cast()?;
@@ -42,20 +46,7 @@ impl OpticLinkMediaSource {
     }
 
     fn create_stream_descriptor(&self) -> Result<IMFStreamDescriptor> {
-        unsafe {
-            let media_type = MFCreateMediaType()?;
-            media_type.SetGUID(&MF_MT_MAJOR_TYPE, &MFMediaType_Video)?;
-            media_type.SetGUID(&MF_MT_SUBTYPE, &MFVideoFormat_H264)?;
-            media_type.SetUINT64(&MF_MT_FRAME_SIZE, (1920u64 << 32) | 1080u64)?;
-            media_type.SetUINT64(&MF_MT_FRAME_RATE, (30u64 << 32) | 1u64)?;
-            media_type.SetUINT64(&MF_MT_PIXEL_ASPECT_RATIO, (1u64 << 32) | 1u64)?;
-            media_type.SetUINT32(&MF_MT_INTERLACE_MODE, MFVideoInterlace_Progressive.0 as u32)?;
-            media_type.SetUINT32(&MF_MT_ALL_SAMPLES_INDEPENDENT, 1)?;
-
-            let media_types = [Some(media_type)];
-            let sd = MFCreateStreamDescriptor(0, &media_types)?;
-            Ok(sd)
-        }
+        media_stream::create_stream_descriptor(&self.sink.format())
     }
 }
 
@@ -0,0 +1,271 @@
+use std::time::{Duration, Instant};
+use serde::Serialize;
+
+use crate::recovery::RecoveryAction;
+use crate::webrtc_client::VideoFrame;
+
+/// Consecutive frames repeating the picture that count as frozen (~3 s at 30 fps)
+const FROZEN_REPEATS: u32 = 90;
+/// Largest P-frame taken for a repeated picture. An all-skip H.264 slice is a start
+/// code and NAL header (5 bytes), a slice header (4-8 bytes) and one skip run over
+/// the whole picture (4 bytes at 1080p); 32 bytes leaves room for an AUD or SEI.
+const REPEAT_BYTES: usize = 32;
+/// Hardware encoders slice their pictures, and each slice brings its own header and
+/// skip run (~12 bytes). A byte per 64 16×16 blocks allows a slice per ~800 blocks,
+/// ten at 1080p.
+const BLOCKS_PER_REPEAT_BYTE: usize = 64;
+
+/// Recovery steps, each with how long it gets before the next one is tried. The
+/// connection steps are for `Problem::Stalled` only.
+const LADDER: [(Step, Duration); 4] = [
+    (Step::Keyframe, Duration::from_secs(2)),
+    (Step::Recover(RecoveryAction::RestartIce), Duration::from_secs(6)),
+    (Step::Recover(RecoveryAction::Renegotiate), Duration::from_secs(10)),
+    (Step::Alert, Duration::ZERO),
+];
+
+/// What is wrong with the stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Problem {
+    /// No frames at all, e.g. the phone locked its screen
+    Stalled,
+    /// Frames keep coming but the picture doesn't change, e.g. a throttled tab. A
+    /// static scene at a low bitrate looks the same, so this never touches the
+    /// connection.
+    Frozen,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+    Keyframe,
+    Recover(RecoveryAction),
+    Alert,
+}
+
+/// What the pipeline should do about the stream's health
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchdogAction {
+    /// Ask the phone for a keyframe (PLI, then FIR)
+    RequestKeyframe(Problem),
+    /// Ask the phone for an ICE restart or a new connection
+    Recover(RecoveryAction, Problem),
+    /// Nothing helped; tell the user
+    Alert(Problem),
+    /// Frames are moving again after a problem was reported
+    Recovered,
+}
+
+/// Whether a frame most likely shows the previous picture again. Compressed frames
+/// never repeat byte for byte; an encoder whose source froze sends P-frames that
+/// skip every block instead, far smaller than even a still camera image costs once
+/// sensor noise is coded. Concealment repeats look the same.
+pub fn repeats_picture(frame: &VideoFrame) -> bool {
+    if frame.keyframe || frame.width == 0 || frame.height == 0 {
+        return false;
+    }
+    let blocks = (frame.width as usize).div_ceil(16) * (frame.height as usize).div_ceil(16);
+    frame.data.len() <= REPEAT_BYTES + blocks / BLOCKS_PER_REPEAT_BYTE
+}
+
+/// Notices a stream that stalled (no frames for `stall_after`) or froze (frames
+/// that keep repeating the picture). A stall escalates from a keyframe request to an
+/// ICE restart, then a new connection, and finally an alert for the user; a frozen
+/// picture arrives over a working link, so it gets the keyframe request and the
+/// alert only.
+///
+/// The ladder only starts over once the picture moves again, so a stall that
+/// survives renegotiating ends in an alert instead of going round in circles.
+#[derive(Debug)]
+pub struct StreamWatchdog {
+    stall_after: Duration,
+    watching: bool,
+    /// Last frame, or when watching (re)started if there was none since
+    last_frame: Instant,
+    /// Consecutive frames that repeated the picture
+    repeats: u32,
+    problem: Option<Problem>,
+    /// Next step on the ladder and when it is due
+    step: usize,
+    step_due: Instant,
+}
+
+impl StreamWatchdog {
+    pub fn new(stall_after: Duration, now: Instant) -> Self {
+        Self {
+            stall_after,
+            watching: false,
+            last_frame: now,
+            repeats: 0,
+            problem: None,
+            step: 0,
+            step_due: now,
+        }
+    }
+
+    pub fn set_stall_after(&mut self, stall_after: Duration) {
+        self.stall_after = stall_after;
+    }
+
+    /// Watch only while the phone is connected; connection loss is left to ICE
+    /// recovery. Resuming restarts the stall clock and gives the pending step a
+    /// full wait, since the phone may just have reconnected.
+    pub fn set_watching(&mut self, watching: bool, now: Instant) {
+        if watching && !self.watching {
+            self.last_frame = now;
+            self.repeats = 0;
+            if self.problem.is_some() {
+                self.step_due = now + LADDER[self.step.saturating_sub(1)].1;
+            }
+        }
+        self.watching = watching;
+    }
+
+    /// A frame arrived; `repeats_picture` as judged by [`repeats_picture`]
+    pub fn on_frame(&mut self, repeats_picture: bool, now: Instant) {
+        self.last_frame = now;
+        self.repeats = if repeats_picture { self.repeats + 1 } else { 0 };
+    }
+
+    fn diagnose(&self, now: Instant) -> Option<Problem> {
+        if now.saturating_duration_since(self.last_frame) >= self.stall_after {
+            Some(Problem::Stalled)
+        } else if self.repeats >= FROZEN_REPEATS {
+            Some(Problem::Frozen)
+        } else {
+            None
+        }
+    }
+
+    pub fn poll(&mut self, now: Instant) -> Option<WatchdogAction> {
+        if !self.watching {
+            return None;
+        }
+        let Some(problem) = self.diagnose(now) else {
+            if self.problem.take().is_some() {
+                self.step = 0;
+                return Some(WatchdogAction::Recovered);
+            }
+            return None;
+        };
+        self.problem = Some(problem);
+        if now < self.step_due {
+            return None;
+        }
+        while problem != Problem::Stalled && matches!(LADDER.get(self.step), Some((Step::Recover(_), _))) {
+            self.step += 1;
+        }
+        let &(step, wait) = LADDER.get(self.step)?;
+        self.step += 1;
+        self.step_due = now + wait;
+        Some(match step {
+            Step::Keyframe => WatchdogAction::RequestKeyframe(problem),
+            Step::Recover(action) => WatchdogAction::Recover(action, problem),
+            Step::Alert => WatchdogAction::Alert(problem),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STALL: Duration = Duration::from_secs(2);
+
+    fn frame(size: usize, keyframe: bool) -> VideoFrame {
+        VideoFrame::h264(vec![0; size], 1920, 1080, keyframe, Default::default())
+    }
+
+    #[test]
+    fn stall_escalates_step_by_step_and_recovers_on_frames() {
+        let start = Instant::now();
+        let mut watchdog = StreamWatchdog::new(STALL, start);
+        watchdog.set_watching(true, start);
+        watchdog.on_frame(false, start);
+        assert_eq!(watchdog.poll(start + Duration::from_secs(1)), None);
+
+        let mut now = start + STALL;
+        let mut actions = Vec::new();
+        for _ in 0..40 {
+            actions.extend(watchdog.poll(now));
+            now += Duration::from_secs(1);
+        }
+        assert_eq!(actions, [
+            WatchdogAction::RequestKeyframe(Problem::Stalled),
+            WatchdogAction::Recover(RecoveryAction::RestartIce, Problem::Stalled),
+            WatchdogAction::Recover(RecoveryAction::Renegotiate, Problem::Stalled),
+            WatchdogAction::Alert(Problem::Stalled),
+        ]);
+
+        watchdog.on_frame(false, now);
+        assert_eq!(watchdog.poll(now), Some(WatchdogAction::Recovered));
+        assert_eq!(watchdog.poll(now + STALL), Some(WatchdogAction::RequestKeyframe(Problem::Stalled)));
+    }
+
+    #[test]
+    fn only_skip_sized_p_frames_repeat_the_picture() {
+        // 8160 blocks at 1080p: up to 159 bytes
+        assert!(repeats_picture(&frame(24, false)));
+        assert!(repeats_picture(&frame(159, false)));
+        assert!(!repeats_picture(&frame(160, false)));
+        assert!(!repeats_picture(&frame(24, true)));
+        assert!(!repeats_picture(&VideoFrame::h264(vec![0; 24], 0, 0, false, Default::default())));
+    }
+
+    #[test]
+    fn repeated_picture_counts_as_frozen_until_it_moves() {
+        let start = Instant::now();
+        let mut watchdog = StreamWatchdog::new(STALL, start);
+        watchdog.set_watching(true, start);
+        let mut now = start;
+        for n in 0..FROZEN_REPEATS as u64 {
+            now = start + Duration::from_millis(n * 33);
+            watchdog.on_frame(repeats_picture(&frame(24, false)), now);
+        }
+        assert_eq!(watchdog.poll(now), Some(WatchdogAction::RequestKeyframe(Problem::Frozen)));
+
+        watchdog.on_frame(repeats_picture(&frame(4000, false)), now);
+        assert_eq!(watchdog.poll(now), Some(WatchdogAction::Recovered));
+    }
+
+    #[test]
+    fn frozen_picture_never_restarts_the_connection() {
+        let start = Instant::now();
+        let mut watchdog = StreamWatchdog::new(STALL, start);
+        watchdog.set_watching(true, start);
+        let mut actions = Vec::new();
+        // A static scene: skip-sized frames for 40 s over a healthy link
+        for n in 0..1200u64 {
+            let now = start + Duration::from_millis(n * 33);
+            watchdog.on_frame(true, now);
+            actions.extend(watchdog.poll(now));
+        }
+        assert_eq!(actions, [
+            WatchdogAction::RequestKeyframe(Problem::Frozen),
+            WatchdogAction::Alert(Problem::Frozen),
+        ]);
+    }
+
+    #[test]
+    fn reconnecting_resumes_the_ladder_where_it_was() {
+        let start = Instant::now();
+        let mut watchdog = StreamWatchdog::new(STALL, start);
+        watchdog.set_watching(true, start);
+        let now = start + STALL;
+        assert!(matches!(watchdog.poll(now), Some(WatchdogAction::RequestKeyframe(_))));
+        assert!(matches!(watchdog.poll(now + Duration::from_secs(2)), Some(WatchdogAction::Recover(..))));
+
+        // Renegotiating is left to ICE recovery while the link is down
+        watchdog.set_watching(false, now + Duration::from_secs(3));
+        assert_eq!(watchdog.poll(now + Duration::from_secs(20)), None);
+
+        // Back up, still no frames: the next step, not the first one again
+        let back = now + Duration::from_secs(20);
+        watchdog.set_watching(true, back);
+        assert_eq!(watchdog.poll(back + STALL), None);
+        assert_eq!(
+            watchdog.poll(back + Duration::from_secs(6)),
+            Some(WatchdogAction::Recover(RecoveryAction::Renegotiate, Problem::Stalled))
+        );
+    }
+}
@@ -1,110 +1,411 @@
 use anyhow::Result;
-use std::sync::Arc;
+use bytes::Bytes;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant, SystemTime};
 use tokio::sync::mpsc;
 use futures::{StreamExt, SinkExt};
 use tokio_tungstenite::{connect_async, tungstenite::Message};
 
+use crate::bandwidth::BandwidthEstimator;
+use crate::codec_policy::CodecPolicy;
+use crate::concealment::{ConcealmentTracker, Verdict};
+use crate::control::{self, ControlLink};
+use crate::depacketizer::{self, Codec};
+use crate::frame_timing::{CaptureClock, FrameClock, FrameTiming};
+use crate::ice_mux::{self, IceMux};
+use crate::jitter_buffer::{JitterBuffer, JitterStats};
+use crate::keyframe::KeyframeRequester;
+use crate::negotiation::Negotiation;
+use crate::pipeline::{PhoneLink, Pipeline};
+use crate::recovery::{self, IceRecovery, Link, RecoveryAction};
+use crate::transport_stats::{InterarrivalJitter, ReceiveCounters, TransportSample};
+use crate::turn_relay::TurnRelay;
+
+/// How long the track reader waits for a packet when the jitter buffer is empty
+const IDLE_WAIT: Duration = Duration::from_secs(1);
+/// Minimum spacing between jitter buffer log lines
+const JITTER_REPORT_INTERVAL: Duration = Duration::from_secs(5);
+/// How often the peer connection's stats are sampled into the transport history
+const TRANSPORT_STATS_INTERVAL: Duration = Duration::from_secs(1);
+/// Dynamic payload type range (RFC 3551)
+const FIRST_PAYLOAD_TYPE: u8 = 96;
+const LAST_PAYLOAD_TYPE: u8 = 127;
+
 /// Frame data sent from WebRTC to Virtual Camera
+#[derive(Clone)]
 pub struct VideoFrame {
-    pub data: Vec<u8>,
-    pub width: u32,  // Not used for H.264 stream (embedded in SPS)
-    pub height: u32, // Not used for H.264 stream
+    /// Annex-B access unit for H.264/H.265, raw frame for VP8/VP9, OBU temporal unit for AV1.
+    /// Pooled and shared: clones are cheap and don't copy the frame.
+    pub data: Bytes,
+    /// Picture size from the bitstream (SPS, VP8 keyframe header, VP9 scalability
+    /// structure, AV1 sequence header); 0 until known
+    pub width: u32,
+    pub height: u32,
+    /// Frames per second as (numerator, denominator), when the bitstream signals it
+    pub frame_rate: Option<(u32, u32)>,
+    pub codec: Codec,
+    /// Decodable on its own (IDR / IRAP / VP8, VP9 or AV1 keyframe)
+    pub keyframe: bool,
+    /// Presentation time, arrival time and RTP sequence range
+    pub timing: FrameTiming,
+}
+
+impl VideoFrame {
+    pub fn format(&self) -> VideoFormat {
+        VideoFormat {
+            codec: self.codec,
+            width: self.width,
+            height: self.height,
+            frame_rate: self.frame_rate,
+        }
+    }
+
+    /// H.264 frame for module tests
+    #[cfg(test)]
+    pub fn h264(data: impl Into<Bytes>, width: u32, height: u32, keyframe: bool, timing: FrameTiming) -> Self {
+        Self { data: data.into(), width, height, frame_rate: None, codec: Codec::H264, keyframe, timing }
+    }
+}
+
+/// Codec, size and frame rate of the incoming stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VideoFormat {
+    pub codec: Codec,
+    pub width: u32,
+    pub height: u32,
+    pub frame_rate: Option<(u32, u32)>,
+}
+
+/// What the WebRTC client publishes on the frame bus
+#[derive(Clone)]
+pub enum MediaEvent {
+    /// The stream's codec, size or frame rate changed; sent ahead of the first frame
+    /// in the new format so sinks can renegotiate
+    FormatChanged(VideoFormat),
+    Frame(VideoFrame),
+}
+
+/// Register the video codecs the policy accepts, in its preference order.
+fn register_codecs(media_engine: &mut webrtc::api::media_engine::MediaEngine, policy: &CodecPolicy) -> Result<()> {
+    use webrtc::rtp_transceiver::rtp_codec::{RTCRtpCodecCapability, RTCRtpCodecParameters, RTPCodecType};
+    use webrtc::rtp_transceiver::RTCPFeedback;
+
+    let feedback: Vec<RTCPFeedback> =
+        [("goog-remb", ""), ("transport-cc", ""), ("ccm", "fir"), ("nack", ""), ("nack", "pli")]
+        .into_iter()
+        .map(|(typ, parameter)| RTCPFeedback { typ: typ.to_owned(), parameter: parameter.to_owned() })
+        .collect();
+
+    for codec in policy.accepted().into_iter().filter(|c| !c.can_output()) {
+        println!("[Codecs] Accepting {} from the phone, but the virtual camera can't output it", codec.name());
+    }
+    let entries = policy.entries();
+    if entries.is_empty() {
+        anyhow::bail!("Codec policy accepts no codec (no H.264 profile-level-id or packetization mode?)");
+    }
+    // Dynamic payload types; the phone's offer decides the ones actually used
+    for (entry, payload_type) in entries.into_iter().zip(FIRST_PAYLOAD_TYPE..=LAST_PAYLOAD_TYPE) {
+        media_engine.register_codec(
+            RTCRtpCodecParameters {
+                capability: RTCRtpCodecCapability {
+                    mime_type: entry.codec.mime_type().to_owned(),
+                    clock_rate: 90000,
+                    channels: 0,
+                    sdp_fmtp_line: entry.sdp_fmtp_line,
+                    rtcp_feedback: feedback.clone(),
+                },
+                payload_type,
+                ..Default::default()
+            },
+            RTPCodecType::Video,
+        )?;
+    }
+    Ok(())
+}
+
+/// Cap the phone's send rate for `media_ssrc` at `bitrate` bps.
+async fn send_remb(pc: &Weak<webrtc::peer_connection::RTCPeerConnection>, media_ssrc: u32, bitrate: u64) {
+    use webrtc::rtcp::payload_feedbacks::receiver_estimated_maximum_bitrate::ReceiverEstimatedMaximumBitrate;
+
+    let Some(pc) = pc.upgrade() else { return };
+    let remb = ReceiverEstimatedMaximumBitrate {
+        sender_ssrc: 0,
+        bitrate: bitrate as f32,
+        ssrcs: vec![media_ssrc],
+    };
+    if let Err(e) = pc.write_rtcp(&[Box::new(remb)]).await {
+        eprintln!("[BWE] write_rtcp error: {}", e);
+    }
 }
 
-struct H264Assembler {
-    current_timestamp: u32,
-    frame_buffer: Vec<u8>,
-    fu_buffer: Vec<u8>,
-    in_fu: bool,
+/// Read one transport sample from the peer connection's stats: the selected
+/// candidate pair and its candidates, and the inbound video stream.
+async fn transport_sample(
+    pc: &webrtc::peer_connection::RTCPeerConnection,
+    counters: &ReceiveCounters,
+) -> TransportSample {
+    use webrtc::stats::StatsReportType;
+
+    let report = pc.get_stats().await;
+    let mut sample = TransportSample {
+        timestamp_ms: SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default(),
+        jitter_ms: counters.jitter_ms(),
+        packets_lost: counters.lost(),
+        estimated_bitrate_kbps: counters.estimate_bps().map(|bps| bps as f64 / 1000.0),
+        ..Default::default()
+    };
+    let mut pair = None;
+    for stats in report.reports.values() {
+        match stats {
+            StatsReportType::CandidatePair(p) if p.nominated => pair = Some(p),
+            StatsReportType::InboundRTP(rtp) if rtp.kind == "video" => {
+                sample.packets_received += rtp.packets_received;
+                sample.bytes_received += rtp.bytes_received;
+                sample.nack_count += rtp.nack_count;
+                sample.pli_count += rtp.pli_count.unwrap_or_default();
+                sample.fir_count += rtp.fir_count.unwrap_or_default();
+            }
+            _ => {}
+        }
+    }
+    if let Some(pair) = pair {
+        sample.rtt_ms = Some(pair.current_round_trip_time * 1000.0);
+        if let Some(StatsReportType::LocalCandidate(local)) = report.reports.get(&pair.local_candidate_id) {
+            sample.local_candidate_type = Some(local.candidate_type.to_string());
+            sample.local_address = Some(format!("{}:{}", local.ip, local.port));
+            sample.protocol = Some(local.network_type.to_string());
+        }
+        if let Some(StatsReportType::RemoteCandidate(remote)) = report.reports.get(&pair.remote_candidate_id) {
+            sample.remote_candidate_type = Some(remote.candidate_type.to_string());
+            sample.remote_address = Some(format!("{}:{}", remote.ip, remote.port));
+        }
+    }
+    sample
 }
 
-impl H264Assembler {
-    fn new() -> Self {
-        Self {
-            current_timestamp: 0,
-            frame_buffer: Vec::with_capacity(100 * 1024), // 100KB prealloc
-            fu_buffer: Vec::with_capacity(50 * 1024),
-            in_fu: false,
+/// Sample a peer connection's transport stats into the pipeline until it closes.
+async fn sample_transport(
+    pc: Weak<webrtc::peer_connection::RTCPeerConnection>,
+    counters: Arc<ReceiveCounters>,
+    pipeline: Pipeline,
+) {
+    use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+
+    let mut ticks = tokio::time::interval(TRANSPORT_STATS_INTERVAL);
+    loop {
+        ticks.tick().await;
+        let Some(pc) = pc.upgrade() else { break };
+        if pc.connection_state() == RTCPeerConnectionState::Closed {
+            break;
         }
+        pipeline.record_transport(transport_sample(&pc, &counters).await);
+    }
+}
+
+/// Answer `sdp` on `pc` and send the answer to the phone, shaped by `policy`.
+/// Also takes re-offers (ICE restarts, added or removed tracks) on a connection
+/// that is already up.
+async fn answer_offer(
+    pc: &webrtc::peer_connection::RTCPeerConnection,
+    sdp: &str,
+    policy: &CodecPolicy,
+    signal_tx: &mpsc::UnboundedSender<String>,
+    ice_mux: Option<&IceMux>,
+) -> Result<()> {
+    let offer = webrtc::peer_connection::sdp::session_description::RTCSessionDescription::offer(sdp.to_string())?;
+    pc.set_remote_description(offer).await?;
+    let answer = pc.create_answer(None).await?;
+    pc.set_local_description(answer.clone()).await?;
+
+    // The phone reads our limits from the answer; our own
+    // description keeps what webrtc-rs generated
+    let answer_msg = serde_json::json!({
+        "type": "answer",
+        "sdp": policy.shape_sdp(&answer.sdp),
+        "target": "phone"
+    });
+    let _ = signal_tx.send(answer_msg.to_string());
+    println!("[VCam Client] Answer sent");
+
+    // webrtc-rs only gathers UDP candidates, so advertise the
+    // passive ICE-TCP listener ourselves
+    if let Some(port) = ice_mux.and_then(|m| m.tcp_port()) {
+        let ip = local_ip_address::local_ip()
+            .unwrap_or(std::net::IpAddr::from([127, 0, 0, 1]));
+        let sdp_mid = answer.sdp.lines()
+            .find_map(|l| l.strip_prefix("a=mid:"))
+            .unwrap_or("0")
+            .trim()
+            .to_string();
+        let msg = serde_json::json!({
+            "type": "ice-candidate",
+            "candidate": ice_mux::passive_tcp_candidate(ip, port),
+            "sdp_mid": sdp_mid,
+            "sdp_m_line_index": 0,
+            "target": "phone"
+        });
+        let _ = signal_tx.send(msg.to_string());
     }
+    Ok(())
+}
 
-    fn push(&mut self, payload: &[u8], timestamp: u32) -> Option<VideoFrame> {
-        let mut completed_frame = None;
+/// Offer from our side on a connection that is up, e.g. to apply new receive limits
+/// without dropping the video. Skipped while another offer or answer is in flight;
+/// the phone's answer arrives as an `answer` message.
+async fn send_offer(
+    pc: &webrtc::peer_connection::RTCPeerConnection,
+    negotiation: &Negotiation,
+    policy: &CodecPolicy,
+    signal_tx: &mpsc::UnboundedSender<String>,
+) -> Result<()> {
+    use webrtc::peer_connection::signaling_state::RTCSignalingState;
+    if pc.signaling_state() != RTCSignalingState::Stable || !negotiation.begin_offer() {
+        return Ok(());
+    }
+    let result = async {
+        let offer = pc.create_offer(None).await?;
+        pc.set_local_description(offer.clone()).await?;
+        let msg = serde_json::json!({
+            "type": "offer",
+            "sdp": policy.shape_sdp(&offer.sdp),
+            "target": "phone"
+        });
+        let _ = signal_tx.send(msg.to_string());
+        println!("[VCam Client] Offer sent");
+        Ok(())
+    }
+    .await;
+    negotiation.end_offer();
+    result
+}
 
-        if self.current_timestamp != timestamp {
-            if !self.frame_buffer.is_empty() {
-                completed_frame = Some(VideoFrame {
-                    data: self.frame_buffer.clone(),
-                    width: 0,
-                    height: 0,
-                });
+/// Open the reliable, ordered control data channel on `pc` and hook it up to
+/// `control`. Adding it renegotiates from our side.
+async fn open_control_channel(pc: &webrtc::peer_connection::RTCPeerConnection, control: Arc<ControlLink>) {
+    let channel = match pc.create_data_channel(control::CHANNEL_LABEL, None).await {
+        Ok(channel) => channel,
+        Err(e) => {
+            eprintln!("[Control] Failed to create data channel: {}", e);
+            return;
+        }
+    };
+    let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+    let writer = Arc::downgrade(&channel);
+    tokio::spawn(async move {
+        while let Some(text) = rx.recv().await {
+            let Some(channel) = writer.upgrade() else { break };
+            if let Err(e) = channel.send_text(text).await {
+                eprintln!("[Control] Data channel send error: {}", e);
+                break;
             }
-            self.frame_buffer.clear();
-            self.current_timestamp = timestamp;
-            // Reset FU state on new frame? Ideally yes, but RTP can be out of order?
-            // Assuming simplified in-order delivery for now.
-            self.in_fu = false; 
-            self.fu_buffer.clear();
         }
+    });
+
+    let (control_o, tx_o) = (control.clone(), tx.clone());
+    channel.on_open(Box::new(move || {
+        control_o.set_channel(tx_o);
+        Box::pin(async {})
+    }));
+    let control_c = control.clone();
+    channel.on_close(Box::new(move || {
+        control_c.clear_channel(&tx);
+        Box::pin(async {})
+    }));
+    channel.on_message(Box::new(move |message| {
+        control.on_text(&String::from_utf8_lossy(&message.data));
+        Box::pin(async {})
+    }));
+}
+
+/// Which of a connection's video tracks feeds the bus: the first to deliver, and
+/// when it ends, the next one that does. A second camera added alongside the first
+/// waits its turn instead of interleaving with it.
+#[derive(Default)]
+struct TrackFeed {
+    /// SSRC of the feeding track; 0 for none
+    ssrc: AtomicU32,
+}
 
-        if payload.is_empty() {
-            return completed_frame;
+impl TrackFeed {
+    /// Whether `ssrc` feeds the bus, taking over if no track does
+    fn claim(&self, ssrc: u32) -> bool {
+        match self.ssrc.compare_exchange(0, ssrc, Ordering::AcqRel, Ordering::Acquire) {
+            Ok(_) => true,
+            Err(current) => current == ssrc,
         }
+    }
+
+    fn release(&self, ssrc: u32) {
+        let _ = self.ssrc.compare_exchange(ssrc, 0, Ordering::AcqRel, Ordering::Acquire);
+    }
+}
+
+/// The phone's current peer connection
+struct Session {
+    pc: Arc<webrtc::peer_connection::RTCPeerConnection>,
+    negotiation: Arc<Negotiation>,
+}
 
-        let header = payload[0];
-        let nalu_type = header & 0x1F;
-
-        if nalu_type == 28 { // FU-A
-            if payload.len() < 2 { return completed_frame; }
-            let fu_header = payload[1];
-            let s_bit = (fu_header & 0x80) != 0;
-            let e_bit = (fu_header & 0x40) != 0;
-            let original_type = (header & 0xE0) | (fu_header & 0x1F);
-
-            if s_bit {
-                self.in_fu = true;
-                self.fu_buffer.clear();
-                // Reconstruct header
-                self.fu_buffer.push(original_type);
-                self.fu_buffer.extend_from_slice(&payload[2..]);
-            } else if self.in_fu {
-                self.fu_buffer.extend_from_slice(&payload[2..]);
-                if e_bit {
-                    self.in_fu = false;
-                    // Append complete NALU to frame
-                    self.frame_buffer.extend_from_slice(&[0, 0, 0, 1]);
-                    self.frame_buffer.extend_from_slice(&self.fu_buffer);
+/// Watch one peer connection's state and, when it loses connectivity, ask the
+/// phone for an ICE restart, or for a new connection if restarts don't help. Also
+/// passes on what the stream watchdog asks for. Ends when the connection closes.
+async fn supervise_connection(
+    mut states: tokio::sync::watch::Receiver<webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState>,
+    mut requests: mpsc::UnboundedReceiver<RecoveryAction>,
+    signal_tx: mpsc::UnboundedSender<String>,
+) {
+    use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+    let mut recovery = IceRecovery::new();
+    loop {
+        let wait = recovery.next_deadline().map_or(IDLE_WAIT, |d| d.saturating_duration_since(Instant::now()));
+        let action = tokio::select! {
+            changed = states.changed() => {
+                if changed.is_err() {
+                    break;
                 }
+                let state = *states.borrow_and_update();
+                let link = match state {
+                    RTCPeerConnectionState::Connected => Link::Connected,
+                    RTCPeerConnectionState::Disconnected => Link::Disconnected,
+                    RTCPeerConnectionState::Failed => Link::Failed,
+                    RTCPeerConnectionState::Closed => break,
+                    _ => continue,
+                };
+                recovery.on_link(link, Instant::now());
+                recovery.poll(Instant::now())
             }
-        } else if nalu_type == 24 { // STAP-A
-            let mut offset = 1;
-            while offset + 2 < payload.len() {
-                let len = ((payload[offset] as usize) << 8) | (payload[offset+1] as usize);
-                offset += 2;
-                if offset + len > payload.len() { break; }
-                
-                self.frame_buffer.extend_from_slice(&[0, 0, 0, 1]);
-                self.frame_buffer.extend_from_slice(&payload[offset..offset+len]);
-                offset += len;
-            }
-        } else {
-            // Single NAL
-            self.frame_buffer.extend_from_slice(&[0, 0, 0, 1]);
-            self.frame_buffer.extend_from_slice(payload);
+            Some(action) = requests.recv() => Some(action),
+            _ = tokio::time::sleep(wait) => recovery.poll(Instant::now()),
+        };
+        let request = match action {
+            Some(RecoveryAction::RestartIce) => "ice-restart-request",
+            Some(RecoveryAction::Renegotiate) => "renegotiate-request",
+            None => continue,
+        };
+        println!("[Recovery] Sending {}", request);
+        let msg = serde_json::json!({ "type": request, "target": "phone" });
+        if signal_tx.send(msg.to_string()).is_err() {
+            break;
         }
-
-        completed_frame
     }
 }
 
 /// Start the Rust-side WebRTC client that connects to the signaling server
 /// and receives video frames for piping to the virtual camera.
 pub async fn start_virtual_cam_client(
-    frame_tx: mpsc::UnboundedSender<VideoFrame>,
+    pipeline: Pipeline,
+    control: Arc<ControlLink>,
+    turn_relay: Option<Arc<TurnRelay>>,
+    ice_mux: Option<Arc<IceMux>>,
 ) -> Result<()> {
     use tokio_tungstenite::tungstenite::Message as WsMsg;
-    tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+    use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
     println!("[VCam Client] Connecting to signaling server...");
+    let network = crate::settings::network();
     
     let url = "ws://127.0.0.1:3001/ws";
     let (ws_stream, _) = connect_async(url).await?;
@@ -113,10 +414,38 @@ pub async fn start_virtual_cam_client(
     
     println!("[VCam Client] Connected to signaling server");
     
+    let policy = crate::settings::video().negotiated_policy();
     let mut media_engine = webrtc::api::media_engine::MediaEngine::default();
-    media_engine.register_default_codecs()?;
+    register_codecs(&mut media_engine, &policy)?;
+    // The codecs list their RTCP feedback themselves, so the interceptors are added
+    // directly: the `configure_*` helpers would append the same feedback again
+    let mut registry = webrtc::interceptor::registry::Registry::new();
+    // Transport-wide congestion control feedback, so the phone's own estimator sees
+    // how its packets arrive; the track reader's REMB caps what it settles on
+    media_engine.register_header_extension(
+        webrtc::rtp_transceiver::rtp_codec::RTCRtpHeaderExtensionCapability {
+            uri: webrtc::sdp::extmap::TRANSPORT_CC_URI.to_owned(),
+        },
+        webrtc::rtp_transceiver::rtp_codec::RTPCodecType::Video,
+        None,
+    )?;
+    registry.add(Box::new(webrtc::interceptor::twcc::receiver::Receiver::builder()));
+    // NACKs for lost packets, so the phone retransmits them while the jitter buffer
+    // still waits; counted in the inbound stream's `nack_count`
+    registry.add(Box::new(webrtc::interceptor::nack::generator::Generator::builder()));
+    let max_bitrate = (policy.max_bitrate_kbps > 0).then(|| policy.max_bitrate_kbps as u64 * 1000);
+
+    // Single-port ICE for firewalled desktops: every session shares one UDP port
+    let mut setting_engine = webrtc::api::setting_engine::SettingEngine::default();
+    if let Some(ref mux) = ice_mux {
+        setting_engine.set_udp_network(webrtc::ice::udp_network::UDPNetwork::Muxed(mux.udp_mux()));
+        setting_engine.set_network_types(vec![webrtc::ice::network_type::NetworkType::Udp4]);
+    }
+
     let api = webrtc::api::APIBuilder::new()
         .with_media_engine(media_engine)
+        .with_setting_engine(setting_engine)
+        .with_interceptor_registry(registry)
         .build();
     
     let config = webrtc::peer_connection::configuration::RTCConfiguration {
@@ -127,20 +456,38 @@ pub async fn start_virtual_cam_client(
         ..Default::default()
     };
     
-    let mut current_pc: Option<Arc<webrtc::peer_connection::RTCPeerConnection>> = None;
-    let (ice_tx, mut ice_rx) = mpsc::unbounded_channel::<String>();
-    let ws_write_ice = ws_write.clone();
+    let mut current: Option<Session> = None;
+    let (signal_tx, mut signal_rx) = mpsc::unbounded_channel::<String>();
+    // Control requests fall back to signaling until the data channel opens
+    control.set_signaling(Some(signal_tx.clone()));
+    let ws_write_signal = ws_write.clone();
     tokio::spawn(async move {
-        while let Some(msg) = ice_rx.recv().await {
-            let mut w = ws_write_ice.lock().await;
+        while let Some(msg) = signal_rx.recv().await {
+            let mut w = ws_write_signal.lock().await;
             if let Err(e) = w.send(WsMsg::Text(msg.into())).await {
-                eprintln!("[VCam Client] ICE send error: {}", e);
+                eprintln!("[VCam Client] Signaling send error: {}", e);
                 break;
             }
         }
     });
     
-    while let Some(msg_result) = ws_read.next().await {
+    loop {
+        let msg_result = tokio::select! {
+            msg = ws_read.next() => match msg {
+                Some(msg) => msg,
+                None => break,
+            },
+            // New receive limits in the settings: re-offer on the current connection
+            _ = pipeline.offer_requested() => {
+                if let Some(session) = current.as_ref() {
+                    let policy = crate::settings::video().negotiated_policy();
+                    if let Err(e) = send_offer(&session.pc, &session.negotiation, &policy, &signal_tx).await {
+                        eprintln!("[VCam Client] Offer error: {}", e);
+                    }
+                }
+                continue;
+            }
+        };
         let msg = match msg_result {
             Ok(m) => m,
             Err(e) => {
@@ -155,21 +502,70 @@ pub async fn start_virtual_cam_client(
                 Err(_) => continue,
             };
             match json["type"].as_str() {
+                Some("phone-hello") => {
+                    pipeline.set_phone(PhoneLink::Negotiating);
+                    // Have the phone offer our codecs first, in our order
+                    let mime_types: Vec<&str> = policy.accepted().iter().map(|c| c.mime_type()).collect();
+                    let msg = serde_json::json!({
+                        "type": "codec-preferences",
+                        "codecs": mime_types,
+                        "target": "phone"
+                    });
+                    {
+                        let mut w = ws_write.lock().await;
+                        w.send(Message::Text(msg.to_string().into())).await?;
+                    }
+
+                    // Hand the phone per-session credentials for our TURN relay so it
+                    // can fall back to relayed candidates when host candidates fail
+                    if let Some(ref relay) = turn_relay {
+                        match relay.session_ice_server() {
+                            Ok(server) => {
+                                let msg = serde_json::json!({
+                                    "type": "ice-servers",
+                                    "iceServers": [server],
+                                    "target": "phone"
+                                });
+                                let mut w = ws_write.lock().await;
+                                w.send(Message::Text(msg.to_string().into())).await?;
+                            }
+                            Err(e) => eprintln!("[VCam Client] TURN credential error: {}", e),
+                        }
+                    }
+                }
                 Some("offer") => {
                     let sdp = json["sdp"].as_str().unwrap_or_default();
                     if sdp.is_empty() {
                         eprintln!("[VCam Client] Offer has empty SDP");
                         continue;
                     }
-                    if let Some(old) = current_pc.take() {
-                        let _ = old.close().await;
+                    // A re-offer from the phone's current connection (ICE restart, track
+                    // added or removed) is answered in place, so the video carries on
+                    if let Some(session) = current.as_ref() {
+                        let remote = session.pc.remote_description().await.map(|d| d.sdp).unwrap_or_default();
+                        if recovery::same_session(&remote, sdp) {
+                            use webrtc::peer_connection::signaling_state::RTCSignalingState;
+                            let stable = session.pc.signaling_state() == RTCSignalingState::Stable;
+                            if !session.negotiation.accept_offer(stable) {
+                                println!("[VCam Client] Re-offer collided with ours, waiting for the phone's answer");
+                                continue;
+                            }
+                            println!("[VCam Client] Received re-offer, answering on the current connection...");
+                            let policy = crate::settings::video().negotiated_policy();
+                            answer_offer(&session.pc, sdp, &policy, &signal_tx, ice_mux.as_deref()).await?;
+                            continue;
+                        }
+                    }
+                    if let Some(old) = current.take() {
+                        let _ = old.pc.close().await;
                     }
                     println!("[VCam Client] Received offer, creating answer...");
+                    pipeline.set_phone(PhoneLink::Negotiating);
                     
                     let pc = Arc::new(api.new_peer_connection(config.clone()).await?);
-                    let ice_tx_c = ice_tx.clone();
+                    let signal_tx_c = signal_tx.clone();
                     pc.on_ice_candidate(Box::new(move |candidate| {
-                        let tx = ice_tx_c.clone();
+                        let tx = signal_tx_c.clone();
                         Box::pin(async move {
                             if let Some(c) = candidate {
                                 let json = c.to_json().unwrap();
@@ -184,53 +580,229 @@ pub async fn start_virtual_cam_client(
                             }
                         })
                     }));
-                    let frame_tx_c = frame_tx.clone();
+                    let pipeline_t = pipeline.clone();
+                    let counters = Arc::new(ReceiveCounters::default());
+                    let counters_t = counters.clone();
+                    let jitter_latency = Duration::from_millis(network.jitter_buffer_ms as u64);
+                    let pc_weak = Arc::downgrade(&pc);
+                    let feed = Arc::new(TrackFeed::default());
                     pc.on_track(Box::new(move |track, _receiver, _transceiver| {
-                        let tx = frame_tx_c.clone();
+                        let pipeline = pipeline_t.clone();
+                        let counters = counters_t.clone();
+                        let feed = feed.clone();
+                        let keyframes = Arc::new(KeyframeRequester::new(pc_weak.clone(), track.ssrc()));
+                        let remb_pc = pc_weak.clone();
                         Box::pin(async move {
-                            println!("[VCam Client] Track received: codec={}", track.codec().capability.mime_type);
+                            let capability = track.codec().capability;
+                            println!("[VCam Client] Track received: kind={} codec={}", track.kind(), capability.mime_type);
+                            if track.kind() != webrtc::rtp_transceiver::rtp_codec::RTPCodecType::Video {
+                                println!("[VCam Client] Not a video track, ignoring it");
+                                return;
+                            }
+                            let Some(codec) = Codec::from_mime_type(&capability.mime_type) else {
+                                eprintln!("[VCam Client] Unsupported video codec {}, ignoring track", capability.mime_type);
+                                return;
+                            };
+                            let ssrc = track.ssrc();
+                            let mut feeding = false;
                             let mut buf = vec![0u8; 1500];
-                            let mut assembler = H264Assembler::new();
+                            let mut depacketizer = depacketizer::for_codec(codec, &capability.sdp_fmtp_line);
+                            let mut concealment = ConcealmentTracker::new();
+                            let mut clock = FrameClock::new();
+                            // Only this task writes it, so placing frames takes no lock
+                            let mut capture_clock = CaptureClock::new();
+                            let mut format: Option<VideoFormat> = None;
+                            let mut jitter = JitterBuffer::new(jitter_latency);
+                            let mut interarrival = InterarrivalJitter::new();
+                            let mut bwe = BandwidthEstimator::new(max_bitrate);
+                            let mut logged_estimate = 0u64;
+                            let mut reported = jitter.stats();
+                            let mut published = jitter.stats();
+                            let mut last_report = Instant::now();
                             loop {
-                                match track.read(&mut buf).await {
-                                    Ok((rtp_packet, _attributes)) => {
-                                        let payload = &rtp_packet.payload;
-                                        if let Some(frame) = assembler.push(payload, rtp_packet.header.timestamp) {
-                                            let _ = tx.send(frame);
+                                // Wake up for the head-of-line deadline even if no packet arrives
+                                let wait = jitter.next_deadline()
+                                    .map(|d| d.saturating_duration_since(Instant::now()))
+                                    .unwrap_or(IDLE_WAIT);
+                                match tokio::time::timeout(wait, track.read(&mut buf)).await {
+                                    Ok(Ok((rtp_packet, _attributes))) => {
+                                        // Another video track feeds the camera; drain this one
+                                        // until it's our turn
+                                        if !feeding {
+                                            if !feed.claim(ssrc) {
+                                                continue;
+                                            }
+                                            feeding = true;
+                                            KeyframeRequester::set_active(&keyframes);
+                                            // Start from a clean picture rather than waiting for the next periodic IDR
+                                            keyframes.request("track start").await;
                                         }
+                                        let arrival = Instant::now();
+                                        interarrival.on_packet(rtp_packet.header.timestamp, arrival);
+                                        bwe.on_packet(rtp_packet.header.timestamp, rtp_packet.payload.len(), arrival);
+                                        jitter.push(rtp_packet, arrival);
                                     }
-                                    Err(e) => {
+                                    Ok(Err(e)) => {
                                         println!("[VCam Client] Track read error: {}", e);
                                         break;
                                     }
+                                    Err(_) if !feeding => continue,
+                                    Err(_) => {} // deadline reached, release what's due
+                                }
+
+                                let now = Instant::now();
+                                let mut lost = jitter.stats().lost;
+                                let mut loss_seen = false;
+                                while let Some(released) = jitter.pop(now) {
+                                    // A sequence gap means a broken reference chain: conceal
+                                    // the frame it hit and ask for a fresh IDR
+                                    if jitter.stats().lost > lost {
+                                        let gap = jitter.stats().lost - lost;
+                                        lost = jitter.stats().lost;
+                                        loss_seen = true;
+                                        concealment.packets_lost(released.seq.saturating_sub(gap)..released.seq);
+                                    }
+                                    let header = &released.packet.header;
+                                    clock.on_packet(header.timestamp, released.seq, header.marker, released.arrival);
+                                    for mut frame in depacketizer.push(&released.packet.payload, header.timestamp, header.marker) {
+                                        clock.stamp(&mut frame);
+                                        if frame.keyframe {
+                                            keyframes.keyframe_received();
+                                        }
+                                        let mut frame = match concealment.inspect(&frame) {
+                                            Verdict::Forward => frame,
+                                            Verdict::Repeat(repeat) => {
+                                                pipeline.frame_withheld();
+                                                repeat
+                                            }
+                                            Verdict::Drop => {
+                                                pipeline.frame_withheld();
+                                                continue;
+                                            }
+                                        };
+                                        let frame_format = frame.format();
+                                        if frame_format.width > 0 && format != Some(frame_format) {
+                                            println!(
+                                                "[VCam Client] Stream format: {} {}x{} @ {}",
+                                                codec.name(), frame_format.width, frame_format.height,
+                                                frame_format.frame_rate
+                                                    .map(|(num, den)| format!("{:.2} fps", num as f64 / den as f64))
+                                                    .unwrap_or_else(|| "unknown rate".into())
+                                            );
+                                            format = Some(frame_format);
+                                            pipeline.publish(MediaEvent::FormatChanged(frame_format));
+                                        }
+                                        capture_clock.place(&mut frame, Instant::now());
+                                        pipeline.publish(MediaEvent::Frame(frame));
+                                    }
+                                }
+
+                                pipeline.set_jitter_depth(jitter.depth());
+                                if jitter.stats() != published {
+                                    published = jitter.stats();
+                                    pipeline.set_jitter_stats(published);
+                                }
+                                counters.update(&interarrival, jitter.stats().lost);
+
+                                if let Some(bitrate) = bwe.update(now, jitter.stats().lost) {
+                                    counters.set_estimate(bitrate);
+                                    if bitrate.abs_diff(logged_estimate) * 10 > logged_estimate {
+                                        println!("[BWE] Estimate {} kbps (receiving {:.0} kbps)", bitrate / 1000, bwe.incoming_bitrate(now) / 1000.0);
+                                        logged_estimate = bitrate;
+                                    }
+                                    send_remb(&remb_pc, track.ssrc(), bitrate).await;
+                                }
+
+                                if loss_seen {
+                                    keyframes.request("packet loss").await;
+                                } else {
+                                    keyframes.poll().await;
+                                }
+
+                                let stats = jitter.stats();
+                                if stats != reported && last_report.elapsed() >= JITTER_REPORT_INTERVAL {
+                                    println!(
+                                        "[VCam Client] Jitter buffer: depth={} reordered={} late={} lost={} dup={}",
+                                        jitter.depth(), stats.reordered, stats.late, stats.lost, stats.duplicates
+                                    );
+                                    reported = stats;
+                                    last_report = Instant::now();
                                 }
                             }
+                            KeyframeRequester::clear_active(&keyframes);
+                            if feeding {
+                                feed.release(ssrc);
+                                pipeline.set_jitter_depth(0);
+                                pipeline.set_jitter_stats(JitterStats::default());
+                            }
                         })
                     }));
-                    pc.on_peer_connection_state_change(Box::new(|state| {
+                    let pipeline_c = pipeline.clone();
+                    let (link_tx, link_rx) = tokio::sync::watch::channel(RTCPeerConnectionState::New);
+                    pc.on_peer_connection_state_change(Box::new(move |state| {
                         println!("[VCam Client] Connection state: {}", state);
+                        match state {
+                            RTCPeerConnectionState::Connected => pipeline_c.set_phone(PhoneLink::Connected),
+                            // Recovery takes it from here; the phone comes back with a
+                            // restarted or new connection
+                            RTCPeerConnectionState::Disconnected | RTCPeerConnectionState::Failed => {
+                                pipeline_c.set_phone(PhoneLink::Interrupted)
+                            }
+                            // Closed is our own doing: a new offer replaced this connection,
+                            // or the client is shutting down and reports that itself
+                            _ => {}
+                        }
+                        let _ = link_tx.send(state);
                         Box::pin(async {})
                     }));
-                    
-                    let offer = webrtc::peer_connection::sdp::session_description::RTCSessionDescription::offer(sdp.to_string())?;
-                    pc.set_remote_description(offer).await?;
-                    let answer = pc.create_answer(None).await?;
-                    pc.set_local_description(answer.clone()).await?;
-                    
-                    let answer_msg = serde_json::json!({
-                        "type": "answer",
-                        "sdp": answer.sdp,
-                        "target": "phone"
-                    });
-                    {
-                        let mut w = ws_write.lock().await;
-                        w.send(Message::Text(answer_msg.to_string().into())).await?;
+                    let (recovery_tx, recovery_rx) = mpsc::unbounded_channel();
+                    pipeline.set_recovery_requests(recovery_tx);
+                    tokio::spawn(supervise_connection(link_rx, recovery_rx, signal_tx.clone()));
+
+                    let negotiation = Arc::new(Negotiation::new(false));
+                    let negotiation_c = negotiation.clone();
+                    let offer_pc = Arc::downgrade(&pc);
+                    let signal_tx_o = signal_tx.clone();
+                    pc.on_negotiation_needed(Box::new(move || {
+                        let negotiation = negotiation_c.clone();
+                        let pc = offer_pc.clone();
+                        let tx = signal_tx_o.clone();
+                        Box::pin(async move {
+                            let Some(pc) = pc.upgrade() else { return };
+                            let policy = crate::settings::video().negotiated_policy();
+                            if let Err(e) = send_offer(&pc, &negotiation, &policy, &tx).await {
+                                eprintln!("[VCam Client] Offer error: {}", e);
+                            }
+                        })
+                    }));
+
+                    answer_offer(&pc, sdp, &crate::settings::video().negotiated_policy(), &signal_tx, ice_mux.as_deref()).await?;
+                    open_control_channel(&pc, control.clone()).await;
+                    tokio::spawn(sample_transport(Arc::downgrade(&pc), counters, pipeline.clone()));
+                    current = Some(Session { pc, negotiation });
+                }
+                Some("answer") => {
+                    let sdp = json["sdp"].as_str().unwrap_or_default();
+                    let Some(session) = current.as_ref() else { continue };
+                    let answer = match webrtc::peer_connection::sdp::session_description::RTCSessionDescription::answer(sdp.to_string()) {
+                        Ok(answer) => answer,
+                        Err(e) => {
+                            eprintln!("[VCam Client] Bad answer: {}", e);
+                            continue;
+                        }
+                    };
+                    // Stale if it answers an offer the phone rolled back in favour of ours
+                    match session.pc.set_remote_description(answer).await {
+                        Ok(()) => println!("[VCam Client] Answer received"),
+                        Err(e) => eprintln!("[VCam Client] Ignoring answer: {}", e),
                     }
-                    println!("[VCam Client] Answer sent");
-                    current_pc = Some(pc);
                 }
+                Some("control") => match serde_json::from_value(json["frame"].clone()) {
+                    Ok(frame) => control.on_frame(frame),
+                    Err(e) => eprintln!("[Control] Bad message: {}", e),
+                },
                 Some("ice-candidate") => {
-                    if let Some(ref pc) = current_pc {
+                    if let Some(Session { pc, negotiation }) = current.as_ref() {
                         let candidate = json["candidate"].as_str().unwrap_or_default();
                         let sdp_mid = json["sdp_mid"].as_str().map(|s| s.to_string());
                         let sdp_mline_index = json["sdp_m_line_index"].as_u64().map(|n| n as u16);
@@ -241,7 +813,10 @@ pub async fn start_virtual_cam_client(
                             username_fragment: None,
                         };
                         if let Err(e) = pc.add_ice_candidate(ice).await {
-                            eprintln!("[VCam Client] add_ice_candidate error: {}", e);
+                            // Candidates of an offer we ignored can't apply
+                            if !negotiation.ignoring_offer() {
+                                eprintln!("[VCam Client] add_ice_candidate error: {}", e);
+                            }
                         }
                     }
                 }
@@ -249,6 +824,6 @@ pub async fn start_virtual_cam_client(
             }
         }
     }
-    
+
     Ok(())
 }
@@ -0,0 +1,186 @@
+import { useState, useEffect, useCallback, useRef } from 'react';
+import { invoke } from '@tauri-apps/api/core';
+import { useToast } from '../Toast';
+import { IconFlipCamera } from '../Icons';
+import type { CameraState, FocusMode, Range } from './camera';
+
+const RESOLUTIONS = [
+    { label: '640 × 480', width: 640, height: 480 },
+    { label: '1280 × 720', width: 1280, height: 720 },
+    { label: '1920 × 1080', width: 1920, height: 1080 },
+    { label: '3840 × 2160', width: 3840, height: 2160 },
+];
+const FRAME_RATES = [15, 24, 30, 60];
+/** Quiet time after the last slider change before it goes to the phone */
+const SLIDER_DEBOUNCE_MS = 150;
+const FOCUS_LABELS: Record<FocusMode, string> = {
+    'continuous': 'Continuous',
+    'single-shot': 'Single shot',
+    'manual': 'Manual',
+};
+
+const fits = (value: number, range: Range | null) => !range || (value >= range.min && value <= range.max);
+
+/** Zoom, torch, focus and the rest of the phone's camera, over the control channel. */
+export default function CameraControls() {
+    const [camera, setCamera] = useState<CameraState | null>(null);
+    const [busy, setBusy] = useState(false);
+    const pending = useRef<Record<string, number>>({});
+    const toast = useToast();
+
+    useEffect(() => {
+        // Asked once the phone is connected; the control channel may still be opening
+        const timer = window.setTimeout(() => {
+            invoke<CameraState>('get_camera_state')
+                .then(setCamera)
+                .catch(e => console.warn('[Camera] No camera state:', e));
+        }, 500);
+        return () => clearTimeout(timer);
+    }, []);
+
+    useEffect(() => () => Object.values(pending.current).forEach(clearTimeout), []);
+
+    const send = useCallback(async (command: string, args: Record<string, unknown> = {}) => {
+        setBusy(true);
+        try {
+            setCamera(await invoke<CameraState>(command, args));
+        } catch (e) {
+            toast.show(`Camera: ${e}`, 'error');
+        } finally {
+            setBusy(false);
+        }
+    }, [toast]);
+
+    // Sliders update locally on every change, by pointer or keyboard, and send once
+    // they settle
+    const slide = (key: 'zoom' | 'exposureCompensation', value: number, command: string, arg: string) => {
+        setCamera(c => c && { ...c, settings: { ...c.settings, [key]: value } });
+        clearTimeout(pending.current[command]);
+        pending.current[command] = window.setTimeout(() => {
+            delete pending.current[command];
+            send(command, { [arg]: value });
+        }, SLIDER_DEBOUNCE_MS);
+    };
+
+    if (!camera) return null;
+    const { capabilities: caps, settings } = camera;
+
+    return (
+        <div className="sidebar-section">
+            <div className="panel-header">
+                <span>Camera</span>
+                <button
+                    className="btn btn-icon"
+                    title="Switch camera"
+                    disabled={busy}
+                    onClick={() => send('switch_camera', { facing: null })}
+                >
+                    <IconFlipCamera size={13} />
+                </button>
+            </div>
+
+            <div className="camera-controls">
+                {camera.label && <p className="form-hint">{camera.label}</p>}
+
+                {caps.zoom && (
+                    <div className="form-group">
+                        <label className="form-label">Zoom ({(settings.zoom ?? caps.zoom.min).toFixed(1)}×)</label>
+                        <input
+                            type="range"
+                            min={caps.zoom.min}
+                            max={caps.zoom.max}
+                            step={caps.zoom.step ?? 0.1}
+                            value={settings.zoom ?? caps.zoom.min}
+                            onChange={e => slide('zoom', parseFloat(e.target.value), 'set_zoom', 'zoom')}
+                        />
+                    </div>
+                )}
+
+                {caps.exposureCompensation && (
+                    <div className="form-group">
+                        <label className="form-label">
+                            Exposure ({(settings.exposureCompensation ?? 0).toFixed(1)} EV)
+                        </label>
+                        <input
+                            type="range"
+                            min={caps.exposureCompensation.min}
+                            max={caps.exposureCompensation.max}
+                            step={caps.exposureCompensation.step ?? 0.1}
+                            value={settings.exposureCompensation ?? 0}
+                            onChange={e =>
+                                slide('exposureCompensation', parseFloat(e.target.value), 'set_exposure_compensation', 'value')
+                            }
+                        />
+                    </div>
+                )}
+
+                {caps.torch && (
+                    <div className="form-group">
+                        <label className="form-label">Torch</label>
+                        <div
+                            className={`toggle ${settings.torch ? 'active' : ''}`}
+                            onClick={() => !busy && send('set_torch', { on: !settings.torch })}
+                        />
+                    </div>
+                )}
+
+                {caps.focusModes.length > 0 && (
+                    <div className="form-group">
+                        <label className="form-label">Focus</label>
+                        <select
+                            className="select"
+                            value={settings.focusMode ?? ''}
+                            disabled={busy}
+                            onChange={e => send('set_focus_mode', { mode: e.target.value })}
+                        >
+                            {caps.focusModes.map(m => (
+                                <option key={m} value={m}>{FOCUS_LABELS[m]}</option>
+                            ))}
+                        </select>
+                    </div>
+                )}
+
+                <div className="form-group">
+                    <label className="form-label">Resolution</label>
+                    <select
+                        className="select"
+                        value={`${settings.width}x${settings.height}`}
+                        disabled={busy}
+                        onChange={e => {
+                            const [width, height] = e.target.value.split('x').map(Number);
+                            send('set_resolution', { width, height });
+                        }}
+                    >
+                        {!RESOLUTIONS.some(r => r.width === settings.width && r.height === settings.height) && (
+                            <option value={`${settings.width}x${settings.height}`}>
+                                {settings.width ?? '?'} × {settings.height ?? '?'}
+                            </option>
+                        )}
+                        {RESOLUTIONS
+                            .filter(r => fits(r.width, caps.width) && fits(r.height, caps.height))
+                            .map(r => (
+                                <option key={r.label} value={`${r.width}x${r.height}`}>{r.label}</option>
+                            ))}
+                    </select>
+                </div>
+
+                <div className="form-group">
+                    <label className="form-label">Frame Rate</label>
+                    <select
+                        className="select"
+                        value={settings.frameRate != null ? Math.round(settings.frameRate) : ''}
+                        disabled={busy}
+                        onChange={e => send('set_framerate', { fps: Number(e.target.value) })}
+                    >
+                        {settings.frameRate != null && !FRAME_RATES.includes(Math.round(settings.frameRate)) && (
+                            <option value={Math.round(settings.frameRate)}>{Math.round(settings.frameRate)} fps</option>
+                        )}
+                        {FRAME_RATES.filter(fps => fits(fps, caps.frameRate)).map(fps => (
+                            <option key={fps} value={fps}>{fps} fps</option>
+                        ))}
+                    </select>
+                </div>
+            </div>
+        </div>
+    );
+}
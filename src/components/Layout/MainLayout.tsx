@@ -1,5 +1,6 @@
 import { useState, useEffect, useRef, useCallback } from 'react';
 import { invoke } from '@tauri-apps/api/core';
+import { listen } from '@tauri-apps/api/event';
 import Sidebar from './Sidebar';
 import PreviewPanel from './PreviewPanel';
 import ControlBar from './ControlBar';
@@ -7,6 +8,16 @@ import StatusBar from './StatusBar';
 import SettingsModal from '../Settings/SettingsModal';
 import LoadingScreen from './LoadingScreen';
 import { useToast } from '../Toast';
+import {
+    ALERT_EVENT,
+    PipelineStats,
+    PipelineStatus,
+    STATE_EVENT,
+    STATS_EVENT,
+    StreamAlert,
+    Transition,
+} from './pipeline';
+import { DeviceTelemetry, LOW_BATTERY_EVENT, LowBattery, TELEMETRY_EVENT } from './telemetry';
 import '../../styles/theme.css';
 import './Layout.css';
 
@@ -32,6 +43,8 @@ export default function MainLayout() {
         fps: 0,
         status: 'disconnected',
     });
+    const [pipelineStats, setPipelineStats] = useState<PipelineStats | null>(null);
+    const [telemetry, setTelemetry] = useState<DeviceTelemetry | null>(null);
     const [connectedDevices, setConnectedDevices] = useState<
         { id: string; deviceName: string; platform: string }[]
     >([]);
@@ -71,16 +84,53 @@ export default function MainLayout() {
         } catch {}
     }, []);
 
-    // ── Virtual cam status polling ──────────────────────────────────────────
+    // ── Pipeline state and stats (pushed by the backend) ────────────────────
     useEffect(() => {
-        const interval = setInterval(async () => {
-            try {
-                const statusStr = await invoke<string>('get_virtual_cam_status');
-                const status = JSON.parse(statusStr);
-                setVirtualCamActive(status.active);
-            } catch {}
-        }, 1000);
-        return () => clearInterval(interval);
+        let lastError: string | null = null;
+        const applyStatus = (status: PipelineStatus) => {
+            setVirtualCamActive(status.cameraActive);
+            // e.g. the phone negotiated a codec the virtual camera can't take
+            if (status.error && status.error !== lastError) {
+                toastShowRef.current(status.error, 'error');
+            }
+            lastError = status.error;
+        };
+        // The watchdog already tried keyframes, an ICE restart and renegotiating
+        const applyAlert = (alert: StreamAlert) => {
+            if (alert.problem === 'stalled') {
+                toastShowRef.current('No video from the phone — check that its screen is on and the page is open', 'warning');
+            } else if (alert.problem === 'frozen') {
+                toastShowRef.current('The phone\'s picture is frozen — bring its camera page to the front', 'warning');
+            } else {
+                toastShowRef.current('Video from the phone resumed', 'success');
+            }
+        };
+        invoke<PipelineStatus>('get_virtual_cam_status').then(applyStatus).catch(() => {});
+        const unlisten = [
+            listen<PipelineStatus>(STATE_EVENT, e => applyStatus(e.payload)),
+            listen<PipelineStats>(STATS_EVENT, e => setPipelineStats(e.payload)),
+            listen<StreamAlert>(ALERT_EVENT, e => applyAlert(e.payload)),
+        ];
+        return () => {
+            unlisten.forEach(p => p.then(stop => stop()));
+        };
+    }, []);
+
+    // ── Phone telemetry (battery, CPU pressure, camera) ─────────────────────
+    useEffect(() => {
+        invoke<DeviceTelemetry | null>('get_device_telemetry').then(setTelemetry).catch(() => {});
+        const unlisten = [
+            listen<DeviceTelemetry>(TELEMETRY_EVENT, e => setTelemetry(e.payload)),
+            listen<LowBattery>(LOW_BATTERY_EVENT, e =>
+                toastShowRef.current(
+                    `Phone battery at ${Math.round(e.payload.level * 100)}% — plug it in to keep streaming`,
+                    'warning'
+                )
+            ),
+        ];
+        return () => {
+            unlisten.forEach(p => p.then(stop => stop()));
+        };
     }, []);
 
     // ── Drain MSE queue ─────────────────────────────────────────────────────
@@ -295,7 +345,6 @@ export default function MainLayout() {
                         const s = JSON.parse(saved);
                         if (s.virtualCam?.autoStart) {
                             invoke('start_virtual_cam').catch(() => {});
-                            setVirtualCamActive(true);
                         }
                     }
                 } catch {}
@@ -330,12 +379,12 @@ export default function MainLayout() {
     const toggleVirtualCam = async () => {
         try {
             if (virtualCamActive) {
-                await invoke('stop_virtual_cam');
-                setVirtualCamActive(false);
+                const t = await invoke<Transition>('stop_virtual_cam');
+                setVirtualCamActive(t.to !== 'stopped' && t.to !== 'idle');
                 toast.show('Virtual Camera stopped', 'info');
             } else {
-                await invoke('start_virtual_cam');
-                setVirtualCamActive(true);
+                const t = await invoke<Transition>('start_virtual_cam');
+                setVirtualCamActive(t.to !== 'stopped' && t.to !== 'idle');
                 toast.show('Virtual Camera started', 'success');
             }
         } catch (e) {
@@ -355,17 +404,33 @@ export default function MainLayout() {
         }
     };
 
+    // While the virtual camera is fed, show what the backend measures rather than
+    // the preview relay
+    const displayStats: ConnectionStats = pipelineStats?.state === 'streaming'
+        ? {
+            ...connectionStats,
+            status: 'live',
+            resolution: pipelineStats.width
+                ? `${pipelineStats.width}x${pipelineStats.height}`
+                : connectionStats.resolution,
+            fps: Math.round(pipelineStats.fpsOut),
+            bitrate: Math.round(pipelineStats.bitrateKbps),
+            latency: Math.round(pipelineStats.latencyMs ?? 0),
+        }
+        : connectionStats;
+
     return (
         <div className="main-layout">
             <div className="layout-content">
                 <Sidebar
                     status={connectionStats.status}
                     connectedDevices={connectedDevices}
+                    telemetry={telemetry}
                 />
                 <PreviewPanel
                     videoRef={videoRef}
                     status={connectionStats.status}
-                    stats={connectionStats}
+                    stats={displayStats}
                     mirror={mirrorVideo}
                 />
             </div>
@@ -376,7 +441,7 @@ export default function MainLayout() {
                 status={connectionStats.status}
                 connectedCount={connectedDevices.length}
             />
-            <StatusBar stats={connectionStats} />
+            <StatusBar stats={displayStats} pipeline={pipelineStats} />
 
             {showSettings && (
                 <SettingsModal
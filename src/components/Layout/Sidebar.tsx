@@ -2,6 +2,8 @@ import { useState, useEffect } from 'react';
 import { invoke } from '@tauri-apps/api/core';
 import { QRCodeSVG } from 'qrcode.react';
 import { IconPlus, IconPhone, IconCopy } from '../Icons';
+import CameraControls from './CameraControls';
+import type { DeviceTelemetry } from './telemetry';
 
 interface ConnectedDevice {
     id: string;
@@ -12,6 +14,7 @@ interface ConnectedDevice {
 interface SidebarProps {
     status: 'disconnected' | 'connecting' | 'connected' | 'live';
     connectedDevices?: ConnectedDevice[];
+    telemetry?: DeviceTelemetry | null;
 }
 
 interface ConnectionInfo {
@@ -20,7 +23,18 @@ interface ConnectionInfo {
     https_port: number;
 }
 
-export default function Sidebar({ status, connectedDevices = [] }: SidebarProps) {
+/** Battery, CPU pressure and capture rate, e.g. "54% charging · CPU pressure serious · 29.8 fps" */
+function describeTelemetry(t: DeviceTelemetry): string {
+    const parts: string[] = [];
+    if (t.battery) {
+        parts.push(`${Math.round(t.battery.level * 100)}%${t.battery.charging ? ' charging' : ''}`);
+    }
+    if (t.thermal === 'serious' || t.thermal === 'critical') parts.push(`CPU pressure ${t.thermal}`);
+    if (t.captureFps != null) parts.push(`${t.captureFps.toFixed(1)} fps`);
+    return parts.join(' · ');
+}
+
+export default function Sidebar({ status, connectedDevices = [], telemetry = null }: SidebarProps) {
     const [connInfo, setConnInfo] = useState<ConnectionInfo | null>(null);
     const [copied, setCopied] = useState(false);
 
@@ -86,10 +100,21 @@ export default function Sidebar({ status, connectedDevices = [] }: SidebarProps)
                                 ))}
                             </div>
                         )}
+                        {telemetry && status !== 'disconnected' && describeTelemetry(telemetry) && (
+                            <div
+                                className={`device-telemetry${telemetry.thermal === 'critical' ? ' warning' : ''}`}
+                                title="CPU pressure is the phone browser's Compute Pressure reading, a proxy for heat"
+                            >
+                                {describeTelemetry(telemetry)}
+                            </div>
+                        )}
                     </div>
                 </div>
             </div>
 
+            {/* Phone camera controls, once there is a phone to control */}
+            {(status === 'connected' || status === 'live') && <CameraControls />}
+
             {/* Connect section */}
             <div className="sidebar-section">
                 <div className="panel-header">
@@ -1,3 +1,5 @@
+import { PipelineStats } from './pipeline';
+
 interface ConnectionStats {
     latency: number;
     bitrate: number;
@@ -8,11 +10,12 @@ interface ConnectionStats {
 
 interface StatusBarProps {
     stats: ConnectionStats;
+    pipeline?: PipelineStats | null;
 }
 
 const VERSION = 'v0.2.0';
 
-export default function StatusBar({ stats }: StatusBarProps) {
+export default function StatusBar({ stats, pipeline }: StatusBarProps) {
     const getStatusText = () => {
         switch (stats.status) {
             case 'live': return 'Streaming';
@@ -61,6 +64,47 @@ export default function StatusBar({ stats }: StatusBarProps) {
                         </div>
                     </>
                 )}
+
+                {pipeline?.state === 'streaming' && (
+                    <>
+                        <div className="status-item">
+                            <span>Codec:</span>
+                            <span className="status-value">{pipeline.codec ?? '-'}</span>
+                        </div>
+                        <div className="status-item">
+                            <span>Keyframes:</span>
+                            <span className="status-value">
+                                {pipeline.keyframeIntervalMs != null
+                                    ? `${(pipeline.keyframeIntervalMs / 1000).toFixed(1)}s`
+                                    : '-'}
+                            </span>
+                        </div>
+                        <div className="status-item">
+                            <span>Dropped:</span>
+                            <span className="status-value">{pipeline.droppedFrames}</span>
+                        </div>
+                        <div className="status-item">
+                            <span>Jitter buffer:</span>
+                            <span
+                                className="status-value"
+                                title={`Reordered ${pipeline.jitterBuffer.reordered}, late ${pipeline.jitterBuffer.late}, duplicates ${pipeline.jitterBuffer.duplicates}`}
+                            >
+                                {pipeline.jitterBufferDepth} pkts
+                            </span>
+                        </div>
+                        <div className="status-item">
+                            <span>Lost:</span>
+                            <span className="status-value">{pipeline.jitterBuffer.lost} pkts</span>
+                        </div>
+                    </>
+                )}
+                {pipeline?.state === 'degraded' && (
+                    <div className="status-item">
+                        <span className="status-value" style={{ color: 'var(--accent-warning)' }}>
+                            Virtual camera degraded ({pipeline.connection})
+                        </span>
+                    </div>
+                )}
             </div>
 
             <div className="status-group">
@@ -0,0 +1,40 @@
+// Types for the phone camera controls (src-tauri/src/control.rs)
+
+export type Facing = 'user' | 'environment';
+export type FocusMode = 'continuous' | 'single-shot' | 'manual';
+
+/** A numeric capability as the phone's browser reports it */
+export interface Range {
+    min: number;
+    max: number;
+    step?: number | null;
+}
+
+/** What the phone's current camera can do; null where it can't be controlled */
+export interface CameraCapabilities {
+    zoom: Range | null;
+    torch: boolean;
+    focusModes: FocusMode[];
+    exposureCompensation: Range | null;
+    width: Range | null;
+    height: Range | null;
+    frameRate: Range | null;
+}
+
+export interface CameraSettings {
+    facing: Facing | null;
+    zoom: number | null;
+    torch: boolean | null;
+    focusMode: FocusMode | null;
+    exposureCompensation: number | null;
+    width: number | null;
+    height: number | null;
+    frameRate: number | null;
+}
+
+/** Returned by get_camera_state and every camera command */
+export interface CameraState {
+    label: string | null;
+    capabilities: CameraCapabilities;
+    settings: CameraSettings;
+}
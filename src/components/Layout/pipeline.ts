@@ -0,0 +1,62 @@
+// Types and event names for the backend pipeline (src-tauri/src/pipeline.rs)
+
+export type PipelineState =
+    | 'idle'
+    | 'waitingForPhone'
+    | 'negotiating'
+    | 'streaming'
+    | 'degraded'
+    | 'stopped';
+
+export type PhoneLink = 'absent' | 'negotiating' | 'connected' | 'interrupted';
+
+/** Returned by start_virtual_cam / stop_virtual_cam */
+export interface Transition {
+    from: PipelineState;
+    to: PipelineState;
+}
+
+/** Payload of `pipeline-state`, also returned by get_virtual_cam_status */
+export interface PipelineStatus {
+    state: PipelineState;
+    cameraActive: boolean;
+    error: string | null;
+}
+
+/** Jitter buffer counters for the current video track */
+export interface JitterStats {
+    reordered: number;
+    late: number;
+    duplicates: number;
+    lost: number;
+}
+
+/** Payload of `pipeline-stats`, emitted every second */
+export interface PipelineStats {
+    state: PipelineState;
+    connection: PhoneLink;
+    fpsIn: number;
+    fpsOut: number;
+    bitrateKbps: number;
+    width: number;
+    height: number;
+    codec: string | null;
+    keyframeIntervalMs: number | null;
+    droppedFrames: number;
+    jitterBufferDepth: number;
+    jitterBuffer: JitterStats;
+    latencyMs: number | null;
+}
+
+/** What the stream watchdog found wrong with the phone's stream */
+export type StreamProblem = 'stalled' | 'frozen';
+
+/** Payload of `stream-alert` */
+export interface StreamAlert {
+    /** null once frames move again */
+    problem: StreamProblem | null;
+}
+
+export const STATE_EVENT = 'pipeline-state';
+export const STATS_EVENT = 'pipeline-stats';
+export const ALERT_EVENT = 'stream-alert';
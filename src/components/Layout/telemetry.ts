@@ -0,0 +1,31 @@
+// Types and event names for phone telemetry (src-tauri/src/telemetry.rs)
+
+import type { CameraState } from './camera';
+
+/** CPU pressure from the Compute Pressure API, the closest a browser gets to the phone's heat */
+export type ThermalState = 'nominal' | 'fair' | 'serious' | 'critical';
+
+export interface Battery {
+    /** 0 to 1 */
+    level: number;
+    charging: boolean;
+}
+
+/** Payload of `device-telemetry`, also returned by get_device_telemetry; null where the phone's browser can't tell */
+export interface DeviceTelemetry {
+    streaming: boolean;
+    battery: Battery | null;
+    thermal: ThermalState | null;
+    camera: CameraState | null;
+    captureFps: number | null;
+    /** Time since the phone sent it */
+    ageMs: number;
+}
+
+/** Payload of `low-battery` */
+export interface LowBattery {
+    level: number;
+}
+
+export const TELEMETRY_EVENT = 'device-telemetry';
+export const LOW_BATTERY_EVENT = 'low-battery';
@@ -13,6 +13,62 @@ const SIGNALING_SERVER =
 
 type Status = 'idle' | 'ready' | 'connecting' | 'streaming' | 'error';
 type FacingMode = 'environment' | 'user';
+type FocusMode = 'continuous' | 'single-shot' | 'manual';
+
+/** Requests from the desktop (src-tauri/src/control.rs) */
+type ControlRequest =
+    | { type: 'getCameraState' }
+    | { type: 'switchCamera'; facing: FacingMode | null }
+    | { type: 'setTorch'; on: boolean }
+    | { type: 'setZoom'; zoom: number }
+    | { type: 'setFocusMode'; mode: FocusMode }
+    | { type: 'setExposureCompensation'; value: number }
+    | { type: 'setResolution'; width: number; height: number }
+    | { type: 'setFramerate'; fps: number }
+    | { type: 'setQuality'; maxBitrateKbps: number };
+
+interface Range { min: number; max: number; step?: number }
+
+/** Answer to camera requests (`CameraState` in control.rs) */
+interface CameraState {
+    label: string | null;
+    capabilities: {
+        zoom: Range | null;
+        torch: boolean;
+        focusModes: FocusMode[];
+        exposureCompensation: Range | null;
+        width: Range | null;
+        height: Range | null;
+        frameRate: Range | null;
+    };
+    settings: {
+        facing: FacingMode | null;
+        zoom: number | null;
+        torch: boolean | null;
+        focusMode: FocusMode | null;
+        exposureCompensation: number | null;
+        width: number | null;
+        height: number | null;
+        frameRate: number | null;
+    };
+}
+
+/** Sent unprompted every few seconds (`TelemetryReport` in src-tauri/src/telemetry.rs) */
+interface TelemetryReport {
+    streaming: boolean;
+    battery: { level: number; charging: boolean } | null;
+    thermal: 'nominal' | 'fair' | 'serious' | 'critical' | null;
+    camera: CameraState | null;
+    captureFps: number | null;
+}
+
+/** A control message, on the `control` data channel or in a signaling `control` message */
+type ControlFrame =
+    | { kind: 'request'; id: number; request: ControlRequest }
+    | { kind: 'response'; id: number; result?: unknown; error?: string }
+    | { kind: 'event'; event: { type: 'telemetry' } & TelemetryReport };
+
+const TELEMETRY_INTERVAL_MS = 5000;
 
 function formatDuration(seconds: number): string {
     const h = Math.floor(seconds / 3600);
@@ -22,6 +78,49 @@ function formatDuration(seconds: number): string {
     return `${String(m).padStart(2, '0')}:${String(s).padStart(2, '0')}`;
 }
 
+const FOCUS_MODES: FocusMode[] = ['continuous', 'single-shot', 'manual'];
+
+/** The camera's capability ranges and current settings, as far as the browser reports them. */
+function describeCamera(track: MediaStreamTrack): CameraState {
+    const caps = (track.getCapabilities?.() ?? {}) as any;
+    const set = (track.getSettings?.() ?? {}) as any;
+    const range = (r: any): Range | null =>
+        typeof r?.min === 'number' && typeof r?.max === 'number'
+            ? { min: r.min, max: r.max, ...(typeof r.step === 'number' ? { step: r.step } : {}) }
+            : null;
+    const num = (v: unknown) => (typeof v === 'number' ? v : null);
+    return {
+        label: track.label || null,
+        capabilities: {
+            zoom: range(caps.zoom),
+            torch: !!caps.torch,
+            focusModes: FOCUS_MODES.filter(m => caps.focusMode?.includes(m)),
+            exposureCompensation: range(caps.exposureCompensation),
+            width: range(caps.width),
+            height: range(caps.height),
+            frameRate: range(caps.frameRate),
+        },
+        settings: {
+            facing: set.facingMode === 'user' || set.facingMode === 'environment' ? set.facingMode : null,
+            zoom: num(set.zoom),
+            torch: typeof set.torch === 'boolean' ? set.torch : null,
+            focusMode: FOCUS_MODES.includes(set.focusMode) ? set.focusMode : null,
+            exposureCompensation: num(set.exposureCompensation),
+            width: num(set.width),
+            height: num(set.height),
+            frameRate: num(set.frameRate),
+        },
+    };
+}
+
+/** Reject a value the camera can't take, with a message the desktop can show. */
+function checkRange(name: string, value: number, range: Range | null) {
+    if (!range) throw new Error(`${name} isn't supported by this camera`);
+    if (value < range.min || value > range.max) {
+        throw new Error(`${name} must be between ${range.min} and ${range.max}`);
+    }
+}
+
 /** Pick the best MIME type that MediaRecorder supports on this device. */
 function getSupportedMimeType(): string {
     const candidates = [
@@ -50,6 +149,14 @@ export default function PhoneClient() {
     const timeoutRef     = useRef<number | null>(null);
     const durationRef    = useRef<number | null>(null);
     const helloIntervalRef = useRef<number | null>(null);
+    const relayServersRef  = useRef<RTCIceServer[]>([]);              // desktop TURN relay
+    const codecPrefsRef    = useRef<string[]>([]);                    // desktop codec policy order
+    const connectVcamRef   = useRef<() => Promise<void>>(async () => {}); // fresh vcam connection
+    const controlRef       = useRef<(r: ControlRequest) => Promise<unknown>>(async () => null); // desktop requests
+    const controlChannelRef = useRef<RTCDataChannel | null>(null);           // open control channel
+    const batteryRef       = useRef<{ level: number; charging: boolean } | null>(null); // BatteryManager
+    const thermalRef       = useRef<TelemetryReport['thermal']>(null);        // CPU pressure
+    const framesRef        = useRef<number | null>(null);                     // frames since the last report
 
     const [status, setStatus]             = useState<Status>('idle');
     const [errorMsg, setErrorMsg]         = useState('');
@@ -58,6 +165,8 @@ export default function PhoneClient() {
     const [torchSupported, setTorchSupported] = useState(false);
     const [duration, setDuration]         = useState(0);
     const [wsReady, setWsReady]           = useState(false);
+    const statusRef = useRef(status);
+    statusRef.current = status;
 
     // ── Camera initialisation ──────────────────────────────────────────────
     const startCamera = useCallback(async (facing: FacingMode = 'environment') => {
@@ -122,8 +231,7 @@ export default function PhoneClient() {
     }, [torchOn]);
 
     // ── Camera flip ───────────────────────────────────────────────────────
-    const flipCamera = useCallback(async () => {
-        const newFacing: FacingMode = facingMode === 'environment' ? 'user' : 'environment';
+    const switchCamera = useCallback(async (newFacing: FacingMode) => {
         setFacingMode(newFacing);
         await startCamera(newFacing);
         if (streamRef.current) {
@@ -133,7 +241,94 @@ export default function PhoneClient() {
                 if (sender) sender.replaceTrack(newTrack).catch(console.error);
             }
         }
-    }, [facingMode, startCamera]);
+    }, [startCamera]);
+
+    const flipCamera = useCallback(
+        () => switchCamera(facingMode === 'environment' ? 'user' : 'environment'),
+        [facingMode, switchCamera]
+    );
+
+    // ── Desktop control ───────────────────────────────────────────────────
+    // Requests arrive on the vcam connection's data channel, or over the
+    // WebSocket while that isn't open; answers go back the same way
+    // Camera requests answer with the camera's state afterwards
+    const handleControl = async (request: ControlRequest): Promise<unknown> => {
+        if (request.type === 'switchCamera') {
+            const facing = request.facing ?? (facingMode === 'environment' ? 'user' : 'environment');
+            if (facing !== facingMode) await switchCamera(facing);
+            const track = streamRef.current?.getVideoTracks()[0];
+            if (!track) throw new Error('No camera');
+            return describeCamera(track);
+        }
+        if (request.type === 'setQuality') {
+            const sender = pcRef.current?.getSenders().find(s => s.track?.kind === 'video');
+            if (!sender) throw new Error('Not streaming');
+            const params = sender.getParameters();
+            if (!params.encodings?.length) params.encodings = [{}];
+            if (request.maxBitrateKbps > 0) {
+                params.encodings[0].maxBitrate = request.maxBitrateKbps * 1000;
+            } else {
+                delete params.encodings[0].maxBitrate;
+            }
+            await sender.setParameters(params);
+            return null;
+        }
+
+        const track = streamRef.current?.getVideoTracks()[0];
+        if (!track) throw new Error('No camera');
+        const { capabilities } = describeCamera(track);
+        const apply = (constraint: Record<string, unknown>) =>
+            track.applyConstraints({ advanced: [constraint] } as MediaTrackConstraints);
+        switch (request.type) {
+            case 'getCameraState':
+                break;
+            case 'setTorch':
+                if (!capabilities.torch) throw new Error('This camera has no torch');
+                await apply({ torch: request.on });
+                setTorchOn(request.on);
+                break;
+            case 'setZoom':
+                checkRange('Zoom', request.zoom, capabilities.zoom);
+                await apply({ zoom: request.zoom });
+                break;
+            case 'setFocusMode':
+                if (!capabilities.focusModes.includes(request.mode)) {
+                    throw new Error(`This camera doesn't support ${request.mode} focus`);
+                }
+                await apply({ focusMode: request.mode });
+                break;
+            case 'setExposureCompensation':
+                checkRange('Exposure compensation', request.value, capabilities.exposureCompensation);
+                await apply({ exposureCompensation: request.value });
+                break;
+            // Size and frame rate are targets: the camera settles on the closest it has
+            case 'setResolution':
+                await track.applyConstraints({
+                    ...track.getConstraints(),
+                    width: { ideal: request.width },
+                    height: { ideal: request.height },
+                });
+                break;
+            case 'setFramerate':
+                if (capabilities.frameRate) checkRange('Frame rate', request.fps, capabilities.frameRate);
+                await track.applyConstraints({ ...track.getConstraints(), frameRate: { ideal: request.fps } });
+                break;
+            default:
+                throw new Error(`Unknown request ${(request as { type: string }).type}`);
+        }
+        return describeCamera(track);
+    };
+    controlRef.current = handleControl;
+
+    const answerControl = async (frame: ControlFrame, reply: (response: ControlFrame) => void) => {
+        if (frame?.kind !== 'request') return;
+        try {
+            const result = await controlRef.current(frame.request);
+            reply({ kind: 'response', id: frame.id, ...(result == null ? {} : { result }) });
+        } catch (e) {
+            reply({ kind: 'response', id: frame.id, error: e instanceof Error ? e.message : String(e) });
+        }
+    };
 
     // ── Signaling ─────────────────────────────────────────────────────────
     const connectSignaling = useCallback(() => {
@@ -179,15 +374,30 @@ export default function PhoneClient() {
             let msg: any;
             try { msg = JSON.parse(event.data); } catch { return; }
 
-            // vcam answer from Rust WebRTC client
-            if (msg.type === 'answer' && !msg.for) {
+            // Embedded TURN relay on the desktop (per-session credentials)
+            if (msg.type === 'ice-servers') {
+                relayServersRef.current = Array.isArray(msg.iceServers) ? msg.iceServers : [];
+
+            // Codecs the desktop accepts, most preferred first
+            } else if (msg.type === 'codec-preferences') {
+                codecPrefsRef.current = Array.isArray(msg.codecs) ? msg.codecs : [];
+
+            // vcam answer from Rust WebRTC client, or its own offer to renegotiate.
+            // We're the polite side of perfect negotiation: when both sides offer
+            // at once, setRemoteDescription rolls ours back and we answer theirs
+            } else if ((msg.type === 'answer' || msg.type === 'offer') && !msg.for) {
+                const pc = pcRef.current;
                 try {
-                    answeredRef.current = true;
-                    if (timeoutRef.current) { clearTimeout(timeoutRef.current); timeoutRef.current = null; }
-                    if (pcRef.current) {
-                        await pcRef.current.setRemoteDescription(
-                            new RTCSessionDescription({ type: 'answer', sdp: msg.sdp })
-                        );
+                    if (msg.type === 'answer') {
+                        answeredRef.current = true;
+                        if (timeoutRef.current) { clearTimeout(timeoutRef.current); timeoutRef.current = null; }
+                    }
+                    if (pc) {
+                        await pc.setRemoteDescription({ type: msg.type, sdp: msg.sdp });
+                        if (msg.type === 'offer') {
+                            await pc.setLocalDescription();
+                            wsRef.current?.send(JSON.stringify({ type: 'answer', sdp: pc.localDescription?.sdp }));
+                        }
                     }
                     // vcam connected — preview relay is already running
                 } catch (e) {
@@ -195,6 +405,25 @@ export default function PhoneClient() {
                     console.warn('[vcam] setRemoteDescription error (non-fatal):', e);
                 }
 
+            // Desktop lost connectivity: restart ICE on the same connection
+            // (renegotiates through onnegotiationneeded)
+            } else if (msg.type === 'ice-restart-request') {
+                pcRef.current?.restartIce();
+
+            // ICE restart didn't bring it back: start over with a new connection
+            } else if (msg.type === 'renegotiate-request') {
+                if (pcRef.current) {
+                    try { await connectVcamRef.current(); } catch (e) {
+                        console.warn('[vcam] Renegotiation failed:', e);
+                    }
+                }
+
+            // Desktop control request, while the data channel is down
+            } else if (msg.type === 'control') {
+                answerControl(msg.frame, response =>
+                    wsRef.current?.send(JSON.stringify({ type: 'control', frame: response }))
+                );
+
             // vcam ICE candidates from Rust
             } else if (msg.type === 'ice-candidate') {
                 if (pcRef.current) {
@@ -210,6 +439,67 @@ export default function PhoneClient() {
         };
     }, []); // eslint-disable-line react-hooks/exhaustive-deps
 
+    // ── Telemetry ─────────────────────────────────────────────────────────
+    // Battery, CPU pressure (a stand-in for heat, which browsers don't expose),
+    // the camera and its real frame rate, for the desktop to show and warn about
+    useEffect(() => {
+        (navigator as any).getBattery?.()
+            .then((battery: { level: number; charging: boolean }) => { batteryRef.current = battery; })
+            .catch(() => {});
+
+        let pressure: any = null;
+        const PressureObserver = (window as any).PressureObserver;
+        if (PressureObserver) {
+            pressure = new PressureObserver((records: { state: TelemetryReport['thermal'] }[]) => {
+                thermalRef.current = records[records.length - 1]?.state ?? null;
+            });
+            pressure.observe('cpu').catch(() => { pressure = null; });
+        }
+
+        // Count frames the camera delivers to the preview
+        const video = videoRef.current as any;
+        let frameCallback: number | null = null;
+        if (video?.requestVideoFrameCallback) {
+            framesRef.current = 0;
+            const onFrame = () => {
+                framesRef.current = (framesRef.current ?? 0) + 1;
+                frameCallback = video.requestVideoFrameCallback(onFrame);
+            };
+            frameCallback = video.requestVideoFrameCallback(onFrame);
+        }
+
+        let lastReport = performance.now();
+        const report = window.setInterval(() => {
+            const now = performance.now();
+            const frames = framesRef.current;
+            if (frames != null) framesRef.current = 0;
+            const track = streamRef.current?.getVideoTracks()[0];
+            const battery = batteryRef.current;
+            const telemetry: TelemetryReport = {
+                streaming: statusRef.current === 'streaming',
+                battery: battery ? { level: battery.level, charging: battery.charging } : null,
+                thermal: thermalRef.current,
+                camera: track ? describeCamera(track) : null,
+                captureFps: frames != null ? Math.round((frames * 10000) / (now - lastReport)) / 10 : null,
+            };
+            lastReport = now;
+
+            const frame: ControlFrame = { kind: 'event', event: { type: 'telemetry', ...telemetry } };
+            const channel = controlChannelRef.current;
+            if (channel?.readyState === 'open') {
+                channel.send(JSON.stringify(frame));
+            } else if (wsRef.current?.readyState === WebSocket.OPEN) {
+                wsRef.current.send(JSON.stringify({ type: 'control', frame }));
+            }
+        }, TELEMETRY_INTERVAL_MS);
+
+        return () => {
+            clearInterval(report);
+            if (frameCallback != null) video.cancelVideoFrameCallback(frameCallback);
+            pressure?.disconnect();
+        };
+    }, []);
+
     const startDurationTimer = () => {
         setDuration(0);
         if (durationRef.current) clearInterval(durationRef.current);
@@ -236,6 +526,97 @@ export default function PhoneClient() {
         };
     }, []); // eslint-disable-line react-hooks/exhaustive-deps
 
+    // ── vcam peer connection ──────────────────────────────────────────────
+    // Offers whenever the connection needs (re)negotiating: first connect, ICE
+    // restarts, tracks added or removed. The desktop answers on the same
+    // connection, so the video keeps going
+    const offerVcam = async (pc: RTCPeerConnection) => {
+        try {
+            await pc.setLocalDescription();
+            wsRef.current?.send(JSON.stringify({ type: 'offer', sdp: pc.localDescription?.sdp }));
+        } catch (e) {
+            console.warn('[vcam] Offer failed:', e);
+        }
+    };
+
+    // Builds a fresh connection and offers it; also used when the desktop asks
+    // to renegotiate after ICE restarts failed
+    const connectVcam = async () => {
+        pcRef.current?.close();
+        const vcamPc = new RTCPeerConnection({
+            iceServers: [...ICE_SERVERS, ...relayServersRef.current],
+        });
+        pcRef.current = vcamPc;
+
+        streamRef.current!.getTracks().forEach(track =>
+            vcamPc.addTrack(track, streamRef.current!)
+        );
+
+        // Offer codecs in the desktop's preferred order (H264 first by default)
+        try {
+            const transceivers = vcamPc.getTransceivers().filter(t => t.sender.track?.kind === 'video');
+            const caps = RTCRtpSender.getCapabilities?.('video');
+            if (transceivers.length > 0 && caps?.codecs?.length) {
+                const order = codecPrefsRef.current.length
+                    ? codecPrefsRef.current
+                    : ['video/H264', 'video/VP8', 'video/VP9', 'video/AV1'];
+                const sorted = [...caps.codecs].sort((a, b) => {
+                    const ia = order.indexOf(a.mimeType);
+                    const ib = order.indexOf(b.mimeType);
+                    return (ia < 0 ? 99 : ia) - (ib < 0 ? 99 : ib);
+                });
+                transceivers[0].setCodecPreferences?.(sorted);
+            }
+        } catch {}
+
+        vcamPc.onicecandidate = (event) => {
+            if (event.candidate) {
+                wsRef.current?.send(JSON.stringify({
+                    type: 'ice-candidate',
+                    candidate: event.candidate.candidate,
+                    sdp_mid: event.candidate.sdpMid,
+                    sdp_m_line_index: event.candidate.sdpMLineIndex,
+                    from: 'vcam',
+                }));
+            }
+        };
+
+        vcamPc.onconnectionstatechange = () => {
+            if (vcamPc.connectionState === 'connected') {
+                answeredRef.current = true;
+                if (timeoutRef.current) { clearTimeout(timeoutRef.current); timeoutRef.current = null; }
+            }
+            if (
+                (vcamPc.connectionState === 'failed' || vcamPc.connectionState === 'disconnected') &&
+                pcRef.current === vcamPc
+            ) {
+                stopDurationTimer();
+                // Don't show error — preview still works via WebSocket relay
+            }
+        };
+
+        // Opened by the desktop once the connection is up
+        vcamPc.ondatachannel = ({ channel }) => {
+            if (channel.label !== 'control') return;
+            controlChannelRef.current = channel;
+            channel.onclose = () => {
+                if (controlChannelRef.current === channel) controlChannelRef.current = null;
+            };
+            channel.onmessage = (e) => {
+                let frame: ControlFrame;
+                try { frame = JSON.parse(e.data); } catch { return; }
+                answerControl(frame, response => {
+                    if (channel.readyState === 'open') channel.send(JSON.stringify(response));
+                });
+            };
+        };
+
+        vcamPc.onnegotiationneeded = () => {
+            if (pcRef.current === vcamPc) offerVcam(vcamPc);
+        };
+    };
+    connectVcamRef.current = connectVcam;
+
     // ── Start streaming ───────────────────────────────────────────────────
     const startStream = async () => {
         if (!streamRef.current?.getVideoTracks().length) {
@@ -255,57 +636,8 @@ export default function PhoneClient() {
 
         try {
             // ── 1. vcam WebRTC peer connection (for Rust virtual camera) ───
-            const vcamPc = new RTCPeerConnection({ iceServers: ICE_SERVERS });
-            pcRef.current = vcamPc;
-
-            streamRef.current!.getTracks().forEach(track =>
-                vcamPc.addTrack(track, streamRef.current!)
-            );
-
-            // Prefer H264 for the virtual camera pipeline (Rust assembler expects it)
-            try {
-                const transceivers = vcamPc.getTransceivers().filter(t => t.sender.track?.kind === 'video');
-                const caps = RTCRtpSender.getCapabilities?.('video');
-                if (transceivers.length > 0 && caps?.codecs?.length) {
-                    const order = ['video/H264', 'video/VP8', 'video/VP9', 'video/AV1'];
-                    const sorted = [...caps.codecs].sort((a, b) => {
-                        const ia = order.indexOf(a.mimeType);
-                        const ib = order.indexOf(b.mimeType);
-                        return (ia < 0 ? 99 : ia) - (ib < 0 ? 99 : ib);
-                    });
-                    transceivers[0].setCodecPreferences?.(sorted);
-                }
-            } catch {}
-
-            vcamPc.onicecandidate = (event) => {
-                if (event.candidate) {
-                    wsRef.current?.send(JSON.stringify({
-                        type: 'ice-candidate',
-                        candidate: event.candidate.candidate,
-                        sdp_mid: event.candidate.sdpMid,
-                        sdp_m_line_index: event.candidate.sdpMLineIndex,
-                        from: 'vcam',
-                    }));
-                }
-            };
-
-            vcamPc.onconnectionstatechange = () => {
-                if (vcamPc.connectionState === 'connected') {
-                    answeredRef.current = true;
-                    if (timeoutRef.current) { clearTimeout(timeoutRef.current); timeoutRef.current = null; }
-                }
-                if (
-                    (vcamPc.connectionState === 'failed' || vcamPc.connectionState === 'disconnected') &&
-                    pcRef.current === vcamPc
-                ) {
-                    stopDurationTimer();
-                    // Don't show error — preview still works via WebSocket relay
-                }
-            };
-
-            const vcamOffer = await vcamPc.createOffer();
-            await vcamPc.setLocalDescription(vcamOffer);
-            wsRef.current?.send(JSON.stringify({ type: 'offer', sdp: vcamOffer.sdp }));
+            await connectVcam();
+            const vcamPc = pcRef.current;
 
             // ── 2. Preview relay via MediaRecorder → WebSocket ────────────
             const mimeType = getSupportedMimeType();
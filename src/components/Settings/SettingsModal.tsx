@@ -1,4 +1,5 @@
 import { useState, useEffect } from 'react';
+import { invoke } from '@tauri-apps/api/core';
 import { IconClose } from '../Icons';
 import './Settings.css';
 
@@ -26,6 +27,56 @@ interface AppSettings {
     };
 }
 
+/** Backend-owned network options (persisted by the Rust side, not localStorage). */
+interface NetworkSettings {
+    turnEnabled: boolean;
+    turnPort: number;
+    udpMuxEnabled: boolean;
+    udpMuxPort: number;
+    iceTcpEnabled: boolean;
+    iceTcpPort: number;
+    jitterBufferMs: number;
+    reconnectInitialMs: number;
+    reconnectMaxMs: number;
+}
+
+/** Backend-owned stream health and alert options; they apply right away. */
+interface StreamSettings {
+    stallTimeoutMs: number;
+    lowBatteryPercent: number;
+}
+
+type OutputCodec = 'h264' | 'h265';
+/** Codecs the desktop can receive; only the output codecs reach the virtual camera. */
+type PhoneCodec = OutputCodec | 'vp8' | 'vp9' | 'av1';
+
+/** What the desktop negotiates with the phone. */
+interface CodecPolicy {
+    codecs: PhoneCodec[];
+    h264ProfileLevelIds: string[];
+    h264PacketizationModes: number[];
+    maxWidth: number;
+    maxHeight: number;
+    maxFramerate: number;
+    maxBitrateKbps: number;
+}
+
+/** Backend-owned codec options. */
+interface VideoCodecSettings {
+    hevcEnabled: boolean;
+    codecPolicy: CodecPolicy;
+    outputCodec: OutputCodec;
+}
+
+const CODEC_ORDERS: { label: string; codecs: PhoneCodec[] }[] = [
+    { label: 'H.264 only', codecs: ['h264'] },
+    { label: 'H.264, then H.265', codecs: ['h264', 'h265'] },
+    { label: 'H.265, then H.264', codecs: ['h265', 'h264'] },
+    { label: 'H.265 only', codecs: ['h265'] },
+    { label: 'H.264, then VP8 (no camera output)', codecs: ['h264', 'vp8'] },
+    { label: 'H.264, then VP9 and AV1 (no camera output)', codecs: ['h264', 'vp9', 'av1'] },
+];
+
 const STORAGE_KEY = 'opticlink-settings';
 
 function loadSettings(): AppSettings {
@@ -48,8 +99,37 @@ function defaultSettings(): AppSettings {
 export default function SettingsModal({ onClose, onMirrorChange }: SettingsModalProps) {
     const [activeTab, setActiveTab] = useState<SettingsTab>('video');
     const [settings, setSettings] = useState<AppSettings>(loadSettings);
+    const [network, setNetwork] = useState<NetworkSettings | null>(null);
+    const [codecs, setCodecs] = useState<VideoCodecSettings | null>(null);
+    const [stream, setStream] = useState<StreamSettings | null>(null);
     const [saved, setSaved] = useState(false);
 
+    useEffect(() => {
+        invoke<NetworkSettings>('get_network_settings').then(setNetwork).catch(() => {});
+        invoke<VideoCodecSettings>('get_video_settings').then(setCodecs).catch(() => {});
+        invoke<StreamSettings>('get_stream_settings').then(setStream).catch(() => {});
+    }, []);
+
+    const updateNetwork = <K extends keyof NetworkSettings>(key: K, value: NetworkSettings[K]) => {
+        setNetwork(prev => (prev ? { ...prev, [key]: value } : prev));
+        setSaved(false);
+    };
+
+    const updateCodecs = <K extends keyof VideoCodecSettings>(key: K, value: VideoCodecSettings[K]) => {
+        setCodecs(prev => (prev ? { ...prev, [key]: value } : prev));
+        setSaved(false);
+    };
+
+    const updateStream = <K extends keyof StreamSettings>(key: K, value: StreamSettings[K]) => {
+        setStream(prev => (prev ? { ...prev, [key]: value } : prev));
+        setSaved(false);
+    };
+
+    const updatePolicy = <K extends keyof CodecPolicy>(key: K, value: CodecPolicy[K]) => {
+        setCodecs(prev => (prev ? { ...prev, codecPolicy: { ...prev.codecPolicy, [key]: value } } : prev));
+        setSaved(false);
+    };
+
     const update = <T extends keyof AppSettings>(
         section: T,
         key: keyof AppSettings[T],
@@ -64,6 +144,9 @@ export default function SettingsModal({ onClose, onMirrorChange }: SettingsModal
 
     const save = () => {
         localStorage.setItem(STORAGE_KEY, JSON.stringify(settings));
+        if (network) invoke('set_network_settings', { network }).catch(console.error);
+        if (codecs) invoke('set_video_settings', { video: codecs }).catch(console.error);
+        if (stream) invoke('set_stream_settings', { stream }).catch(console.error);
         onMirrorChange?.(settings.video.mirror);
         setSaved(true);
         setTimeout(onClose, 600);
@@ -149,6 +232,128 @@ export default function SettingsModal({ onClose, onMirrorChange }: SettingsModal
                                     />
                                     <p className="form-hint">Display resolution, FPS, latency, and bitrate on the preview</p>
                                 </div>
+
+                                {codecs && (
+                                    <>
+                                        <div className="form-group">
+                                            <label className="form-label">Accept H.265 (HEVC)</label>
+                                            <div
+                                                className={`toggle ${codecs.hevcEnabled ? 'active' : ''}`}
+                                                onClick={() => updateCodecs('hevcEnabled', !codecs.hevcEnabled)}
+                                            />
+                                            <p className="form-hint">
+                                                Let iPhones running Safari send H.265 for similar quality at about
+                                                half the bandwidth. Applies on the next reconnect
+                                            </p>
+                                        </div>
+
+                                        <div className="form-group">
+                                            <label className="form-label">Codecs From Phone</label>
+                                            <select
+                                                className="select"
+                                                value={codecs.codecPolicy.codecs.join(',')}
+                                                onChange={e =>
+                                                    updatePolicy('codecs', e.target.value.split(',') as PhoneCodec[])
+                                                }
+                                            >
+                                                {CODEC_ORDERS.map(o => (
+                                                    <option key={o.label} value={o.codecs.join(',')}>{o.label}</option>
+                                                ))}
+                                            </select>
+                                            <p className="form-hint">
+                                                Preference order; H.265 is left out unless accepted above.
+                                                The virtual camera can't output VP8, VP9 or AV1 and reports
+                                                them as an error. Applies on the next reconnect
+                                            </p>
+                                        </div>
+
+                                        <div className="form-group">
+                                            <label className="form-label">H.264 Profiles (profile-level-id)</label>
+                                            <input
+                                                type="text"
+                                                className="input"
+                                                value={codecs.codecPolicy.h264ProfileLevelIds.join(', ')}
+                                                onChange={e =>
+                                                    updatePolicy(
+                                                        'h264ProfileLevelIds',
+                                                        e.target.value.split(',').map(v => v.trim()).filter(Boolean)
+                                                    )
+                                                }
+                                            />
+                                        </div>
+
+                                        <div className="form-group">
+                                            <label className="form-label">Allow H.264 Single-NAL Mode</label>
+                                            <div
+                                                className={`toggle ${codecs.codecPolicy.h264PacketizationModes.includes(0) ? 'active' : ''}`}
+                                                onClick={() =>
+                                                    updatePolicy(
+                                                        'h264PacketizationModes',
+                                                        codecs.codecPolicy.h264PacketizationModes.includes(0) ? [1] : [1, 0]
+                                                    )
+                                                }
+                                            />
+                                            <p className="form-hint">Accept packetization-mode 0 as well as 1</p>
+                                        </div>
+
+                                        <div className="form-group">
+                                            <label className="form-label">Max Resolution and Frame Rate</label>
+                                            <input
+                                                type="number"
+                                                className="input"
+                                                value={codecs.codecPolicy.maxWidth}
+                                                min={0}
+                                                onChange={e => updatePolicy('maxWidth', Math.max(0, parseInt(e.target.value) || 0))}
+                                            />
+                                            <input
+                                                type="number"
+                                                className="input"
+                                                value={codecs.codecPolicy.maxHeight}
+                                                min={0}
+                                                onChange={e => updatePolicy('maxHeight', Math.max(0, parseInt(e.target.value) || 0))}
+                                            />
+                                            <input
+                                                type="number"
+                                                className="input"
+                                                value={codecs.codecPolicy.maxFramerate}
+                                                min={0}
+                                                max={120}
+                                                onChange={e => updatePolicy('maxFramerate', Math.max(0, parseInt(e.target.value) || 0))}
+                                            />
+                                            <p className="form-hint">Width, height and FPS advertised to the phone (0 = no limit)</p>
+                                        </div>
+
+                                        <div className="form-group">
+                                            <label className="form-label">Max Bitrate (kbps)</label>
+                                            <input
+                                                type="number"
+                                                className="input"
+                                                value={codecs.codecPolicy.maxBitrateKbps}
+                                                min={0}
+                                                onChange={e => updatePolicy('maxBitrateKbps', Math.max(0, parseInt(e.target.value) || 0))}
+                                            />
+                                            <p className="form-hint">Upper limit on the phone's video bitrate; below it, the desktop adapts to the network (0 = no limit)</p>
+                                        </div>
+
+                                        <div className="form-group">
+                                            <label className="form-label">Virtual Camera Format</label>
+                                            <select
+                                                className="select"
+                                                value={codecs.outputCodec}
+                                                onChange={e =>
+                                                    updateCodecs('outputCodec', e.target.value as VideoCodecSettings['outputCodec'])
+                                                }
+                                            >
+                                                <option value="h264">H.264</option>
+                                                <option value="h265">H.265 (HEVC)</option>
+                                            </select>
+                                            <p className="form-hint">
+                                                Must match what the phone sends; H.265 also needs apps that can
+                                                decode it. Applies when the virtual camera next starts
+                                            </p>
+                                        </div>
+                                    </>
+                                )}
                             </div>
                         )}
 
@@ -179,6 +384,168 @@ export default function SettingsModal({ onClose, onMirrorChange }: SettingsModal
                                     />
                                 </div>
 
+                                {network && (
+                                    <>
+                                        <div className="form-group">
+                                            <label className="form-label">TURN Relay</label>
+                                            <div
+                                                className={`toggle ${network.turnEnabled ? 'active' : ''}`}
+                                                onClick={() => updateNetwork('turnEnabled', !network.turnEnabled)}
+                                            />
+                                            <p className="form-hint">
+                                                Relay video through this computer when the Wi-Fi blocks
+                                                direct phone-to-desktop traffic (client isolation)
+                                            </p>
+                                        </div>
+
+                                        <div className="form-group">
+                                            <label className="form-label">TURN Relay Port (UDP)</label>
+                                            <input
+                                                type="number"
+                                                className="input"
+                                                value={network.turnPort}
+                                                min={1024}
+                                                max={65535}
+                                                disabled={!network.turnEnabled}
+                                                onChange={e =>
+                                                    updateNetwork('turnPort', parseInt(e.target.value) || 3478)
+                                                }
+                                            />
+                                        </div>
+
+                                        <div className="form-group">
+                                            <label className="form-label">Jitter Buffer (ms)</label>
+                                            <input
+                                                type="number"
+                                                className="input"
+                                                value={network.jitterBufferMs}
+                                                min={0}
+                                                max={500}
+                                                onChange={e =>
+                                                    updateNetwork('jitterBufferMs', Math.max(0, parseInt(e.target.value) || 0))
+                                                }
+                                            />
+                                            <p className="form-hint">
+                                                How long to wait for late or reordered packets. Higher values
+                                                smooth out busy Wi-Fi at the cost of latency
+                                            </p>
+                                        </div>
+
+                                        <div className="form-group">
+                                            <label className="form-label">Reconnect Delay (ms)</label>
+                                            <input
+                                                type="number"
+                                                className="input"
+                                                value={network.reconnectInitialMs}
+                                                min={100}
+                                                onChange={e =>
+                                                    updateNetwork('reconnectInitialMs', Math.max(100, parseInt(e.target.value) || 100))
+                                                }
+                                            />
+                                            <input
+                                                type="number"
+                                                className="input"
+                                                value={network.reconnectMaxMs}
+                                                min={network.reconnectInitialMs}
+                                                onChange={e =>
+                                                    updateNetwork('reconnectMaxMs', Math.max(100, parseInt(e.target.value) || 100))
+                                                }
+                                            />
+                                            <p className="form-hint">
+                                                First and longest wait before reconnecting to the phone. The wait
+                                                doubles after each failed attempt
+                                            </p>
+                                        </div>
+
+                                        <div className="form-group">
+                                            <label className="form-label">Single-Port UDP</label>
+                                            <div
+                                                className={`toggle ${network.udpMuxEnabled ? 'active' : ''}`}
+                                                onClick={() => updateNetwork('udpMuxEnabled', !network.udpMuxEnabled)}
+                                            />
+                                            <p className="form-hint">
+                                                Send all WebRTC traffic through one fixed UDP port so a single
+                                                firewall rule is enough
+                                            </p>
+                                            <input
+                                                type="number"
+                                                className="input"
+                                                value={network.udpMuxPort}
+                                                min={1024}
+                                                max={65535}
+                                                disabled={!network.udpMuxEnabled && !network.iceTcpEnabled}
+                                                onChange={e =>
+                                                    updateNetwork('udpMuxPort', parseInt(e.target.value) || 3003)
+                                                }
+                                            />
+                                        </div>
+
+                                        <div className="form-group">
+                                            <label className="form-label">ICE-TCP Fallback</label>
+                                            <div
+                                                className={`toggle ${network.iceTcpEnabled ? 'active' : ''}`}
+                                                onClick={() => updateNetwork('iceTcpEnabled', !network.iceTcpEnabled)}
+                                            />
+                                            <p className="form-hint">
+                                                Accept the stream over TCP on a fixed port when UDP is blocked
+                                                (also enables single-port UDP)
+                                            </p>
+                                            <input
+                                                type="number"
+                                                className="input"
+                                                value={network.iceTcpPort}
+                                                min={1024}
+                                                max={65535}
+                                                disabled={!network.iceTcpEnabled}
+                                                onChange={e =>
+                                                    updateNetwork('iceTcpPort', parseInt(e.target.value) || 3003)
+                                                }
+                                            />
+                                        </div>
+                                    </>
+                                )}
+
+                                {stream && (
+                                    <>
+                                        <div className="form-group">
+                                            <label className="form-label">Stall Timeout (ms)</label>
+                                            <input
+                                                type="number"
+                                                className="input"
+                                                value={stream.stallTimeoutMs}
+                                                min={500}
+                                                onChange={e =>
+                                                    updateStream('stallTimeoutMs', Math.max(500, parseInt(e.target.value) || 500))
+                                                }
+                                            />
+                                            <p className="form-hint">
+                                                How long without video before the stream is treated as stalled and
+                                                recovery starts
+                                            </p>
+                                        </div>
+
+                                        <div className="form-group">
+                                            <label className="form-label">Low Battery Warning (%)</label>
+                                            <input
+                                                type="number"
+                                                className="input"
+                                                value={stream.lowBatteryPercent}
+                                                min={0}
+                                                max={100}
+                                                onChange={e =>
+                                                    updateStream(
+                                                        'lowBatteryPercent',
+                                                        Math.min(100, Math.max(0, parseInt(e.target.value) || 0))
+                                                    )
+                                                }
+                                            />
+                                            <p className="form-hint">
+                                                Warn when the phone's battery drops below this while streaming (0 = never)
+                                            </p>
+                                        </div>
+                                    </>
+                                )}
+
                                 <div className="form-group settings-info-box">
                                     <p><strong>Phone server</strong> runs on HTTPS port 3002.</p>
                                     <p><strong>Desktop signaling</strong> runs on HTTP port 3001 (loopback only).</p>